@@ -0,0 +1,613 @@
+//! The on-disk layout of VHS (ACMI tape) files, shared by everything that
+//! reads or writes them.
+//!
+//! VHS files have a few sections:
+//!
+//! 1. A header with some magic bytes, offsets into other sections of the file,
+//!    flight time of day, etc.
+//!
+//! 2. A list of entities - planes, etc. which move around the world
+//!
+//! 3. A list of "features" which get an initial position and then stay there.
+//!
+//! 4. A lits of position updates for entities and features (each feature has one).
+//!    Updates don't contain the UID of the entity or feature they apply to.
+//!    Instead, each entity & feature has a "head" offset that points to their
+//!    first update, and each update had a "previous" and "next" offset, forming
+//!    a doubly-linked list of updates for each entity & feature.
+//!
+//! 5. A lists of non-position "events" for entities (switch & DOF changes),
+//!    similarly chained in doubly-linked lists
+//!
+//! 6. A list of "general" events, split into two parts:
+//!    - Event "Headers" with most of the data (position, orientation, velocity,
+//!      scale, flags...)
+//!    - Event "trailers" sorted chronologically by timestamp with the index
+//!      of their corresponding header
+//!
+//! 7. Feature events containing a feature index, a timestamp, and a state change
+//!
+//! 8. A set of calligns and team colors.
+//!
+//! Each record type here can read itself from a reader and write itself to a writer,
+//! and its `SIZE` is exactly the number of bytes either takes.
+
+// generated by rust-bindgen 0.57.0, hacked down manually
+
+use std::io;
+use std::io::prelude::*;
+
+use anyhow::*;
+#[cfg(feature = "serde")]
+use serde_derive::*;
+
+pub mod primitives;
+
+use crate::primitives::*;
+
+/// Keeps track of how far we've read, so we can check it against
+/// the offsets in the header.
+pub struct CountedRead<R> {
+    inner: R,
+    posit: u32, // Welcome to 1998, where files are always < 4 GB.
+}
+
+impl<R: Read> CountedRead<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, posit: 0 }
+    }
+
+    pub fn get_posit(&self) -> u32 {
+        self.posit
+    }
+}
+
+impl<R: Read> Read for CountedRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = self.inner.read(buf);
+        if let Ok(count) = res {
+            self.posit += count as u32;
+        }
+        res
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct TapeHeader {
+    pub file_id: [u8; 4],
+    pub file_size: u32,
+    pub entity_count: i32,
+    pub feature_count: i32,
+    pub entity_offset: u32,
+    pub feature_offset: u32,
+    pub position_count: i32,
+    pub position_offset: u32,
+    pub entity_event_offset: u32,
+    pub general_event_offset: u32,
+    pub general_event_trailer_offset: u32,
+    pub text_event_offset: u32,
+    pub feature_event_offset: u32,
+    pub general_event_count: i32,
+    pub entity_event_count: i32,
+    pub text_event_count: i32,
+    pub feature_event_count: i32,
+    pub start_time: f32,
+    pub total_play_time: f32,
+    pub tod_offset: f32,
+}
+
+impl TapeHeader {
+    /// The header is 80 bytes long; entities start after.
+    pub const SIZE: u32 = 80;
+
+    /// The magic bytes: "TAPE", but little-endian.
+    pub const MAGIC: [u8; 4] = *b"EPAT";
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let mut file_id: [u8; 4] = [0; 4];
+        r.read_exact(&mut file_id)?;
+
+        let file_size = read_u32(r)?;
+        let entity_count = read_i32(r)?;
+        let feature_count = read_i32(r)?;
+        let entity_offset = read_u32(r)?;
+        let feature_offset = read_u32(r)?;
+        let position_count = read_i32(r)?;
+        let position_offset = read_u32(r)?;
+        let entity_event_offset = read_u32(r)?;
+        let general_event_offset = read_u32(r)?;
+        let general_event_trailer_offset = read_u32(r)?;
+        let text_event_offset = read_u32(r)?;
+        let feature_event_offset = read_u32(r)?;
+        let general_event_count = read_i32(r)?;
+        let entity_event_count = read_i32(r)?;
+        let text_event_count = read_i32(r)?;
+        let feature_event_count = read_i32(r)?;
+        let start_time = read_f32(r)?;
+        let total_play_time = read_f32(r)?;
+        let tod_offset = read_f32(r)?;
+
+        Ok(Self {
+            file_id,
+            file_size,
+            entity_count,
+            feature_count,
+            entity_offset,
+            feature_offset,
+            position_count,
+            position_offset,
+            entity_event_offset,
+            general_event_offset,
+            general_event_trailer_offset,
+            text_event_offset,
+            feature_event_offset,
+            general_event_count,
+            entity_event_count,
+            text_event_count,
+            feature_event_count,
+            start_time,
+            total_play_time,
+            tod_offset,
+        })
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&self.file_id)?;
+        write_u32(self.file_size, w)?;
+        write_i32(self.entity_count, w)?;
+        write_i32(self.feature_count, w)?;
+        write_u32(self.entity_offset, w)?;
+        write_u32(self.feature_offset, w)?;
+        write_i32(self.position_count, w)?;
+        write_u32(self.position_offset, w)?;
+        write_u32(self.entity_event_offset, w)?;
+        write_u32(self.general_event_offset, w)?;
+        write_u32(self.general_event_trailer_offset, w)?;
+        write_u32(self.text_event_offset, w)?;
+        write_u32(self.feature_event_offset, w)?;
+        write_i32(self.general_event_count, w)?;
+        write_i32(self.entity_event_count, w)?;
+        write_i32(self.text_event_count, w)?;
+        write_i32(self.feature_event_count, w)?;
+        write_f32(self.start_time, w)?;
+        write_f32(self.total_play_time, w)?;
+        write_f32(self.tod_offset, w)?;
+        Ok(())
+    }
+}
+
+/// An entity or feature - both share the same on-disk format.
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Entity {
+    pub uid: i32,
+    pub kind: i32,
+    pub count: i32,
+    pub flags: u32,
+    pub lead_index: i32,
+    pub slot: i32,
+    pub special_flags: u32,
+    pub first_position_offset: u32,
+    pub first_event_offset: u32,
+}
+
+impl Entity {
+    pub const SIZE: u32 = 36;
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let uid = read_i32(r)?;
+        let kind = read_i32(r)?;
+        let count = read_i32(r)?;
+        let flags = read_u32(r)?;
+        let lead_index = read_i32(r)?;
+        let slot = read_i32(r)?;
+        let special_flags = read_u32(r)?;
+        let first_position_offset = read_u32(r)?;
+        let first_event_offset = read_u32(r)?;
+
+        Ok(Self {
+            uid,
+            kind,
+            count,
+            flags,
+            lead_index,
+            slot,
+            special_flags,
+            first_position_offset,
+            first_event_offset,
+        })
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_i32(self.uid, w)?;
+        write_i32(self.kind, w)?;
+        write_i32(self.count, w)?;
+        write_u32(self.flags, w)?;
+        write_i32(self.lead_index, w)?;
+        write_i32(self.slot, w)?;
+        write_u32(self.special_flags, w)?;
+        write_u32(self.first_position_offset, w)?;
+        write_u32(self.first_event_offset, w)?;
+        Ok(())
+    }
+}
+
+/// A position update or an event for an entity or feature.
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct TimelineEntry {
+    pub time: f32,
+    pub payload: TimelineEntryPayload,
+    pub next_update_offset: u32,
+    pub previous_update_offset: u32,
+}
+
+impl TimelineEntry {
+    pub const SIZE: u32 = 41;
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let time = read_f32(r)?;
+        let payload = match read_u8(r)? {
+            0 => TimelineEntryPayload::Pos(Position::read(r)?),
+            1 => TimelineEntryPayload::Switch(Switch::read(r)?),
+            2 => TimelineEntryPayload::Dof(Dof::read(r)?),
+            wut => bail!("Invalid timeline entry type: {}", wut),
+        };
+        let next_update_offset = read_u32(r)?;
+        let previous_update_offset = read_u32(r)?;
+
+        Ok(Self {
+            time,
+            payload,
+            next_update_offset,
+            previous_update_offset,
+        })
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_f32(self.time, w)?;
+        // Updates are unions of position updates,
+        // switch updates, and DOF updates.
+        // The next byte is the union's tag/discriminant.
+        match &self.payload {
+            TimelineEntryPayload::Pos(pos) => {
+                write_u8(0, w)?;
+                pos.write(w)?;
+            }
+            TimelineEntryPayload::Switch(switch) => {
+                write_u8(1, w)?;
+                switch.write(w)?;
+            }
+            TimelineEntryPayload::Dof(dof) => {
+                write_u8(2, w)?;
+                dof.write(w)?;
+            }
+        };
+        write_u32(self.next_update_offset, w)?;
+        write_u32(self.previous_update_offset, w)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum TimelineEntryPayload {
+    Pos(Position),
+    Switch(Switch),
+    Dof(Dof),
+}
+
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub pitch: f32,
+    pub roll: f32,
+    pub yaw: f32,
+    pub radar_target: i32,
+}
+
+impl Position {
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let x = read_f32(r)?;
+        let y = read_f32(r)?;
+        let z = read_f32(r)?;
+        let pitch = read_f32(r)?;
+        let roll = read_f32(r)?;
+        let yaw = read_f32(r)?;
+        let radar_target = read_i32(r)?;
+
+        Ok(Self {
+            x,
+            y,
+            z,
+            pitch,
+            roll,
+            yaw,
+            radar_target,
+        })
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_f32(self.x, w)?;
+        write_f32(self.y, w)?;
+        write_f32(self.z, w)?;
+        write_f32(self.pitch, w)?;
+        write_f32(self.roll, w)?;
+        write_f32(self.yaw, w)?;
+        write_i32(self.radar_target, w)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Switch {
+    pub switch_index: i32,
+    pub switch_value: i32,
+    pub previous_switch_value: i32,
+}
+
+impl Switch {
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let switch_index = read_i32(r)?;
+        let switch_value = read_i32(r)?;
+        let previous_switch_value = read_i32(r)?;
+
+        // The other union values have another four 4-byte values
+        io::copy(&mut r.take(16), &mut io::sink())?;
+
+        Ok(Self {
+            switch_index,
+            switch_value,
+            previous_switch_value,
+        })
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_i32(self.switch_index, w)?;
+        write_i32(self.switch_value, w)?;
+        write_i32(self.previous_switch_value, w)?;
+
+        // Unused space, taken up by the position update in the union
+        w.write_all(&[0; 16])?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Dof {
+    pub dof_index: i32,
+    pub dof_value: f32,
+    pub previous_dof_value: f32,
+}
+
+impl Dof {
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let dof_index = read_i32(r)?;
+        let dof_value = read_f32(r)?;
+        let previous_dof_value = read_f32(r)?;
+
+        // The other union values have another four 4-byte values
+        io::copy(&mut r.take(16), &mut io::sink())?;
+
+        Ok(Self {
+            dof_index,
+            dof_value,
+            previous_dof_value,
+        })
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_i32(self.dof_index, w)?;
+        write_f32(self.dof_value, w)?;
+        write_f32(self.previous_dof_value, w)?;
+
+        // Unused space, taken up by the position update in the union
+        w.write_all(&[0; 16])?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct GeneralEventHeader {
+    pub event_type: u8,
+    pub index: i32,
+    pub time: f32,
+    pub time_end: f32,
+    pub kind: i32,
+    pub user: i32,
+    pub flags: i32,
+    pub scale: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub dx: f32,
+    pub dy: f32,
+    pub dz: f32,
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+impl GeneralEventHeader {
+    pub const SIZE: u32 = 65;
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let event_type = read_u8(r)?;
+        let index = read_i32(r)?;
+        let time = read_f32(r)?;
+        let time_end = read_f32(r)?;
+        let kind = read_i32(r)?;
+        let user = read_i32(r)?;
+        let flags = read_i32(r)?;
+        let scale = read_f32(r)?;
+        let x = read_f32(r)?;
+        let y = read_f32(r)?;
+        let z = read_f32(r)?;
+        let dx = read_f32(r)?;
+        let dy = read_f32(r)?;
+        let dz = read_f32(r)?;
+        let roll = read_f32(r)?;
+        let pitch = read_f32(r)?;
+        let yaw = read_f32(r)?;
+
+        Ok(Self {
+            event_type,
+            index,
+            time,
+            time_end,
+            kind,
+            user,
+            flags,
+            scale,
+            x,
+            y,
+            z,
+            dx,
+            dy,
+            dz,
+            roll,
+            pitch,
+            yaw,
+        })
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_u8(self.event_type, w)?;
+        write_i32(self.index, w)?;
+        write_f32(self.time, w)?;
+        write_f32(self.time_end, w)?;
+        write_i32(self.kind, w)?;
+        write_i32(self.user, w)?;
+        write_i32(self.flags, w)?;
+        write_f32(self.scale, w)?;
+        write_f32(self.x, w)?;
+        write_f32(self.y, w)?;
+        write_f32(self.z, w)?;
+        write_f32(self.dx, w)?;
+        write_f32(self.dy, w)?;
+        write_f32(self.dz, w)?;
+        write_f32(self.roll, w)?;
+        write_f32(self.pitch, w)?;
+        write_f32(self.yaw, w)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct GeneralEventTrailer {
+    pub time_end: f32,
+    pub index: i32,
+}
+
+impl GeneralEventTrailer {
+    pub const SIZE: u32 = 8;
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let time_end = read_f32(r)?;
+        let index = read_i32(r)?;
+
+        Ok(Self { time_end, index })
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_f32(self.time_end, w)?;
+        write_i32(self.index, w)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct FeatureEvent {
+    pub time: f32,
+    pub index: i32,
+    pub new_status: i32,
+    pub previous_status: i32,
+}
+
+impl FeatureEvent {
+    pub const SIZE: u32 = 16;
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let time = read_f32(r)?;
+        let index = read_i32(r)?;
+        let new_status = read_i32(r)?;
+        let previous_status = read_i32(r)?;
+
+        Ok(Self {
+            time,
+            index,
+            new_status,
+            previous_status,
+        })
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_f32(self.time, w)?;
+        write_i32(self.index, w)?;
+        write_i32(self.new_status, w)?;
+        write_i32(self.previous_status, w)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct CallsignRecord {
+    /// A NUL-padded name
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_label"))]
+    pub label: [u8; 16],
+    pub team_color: i32,
+}
+
+impl CallsignRecord {
+    pub const SIZE: u32 = 20;
+
+    /// A callsign with the given label, cut short if needed
+    /// to leave at least one NUL at the end.
+    pub fn new(label: &str, team_color: i32) -> Self {
+        let mut record = Self {
+            label: [0; 16],
+            team_color,
+        };
+        let len = std::cmp::min(label.len(), record.label.len() - 1);
+        record.label[..len].copy_from_slice(&label.as_bytes()[..len]);
+        record
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let mut label: [u8; 16] = [0; 16];
+        r.read_exact(&mut label)?;
+        let team_color = read_i32(r)?;
+        Ok(Self { label, team_color })
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&self.label)?;
+        write_i32(self.team_color, w)?;
+        Ok(())
+    }
+
+    /// The label up to its first NUL, with any invalid UTF-8 replaced.
+    pub fn label_lossy(&self) -> String {
+        lossy_label(&self.label)
+    }
+}
+
+fn lossy_label(label: &[u8; 16]) -> String {
+    let label_len = label.iter().position(|c| *c == 0).unwrap_or(16);
+    String::from_utf8_lossy(&label[0..label_len]).to_string()
+}
+
+#[cfg(feature = "serde")]
+fn serialize_label<S: serde::Serializer>(label: &[u8; 16], s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&lossy_label(label))
+}
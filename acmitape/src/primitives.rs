@@ -1,13 +1,21 @@
-///! Utility functions for reading and writing primitives
+//! Utility functions for reading and writing little-endian primitives
 use std::io::{Read, Result, Write};
 
+/// Reads a byte from the front of the provided reader
+#[inline]
+pub fn read_u8<R: Read>(r: &mut R) -> Result<u8> {
+    let mut byte: [u8; 1] = [0];
+    r.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
 /// Writes a byte to the provided writer
 #[inline(always)]
 pub fn write_u8<W: Write>(b: u8, w: &mut W) -> Result<()> {
     w.write_all(&[b])
 }
 
-/// Reads a little-endian u32 from the provided reader
+/// Reads a little-endian u32 from the front of the provided reader
 #[inline]
 pub fn read_u32<R: Read>(r: &mut R) -> Result<u32> {
     let mut bytes: [u8; 4] = [0; 4];
@@ -22,7 +30,7 @@ pub fn write_u32<W: Write>(i: u32, w: &mut W) -> Result<()> {
     w.write_all(&bytes)
 }
 
-/// Reads a little-endian i32 from the provided reader
+/// Reads a little-endian i32 from the front of the provided reader
 #[inline]
 pub fn read_i32<R: Read>(r: &mut R) -> Result<i32> {
     let mut bytes: [u8; 4] = [0; 4];
@@ -37,7 +45,7 @@ pub fn write_i32<W: Write>(i: i32, w: &mut W) -> Result<()> {
     w.write_all(&bytes)
 }
 
-/// Reads a little-endian f32 from the provided reader
+/// Reads a little-endian f32 from the front of the provided reader
 #[inline]
 pub fn read_f32<R: Read>(r: &mut R) -> Result<f32> {
     let mut bytes: [u8; 4] = [0; 4];
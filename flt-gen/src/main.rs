@@ -0,0 +1,609 @@
+use std::f32::consts::PI;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::*;
+use log::*;
+use rand::prelude::*;
+use rustc_hash::FxHashMap;
+use structopt::StructOpt;
+
+use flt2vhs::flt::*;
+
+/// Generates synthetic FLT recordings
+///
+/// Aircraft fly lazy circles, lock each other up, shoot missiles,
+/// drop chaff and flares, and flip switches. Ground units crawl along,
+/// and features (buildings, bridges, etc.) get blown up.
+/// The same seed and options always make the same file.
+///
+/// Useful for benchmarking and reproducing bugs without sharing real recordings.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+struct Args {
+    /// Verbosity (-v, -vv, -vvv, etc.)
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+
+    #[structopt(short, long, case_insensitive = true, default_value = "auto")]
+    #[structopt(name = "always/auto/never")]
+    color: logsetup::Color,
+
+    /// Prepend ISO-8601 timestamps to all messages
+    /// (from --verbose). Useful for benchmarking.
+    #[structopt(short, long, verbatim_doc_comment)]
+    timestamps: bool,
+
+    /// Seed for the random number generator
+    #[structopt(short, long, default_value = "0")]
+    seed: u64,
+
+    /// Time of the first record, in seconds
+    #[structopt(long, default_value = "0")]
+    start_time: f32,
+
+    /// Length of the recording, in seconds
+    #[structopt(short, long, default_value = "600")]
+    duration: f32,
+
+    /// Time of day offset, in seconds
+    #[structopt(long, default_value = "43200")]
+    tod_offset: f32,
+
+    /// Position updates per second for each moving entity
+    #[structopt(long, default_value = "2")]
+    rate: f32,
+
+    /// Number of aircraft (each gets a callsign and radar targets)
+    #[structopt(long, default_value = "8")]
+    aircraft: u32,
+
+    /// Number of ground units
+    #[structopt(long, default_value = "16")]
+    ground: u32,
+
+    /// Number of missiles fired by the aircraft
+    #[structopt(long, default_value = "8")]
+    missiles: u32,
+
+    /// Number of chaff bundles dropped by the aircraft
+    #[structopt(long, default_value = "32")]
+    chaff: u32,
+
+    /// Number of flares dropped by the aircraft
+    #[structopt(long, default_value = "32")]
+    flares: u32,
+
+    /// Number of features (static objects)
+    #[structopt(long, default_value = "64")]
+    features: u32,
+
+    /// Number of feature status changes
+    #[structopt(long, default_value = "16")]
+    feature_events: u32,
+
+    /// Number of tracers fired by the aircraft
+    #[structopt(long, default_value = "64")]
+    tracers: u32,
+
+    /// Number of sound effects (besides missile impacts)
+    #[structopt(long, default_value = "32")]
+    sounds: u32,
+
+    /// Number of switch events on the aircraft
+    #[structopt(long, default_value = "64")]
+    switches: u32,
+
+    /// Number of DOF events on the aircraft
+    #[structopt(long, default_value = "64")]
+    dofs: u32,
+
+    /// Insert this many random bytes in the middle of the file
+    #[structopt(long, default_value = "0", name = "bytes")]
+    garbage: usize,
+
+    /// Where to insert --garbage (defaults to somewhere random in the middle half)
+    #[structopt(long, name = "offset")]
+    garbage_at: Option<usize>,
+
+    /// Chop this many bytes off the end of the file,
+    /// like BMS crashed while writing it.
+    #[structopt(long, default_value = "0", name = "count")]
+    #[structopt(verbatim_doc_comment)]
+    truncate: usize,
+
+    /// The FLT file to write
+    #[structopt(name = "output.flt")]
+    output: PathBuf,
+}
+
+fn main() {
+    run().unwrap_or_else(|e| {
+        error!("{:?}", e);
+        std::process::exit(1);
+    });
+}
+
+fn run() -> Result<()> {
+    let args = Args::from_args();
+    logsetup::init_logger(args.verbose, args.timestamps, args.color);
+
+    ensure!(args.duration > 0.0, "Duration must be positive");
+    ensure!(args.rate > 0.0, "Rate must be positive");
+    ensure!(
+        args.aircraft > 0 || (args.missiles + args.chaff + args.flares + args.tracers) == 0,
+        "Need aircraft to shoot missiles or tracers, or drop chaff and flares"
+    );
+
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let flight = generate(&args, &mut rng);
+
+    let mut bytes = Vec::new();
+    flight.write(&mut bytes)?;
+    info!(
+        "Generated {} entities, {} features, {} general events, and {} feature events ({} bytes)",
+        flight.entities.len(),
+        flight.features.len(),
+        flight.general_events.len(),
+        flight.feature_events.len(),
+        bytes.len()
+    );
+
+    if args.garbage > 0 {
+        let at = match args.garbage_at {
+            Some(at) => {
+                ensure!(
+                    at <= bytes.len(),
+                    "Can't insert garbage at {} in a {}-byte file",
+                    at,
+                    bytes.len()
+                );
+                at
+            }
+            None => rng.gen_range(bytes.len() / 4..=bytes.len() * 3 / 4),
+        };
+        let garbage = (0..args.garbage)
+            .map(|_| rng.gen::<u8>())
+            .collect::<Vec<_>>();
+        info!("Inserting {} garbage bytes at {}", args.garbage, at);
+        bytes.splice(at..at, garbage);
+    }
+
+    if args.truncate > 0 {
+        let new_len = bytes.len().saturating_sub(args.truncate);
+        info!("Truncating from {} to {} bytes", bytes.len(), new_len);
+        bytes.truncate(new_len);
+    }
+
+    fs::write(&args.output, &bytes)
+        .with_context(|| format!("Couldn't write {}", args.output.display()))?;
+    Ok(())
+}
+
+// Entity kinds are indexes into BMS's class table.
+// We just need a few distinct ones per category.
+const AIRCRAFT_KINDS: &[i32] = &[1102, 1107, 1136, 1164];
+const GROUND_KINDS: &[i32] = &[1620, 1630, 1706];
+const MISSILE_KINDS: &[i32] = &[2204, 2211];
+const CHAFF_KIND: i32 = 3301;
+const FLARE_KIND: i32 = 3302;
+const FEATURE_KINDS: &[i32] = &[4010, 4022, 4050, 4081];
+
+/// Where the aircraft fly their circles, and where everything else happens.
+const BATTLE_RADIUS: f32 = 60_000.0;
+
+const CALLSIGN_NAMES: &[&str] = &["Viper", "Cowboy", "Falcon", "Snake", "Hawk", "Pontiac"];
+
+/// UIDs for things that aren't aircraft or ground units start here,
+/// keeping the callsign list (indexed by UID) short.
+const FEATURE_UID_START: i32 = 10_000;
+const WEAPON_UID_START: i32 = 100_000;
+
+/// An aircraft flying a circle, so we can find it at any time.
+struct Orbit {
+    center: (f32, f32),
+    altitude: f32,
+    radius: f32,
+    /// Radians per second, signed by direction
+    rate: f32,
+    phase: f32,
+}
+
+impl Orbit {
+    fn random<R: Rng>(rng: &mut R) -> Self {
+        let radius = rng.gen_range(10_000.0..40_000.0);
+        let speed = rng.gen_range(500.0..900.0);
+        let direction = if rng.gen() { 1.0 } else { -1.0 };
+        Self {
+            center: (
+                rng.gen_range(-BATTLE_RADIUS..BATTLE_RADIUS) / 2.0,
+                rng.gen_range(-BATTLE_RADIUS..BATTLE_RADIUS) / 2.0,
+            ),
+            altitude: -rng.gen_range(5_000.0..30_000.0),
+            radius,
+            rate: direction * speed / radius,
+            phase: rng.gen_range(-PI..PI),
+        }
+    }
+
+    fn angle(&self, time: f32) -> f32 {
+        self.phase + self.rate * time
+    }
+
+    /// Position and heading at the given time
+    fn at(&self, time: f32) -> ((f32, f32, f32), f32) {
+        let angle = self.angle(time);
+        let position = (
+            self.center.0 + self.radius * angle.cos(),
+            self.center.1 + self.radius * angle.sin(),
+            self.altitude,
+        );
+        (position, wrap_angle(angle + self.rate.signum() * PI / 2.0))
+    }
+
+    fn update(&self, time: f32, radar_target: i32) -> EntityPositionUpdate {
+        let ((x, y, z), yaw) = self.at(time);
+        EntityPositionUpdate {
+            time,
+            x,
+            y,
+            z,
+            pitch: 0.05 * (time / 10.0).sin(),
+            // Bank into the turn
+            roll: self.rate.signum() * 0.5,
+            yaw,
+            radar_target,
+        }
+    }
+}
+
+fn wrap_angle(a: f32) -> f32 {
+    let wrapped = (a + PI).rem_euclid(2.0 * PI) - PI;
+    // rem_euclid can round up to exactly 2 * PI.
+    if wrapped >= PI {
+        -PI
+    } else {
+        wrapped
+    }
+}
+
+/// Times at which a moving entity gets position updates
+fn update_times(start: f32, end: f32, rate: f32) -> impl Iterator<Item = f32> {
+    let count = ((end - start) * rate).floor() as u32;
+    (0..=count).map(move |i| start + i as f32 / rate)
+}
+
+fn new_entity(kind: i32, flags: u32, position_updates: Vec<EntityPositionUpdate>) -> EntityData {
+    EntityData {
+        position_data: Some(EntityPositionData {
+            kind,
+            flags,
+            position_updates,
+        }),
+        events: Vec::new(),
+    }
+}
+
+fn generate<R: Rng>(args: &Args, rng: &mut R) -> Flight {
+    let start = args.start_time;
+    let end = args.start_time + args.duration;
+    let random_time = |rng: &mut R, latest: f32| rng.gen_range(start..latest.max(start + 0.001));
+
+    let mut flight = Flight {
+        tod_offset: args.tod_offset,
+        start_time: start,
+        end_time: end,
+        ..Default::default()
+    };
+
+    // Aircraft, in flights of four, alternating teams.
+    let aircraft_uids = (1..=args.aircraft as i32).collect::<Vec<_>>();
+    let orbits = aircraft_uids
+        .iter()
+        .map(|_| Orbit::random(rng))
+        .collect::<Vec<_>>();
+
+    for (i, (uid, orbit)) in aircraft_uids.iter().zip(&orbits).enumerate() {
+        // Lock someone else up every thirty seconds or so.
+        let mut radar_target = -1;
+        let mut next_lock = start;
+        let updates = update_times(start, end, args.rate)
+            .map(|time| {
+                if time >= next_lock {
+                    radar_target = if aircraft_uids.len() > 1 && rng.gen_bool(0.75) {
+                        *aircraft_uids
+                            .iter()
+                            .filter(|other| *other != uid)
+                            .choose(rng)
+                            .unwrap()
+                    } else {
+                        -1
+                    };
+                    next_lock = time + rng.gen_range(15.0..45.0);
+                }
+                orbit.update(time, radar_target)
+            })
+            .collect();
+        let kind = *AIRCRAFT_KINDS.choose(rng).unwrap();
+        flight
+            .entities
+            .insert(*uid, new_entity(kind, ENTITY_FLAG_AIRCRAFT, updates));
+
+        let flight_number = i / 4;
+        let label = format!(
+            "{}{}-{}",
+            CALLSIGN_NAMES[flight_number % CALLSIGN_NAMES.len()],
+            flight_number / CALLSIGN_NAMES.len() + 1,
+            i % 4 + 1
+        );
+        flight.callsigns.insert(
+            *uid,
+            CallsignRecord::new(&label, (flight_number % 2) as i32 + 1),
+        );
+    }
+
+    // Ground units trundle along in straight lines.
+    for uid in (0..args.ground as i32).map(|i| args.aircraft as i32 + 1 + i) {
+        let (x, y) = (
+            rng.gen_range(-BATTLE_RADIUS..BATTLE_RADIUS),
+            rng.gen_range(-BATTLE_RADIUS..BATTLE_RADIUS),
+        );
+        let yaw: f32 = rng.gen_range(-PI..PI);
+        let speed = rng.gen_range(10.0..30.0);
+        let updates = update_times(start, end, args.rate)
+            .map(|time| {
+                let distance = speed * (time - start);
+                EntityPositionUpdate {
+                    time,
+                    x: x + distance * yaw.cos(),
+                    y: y + distance * yaw.sin(),
+                    z: 0.0,
+                    pitch: 0.0,
+                    roll: 0.0,
+                    yaw,
+                    radar_target: -1,
+                }
+            })
+            .collect();
+        let kind = *GROUND_KINDS.choose(rng).unwrap();
+        flight.entities.insert(uid, new_entity(kind, 0, updates));
+    }
+
+    let mut next_weapon_uid = WEAPON_UID_START;
+
+    // Missiles fly straight out from their shooter and go boom.
+    for _ in 0..args.missiles {
+        let shooter = rng.gen_range(0..orbits.len());
+        let launch = random_time(rng, end - 1.0);
+        let impact = (launch + rng.gen_range(10.0..40.0)).min(end);
+        let ((x, y, z), yaw) = orbits[shooter].at(launch);
+        let speed = 2000.0;
+
+        let updates = update_times(launch, impact, args.rate)
+            .map(|time| {
+                let distance = speed * (time - launch);
+                EntityPositionUpdate {
+                    time,
+                    x: x + distance * yaw.cos(),
+                    y: y + distance * yaw.sin(),
+                    z,
+                    pitch: 0.0,
+                    roll: 0.0,
+                    yaw,
+                    radar_target: -1,
+                }
+            })
+            .collect::<Vec<_>>();
+        let last = *updates.last().unwrap();
+
+        let kind = *MISSILE_KINDS.choose(rng).unwrap();
+        flight.entities.insert(
+            next_weapon_uid,
+            new_entity(kind, ENTITY_FLAG_MISSILE, updates),
+        );
+        next_weapon_uid += 1;
+
+        flight.general_events.push(GeneralEvent {
+            type_byte: REC_TYPE_STATIONARY_SFX,
+            start: last.time,
+            stop: last.time + 3.0,
+            kind: 1,
+            x: last.x,
+            y: last.y,
+            z: last.z,
+            scale: rng.gen_range(0.5..2.0),
+            ..Default::default()
+        });
+    }
+
+    // Chaff and flares fall out of the sky behind their aircraft.
+    let countermeasures = std::iter::repeat_n((CHAFF_KIND, ENTITY_FLAG_CHAFF), args.chaff as usize)
+        .chain(std::iter::repeat_n(
+            (FLARE_KIND, ENTITY_FLAG_FLARE),
+            args.flares as usize,
+        ))
+        .collect::<Vec<_>>();
+    for (kind, flags) in countermeasures {
+        let dropper = rng.gen_range(0..orbits.len());
+        let dropped = random_time(rng, end - 0.5);
+        let gone = (dropped + 5.0).min(end);
+        let ((x, y, z), yaw) = orbits[dropper].at(dropped);
+
+        let updates = update_times(dropped, gone, args.rate)
+            .map(|time| EntityPositionUpdate {
+                time,
+                x,
+                y,
+                z: z + 50.0 * (time - dropped),
+                pitch: 0.0,
+                roll: 0.0,
+                yaw,
+                radar_target: -1,
+            })
+            .collect();
+        flight
+            .entities
+            .insert(next_weapon_uid, new_entity(kind, flags, updates));
+        next_weapon_uid += 1;
+    }
+
+    // Features are laid out in a grid, in groups of four that share a lead.
+    let feature_uids = (0..args.features as i32)
+        .map(|i| FEATURE_UID_START + i)
+        .collect::<Vec<_>>();
+    let grid_width = (args.features as f32).sqrt().ceil() as i32;
+    for (i, uid) in feature_uids.iter().enumerate() {
+        let i = i as i32;
+        flight.features.insert(
+            *uid,
+            FeatureData {
+                kind: *FEATURE_KINDS.choose(rng).unwrap(),
+                lead_uid: FEATURE_UID_START + i / 4 * 4,
+                slot: i % 4,
+                special_flags: rng.gen_range(0..2),
+                time: start,
+                x: (i % grid_width) as f32 * 500.0,
+                y: (i / grid_width) as f32 * 500.0,
+                z: 0.0,
+                pitch: 0.0,
+                roll: 0.0,
+                yaw: rng.gen_range(-PI..PI),
+            },
+        );
+    }
+
+    if !feature_uids.is_empty() {
+        let mut statuses: FxHashMap<i32, i32> = FxHashMap::default();
+        let mut events = (0..args.feature_events)
+            .map(|_| (random_time(rng, end), *feature_uids.choose(rng).unwrap()))
+            .collect::<Vec<_>>();
+        events.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (time, feature_uid) in events {
+            let previous_status = statuses.get(&feature_uid).copied().unwrap_or(0);
+            let new_status = (previous_status + rng.gen_range(1..3)).min(3);
+            statuses.insert(feature_uid, new_status);
+            flight.feature_events.push(FeatureEvent {
+                time,
+                feature_uid,
+                new_status,
+                previous_status,
+            });
+        }
+    }
+
+    // Guns guns guns
+    for _ in 0..args.tracers {
+        let shooter = rng.gen_range(0..orbits.len());
+        let time = random_time(rng, end);
+        let ((x, y, z), yaw) = orbits[shooter].at(time);
+        flight.general_events.push(GeneralEvent {
+            type_byte: REC_TYPE_TRACER_START,
+            start: time,
+            stop: time + 5.0, // See flt::read_record()
+            x,
+            y,
+            z,
+            dx: 3000.0 * yaw.cos(),
+            dy: 3000.0 * yaw.sin(),
+            dz: 0.0,
+            ..Default::default()
+        });
+    }
+
+    // Half the sounds are stationary, half follow an aircraft.
+    for i in 0..args.sounds {
+        let time = random_time(rng, end);
+        let ttl = rng.gen_range(1.0..10.0);
+        let scale = rng.gen_range(0.5..2.0);
+        let kind = rng.gen_range(1..20);
+        let event = if i % 2 == 0 || orbits.is_empty() {
+            GeneralEvent {
+                type_byte: REC_TYPE_STATIONARY_SFX,
+                start: time,
+                stop: time + ttl,
+                kind,
+                x: rng.gen_range(-BATTLE_RADIUS..BATTLE_RADIUS),
+                y: rng.gen_range(-BATTLE_RADIUS..BATTLE_RADIUS),
+                z: 0.0,
+                scale,
+                ..Default::default()
+            }
+        } else {
+            let user = rng.gen_range(0..orbits.len());
+            let ((x, y, z), yaw) = orbits[user].at(time);
+            let speed = orbits[user].rate.abs() * orbits[user].radius;
+            GeneralEvent {
+                type_byte: REC_TYPE_MOVING_SFX,
+                start: time,
+                stop: time + ttl,
+                kind,
+                user: aircraft_uids[user],
+                flags: rng.gen_range(0..4),
+                x,
+                y,
+                z,
+                dx: speed * yaw.cos(),
+                dy: speed * yaw.sin(),
+                dz: 0.0,
+                scale,
+                ..Default::default()
+            }
+        };
+        flight.general_events.push(event);
+    }
+    flight
+        .general_events
+        .sort_by(|a, b| a.start.total_cmp(&b.start));
+
+    // Gear, flaps, speedbrakes...
+    if !aircraft_uids.is_empty() {
+        let mut switch_values: FxHashMap<(i32, i32), i32> = FxHashMap::default();
+        let mut dof_values: FxHashMap<(i32, i32), f32> = FxHashMap::default();
+
+        let mut events = (0..args.switches + args.dofs)
+            .map(|i| {
+                (
+                    random_time(rng, end),
+                    *aircraft_uids.choose(rng).unwrap(),
+                    i < args.switches,
+                )
+            })
+            .collect::<Vec<_>>();
+        events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        for (time, uid, is_switch) in events {
+            let payload = if is_switch {
+                let switch_number = rng.gen_range(0..10);
+                let previous_switch_value = switch_values
+                    .get(&(uid, switch_number))
+                    .copied()
+                    .unwrap_or(0);
+                let new_switch_value = 1 - previous_switch_value;
+                switch_values.insert((uid, switch_number), new_switch_value);
+                EntityEventPayload::SwitchEvent(SwitchEvent {
+                    switch_number,
+                    new_switch_value,
+                    previous_switch_value,
+                })
+            } else {
+                let dof_number = rng.gen_range(0..8);
+                let previous_dof_value = dof_values.get(&(uid, dof_number)).copied().unwrap_or(0.0);
+                let new_dof_value = rng.gen_range(0.0..1.0);
+                dof_values.insert((uid, dof_number), new_dof_value);
+                EntityEventPayload::DofEvent(DofEvent {
+                    dof_number,
+                    new_dof_value,
+                    previous_dof_value,
+                })
+            };
+            flight
+                .entities
+                .get_mut(&uid)
+                .unwrap()
+                .events
+                .push(EntityEvent { time, payload });
+        }
+    }
+
+    flight
+}
@@ -0,0 +1,256 @@
+//! Writes a flight as a Tacview text ACMI (`.txt.acmi`) file,
+//! so it can be opened in Tacview without going through its VHS importer.
+//!
+//! See <https://www.tacview.net/documentation/acmi/en/> for the format.
+//!
+//! BMS doesn't tell us where its theater is on the globe,
+//! so the origin of its map goes at 0°N 0°E, and positions are
+//! (a flat-earth approximation of) how far north and east of that they are.
+//! We don't know what day it is either, so time frames are times of day
+//! from an arbitrary midnight.
+
+use std::io::prelude::*;
+
+use anyhow::*;
+
+use crate::flt::{self, Flight};
+
+const FEET_TO_METERS: f64 = 0.3048;
+const METERS_PER_DEGREE_LATITUDE: f64 = 110_574.0;
+const METERS_PER_DEGREE_LONGITUDE: f64 = 111_320.0;
+
+/// Writes out a flight as text ACMI to any writer.
+///
+/// Each entity gets its name, color, and type when it first shows up,
+/// a transform each time it moves, and is removed when it stops updating
+/// (unless it's still around when the recording ends).
+/// Features are written as static objects when they show up.
+/// General events (tracers, sound effects) and feature events aren't written.
+pub fn write_to<W: Write>(flight: &Flight, mut w: W) -> Result<()> {
+    write_header(&mut w).context("Couldn't write ACMI header")?;
+
+    // Gather everything that happens, then play it back in order.
+    let mut happenings = Vec::new();
+
+    let mut entity_ids = flight.entities.keys().copied().collect::<Vec<_>>();
+    entity_ids.sort_unstable();
+    for id in entity_ids {
+        let data = match &flight.entities[&id].position_data {
+            Some(data) => data,
+            None => continue,
+        };
+        for (i, update) in data.position_updates.iter().enumerate() {
+            happenings.push((
+                update.time,
+                Happening::Update {
+                    id,
+                    data,
+                    update,
+                    first: i == 0,
+                },
+            ));
+        }
+        if let Some(last) = data.position_updates.last() {
+            if last.time < flight.end_time {
+                happenings.push((last.time, Happening::Removal { id }));
+            }
+        }
+    }
+
+    let mut feature_ids = flight.features.keys().copied().collect::<Vec<_>>();
+    feature_ids.sort_unstable();
+    for id in feature_ids {
+        let feature = &flight.features[&id];
+        happenings.push((feature.time, Happening::Feature { id, feature }));
+    }
+
+    // Stable, so each entity's updates come before its removal.
+    happenings.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+    let mut frame = None;
+    for (time, happening) in &happenings {
+        if frame != Some(*time) {
+            frame = Some(*time);
+            writeln!(w, "#{:.2}", f64::from(*time) + f64::from(flight.tod_offset))?;
+        }
+        happening.write(flight, &mut w)?;
+    }
+
+    w.flush().context("Couldn't write ACMI")?;
+    Ok(())
+}
+
+fn write_header<W: Write>(w: &mut W) -> Result<()> {
+    writeln!(w, "FileType=text/acmi/tacview")?;
+    writeln!(w, "FileVersion=2.2")?;
+    writeln!(w, "0,ReferenceTime=2000-01-01T00:00:00Z")?;
+    writeln!(w, "0,ReferenceLongitude=0")?;
+    writeln!(w, "0,ReferenceLatitude=0")?;
+    writeln!(w, "0,DataSource=Falcon BMS")?;
+    writeln!(w, "0,DataRecorder=flt2vhs {}", env!("CARGO_PKG_VERSION"))?;
+    Ok(())
+}
+
+/// Something to write at a given time
+enum Happening<'a> {
+    Update {
+        id: i32,
+        data: &'a flt::EntityPositionData,
+        update: &'a flt::EntityPositionUpdate,
+        /// Whether this is the first time we've seen the entity
+        first: bool,
+    },
+    Removal {
+        id: i32,
+    },
+    Feature {
+        id: i32,
+        feature: &'a flt::FeatureData,
+    },
+}
+
+impl Happening<'_> {
+    fn write<W: Write>(&self, flight: &Flight, w: &mut W) -> Result<()> {
+        match self {
+            Happening::Update {
+                id,
+                data,
+                update,
+                first,
+            } => {
+                write!(
+                    w,
+                    "{:x},T={}",
+                    entity_object_id(*id),
+                    Transform {
+                        x: update.x,
+                        y: update.y,
+                        z: update.z,
+                        roll: update.roll,
+                        pitch: update.pitch,
+                        yaw: update.yaw,
+                    }
+                )?;
+                if *first {
+                    write_properties(flight, *id, entity_type(data.flags), w)?;
+                }
+                writeln!(w)?;
+            }
+            Happening::Removal { id } => writeln!(w, "-{:x}", entity_object_id(*id))?,
+            Happening::Feature { id, feature } => {
+                write!(
+                    w,
+                    "{:x},T={}",
+                    feature_object_id(*id),
+                    Transform {
+                        x: feature.x,
+                        y: feature.y,
+                        z: feature.z,
+                        roll: feature.roll,
+                        pitch: feature.pitch,
+                        yaw: feature.yaw,
+                    }
+                )?;
+                write_properties(flight, *id, "Ground+Static", w)?;
+                writeln!(w)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the name and color from an object's callsign (if it has one)
+/// and its type
+fn write_properties<W: Write>(flight: &Flight, id: i32, kind: &str, w: &mut W) -> Result<()> {
+    if let Some(callsign) = flight.callsigns.get(&id) {
+        let name = callsign.label_lossy();
+        if !name.is_empty() {
+            write!(w, ",Name={}", escape(&name))?;
+        }
+        if let Some(color) = color(callsign.team_color) {
+            write!(w, ",Color={}", color)?;
+        }
+    }
+    write!(w, ",Type={}", kind)?;
+    Ok(())
+}
+
+/// ACMI IDs are unsigned (and 0 is the global object),
+/// so set a bit above all of our 32-bit ones.
+fn entity_object_id(id: i32) -> u64 {
+    1 << 32 | u64::from(id as u32)
+}
+
+/// Like [`entity_object_id()`], but in a space of their own,
+/// since an entity and a feature can have the same UID.
+fn feature_object_id(id: i32) -> u64 {
+    2 << 32 | u64::from(id as u32)
+}
+
+/// Tacview's object type tags for an entity with the given
+/// [`EntityPositionData::flags`](flt::EntityPositionData::flags)
+fn entity_type(flags: u32) -> &'static str {
+    if flags & flt::ENTITY_FLAG_AIRCRAFT != 0 {
+        "Air+FixedWing"
+    } else if flags & flt::ENTITY_FLAG_MISSILE != 0 {
+        "Weapon+Missile"
+    } else if flags & flt::ENTITY_FLAG_CHAFF != 0 {
+        "Misc+Decoy+Chaff"
+    } else if flags & flt::ENTITY_FLAG_FLARE != 0 {
+        "Misc+Decoy+Flare"
+    } else {
+        // Ground vehicles, ships, and everything else
+        "Ground"
+    }
+}
+
+/// The closest Tacview color to each of Falcon's team colors
+/// (white, green, blue, brown, orange, yellow, red, gray)
+fn color(team_color: i32) -> Option<&'static str> {
+    match team_color {
+        0 | 7 => Some("Grey"),
+        1 => Some("Green"),
+        2 => Some("Blue"),
+        3 | 4 => Some("Orange"),
+        5 => Some("Yellow"),
+        6 => Some("Red"),
+        _ => None,
+    }
+}
+
+/// Commas separate properties, so escape them (and the escape character).
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('\n', " ")
+}
+
+/// A position and orientation in BMS's coordinates:
+/// feet north, east, and down, and radians.
+struct Transform {
+    x: f32,
+    y: f32,
+    z: f32,
+    roll: f32,
+    pitch: f32,
+    yaw: f32,
+}
+
+impl std::fmt::Display for Transform {
+    /// Longitude | Latitude | Altitude | Roll | Pitch | Yaw,
+    /// in degrees and meters
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let meters = |feet: f32| f64::from(feet) * FEET_TO_METERS;
+        write!(
+            f,
+            "{:.7}|{:.7}|{:.2}|{:.1}|{:.1}|{:.1}",
+            meters(self.y) / METERS_PER_DEGREE_LONGITUDE,
+            meters(self.x) / METERS_PER_DEGREE_LATITUDE,
+            -meters(self.z) + 0.0, // No -0
+            self.roll.to_degrees(),
+            self.pitch.to_degrees(),
+            self.yaw.to_degrees(),
+        )
+    }
+}
@@ -0,0 +1,179 @@
+//! Minimum-cost bipartite matching, used to pair up entities when merging flights
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+};
+
+/// Pairs each of `rows` rows with at most one of `columns` columns
+/// (and vice versa) for the lowest total cost.
+///
+/// Each `(row, column, cost)` in `pairs` gives the (non-negative) cost
+/// of pairing a row and column; ones not listed can't be paired.
+/// Leaving a row unpaired costs `unpaired_cost`; leaving a column unpaired is free.
+///
+/// Returns the column each row was paired with, if any.
+pub fn assign(
+    rows: usize,
+    columns: usize,
+    pairs: &[(usize, usize, f32)],
+    unpaired_cost: f32,
+) -> Vec<Option<usize>> {
+    // Give each row a column of its own that means "unpaired".
+    // Every row can then get a column, which the algorithm needs.
+    let all_columns = columns + rows;
+    let mut edges: Vec<Vec<(usize, f64)>> = (0..rows)
+        .map(|row| vec![(columns + row, f64::from(unpaired_cost))])
+        .collect();
+    for (row, column, cost) in pairs {
+        edges[*row].push((*column, f64::from(*cost)));
+    }
+
+    // This is the Hungarian algorithm, done as successive shortest paths
+    // so that it only ever looks at the pairs we were given.
+    // Each row is added in turn by finding the cheapest way to shuffle
+    // the assignments so far to make room for it.
+    //
+    // Potentials keep every pair's reduced cost
+    // (`cost - row_potential - column_potential`) non-negative,
+    // which lets us find that cheapest shuffle with Dijkstra's algorithm.
+    let mut row_potential = vec![0.0; rows];
+    let mut column_potential = vec![0.0; all_columns];
+    let mut row_of: Vec<Option<usize>> = vec![None; all_columns];
+    let mut column_of: Vec<Option<usize>> = vec![None; rows];
+    let mut search = Search::new(all_columns);
+
+    for new_row in 0..rows {
+        search.relax(new_row, 0.0, &edges, &row_potential, &column_potential);
+
+        // Find the nearest unassigned column...
+        let mut finished = Vec::new();
+        let (free_column, shortest) = loop {
+            // There's always one: the new row's own "unpaired" column.
+            let (column, distance) = search.next().expect("No free column");
+            match row_of[column] {
+                None => break (column, distance),
+                Some(row) => {
+                    finished.push(column);
+                    search.relax(row, distance, &edges, &row_potential, &column_potential);
+                }
+            }
+        };
+
+        // ...update potentials so the path there costs nothing
+        // (without making anything else negative)...
+        row_potential[new_row] += shortest;
+        for column in &finished {
+            let slack = shortest - search.distance[*column];
+            column_potential[*column] -= slack;
+            row_potential[row_of[*column].unwrap()] += slack;
+        }
+
+        // ...then flip the assignments along that path.
+        let mut column = free_column;
+        loop {
+            let row = search.reached_from[column];
+            let previous = column_of[row];
+            row_of[column] = Some(row);
+            column_of[row] = Some(column);
+            if row == new_row {
+                break;
+            }
+            column = previous.unwrap();
+        }
+
+        search.reset();
+    }
+
+    column_of
+        .into_iter()
+        .map(|column| column.filter(|c| *c < columns))
+        .collect()
+}
+
+/// Dijkstra's algorithm over columns, reached by way of the rows assigned to them
+struct Search {
+    distance: Vec<f64>,
+    /// The row we came from to get to each column
+    reached_from: Vec<usize>,
+    done: Vec<bool>,
+    /// Columns to reset when we're done
+    touched: Vec<usize>,
+    queue: BinaryHeap<Reverse<(Distance, usize)>>,
+}
+
+impl Search {
+    fn new(columns: usize) -> Self {
+        Self {
+            distance: vec![f64::INFINITY; columns],
+            reached_from: vec![0; columns],
+            done: vec![false; columns],
+            touched: Vec::new(),
+            queue: BinaryHeap::new(),
+        }
+    }
+
+    /// Updates the distance to each column reachable from `row`,
+    /// which is `from` away.
+    fn relax(
+        &mut self,
+        row: usize,
+        from: f64,
+        edges: &[Vec<(usize, f64)>],
+        row_potential: &[f64],
+        column_potential: &[f64],
+    ) {
+        for (column, cost) in &edges[row] {
+            if self.done[*column] {
+                continue;
+            }
+            let to = from + cost - row_potential[row] - column_potential[*column];
+            if to < self.distance[*column] {
+                if self.distance[*column] == f64::INFINITY {
+                    self.touched.push(*column);
+                }
+                self.distance[*column] = to;
+                self.reached_from[*column] = row;
+                self.queue.push(Reverse((Distance(to), *column)));
+            }
+        }
+    }
+
+    /// Returns the nearest column we haven't returned yet, and its distance.
+    fn next(&mut self) -> Option<(usize, f64)> {
+        while let Some(Reverse((Distance(distance), column))) = self.queue.pop() {
+            if self.done[column] || distance > self.distance[column] {
+                continue; // Stale; we found a shorter way there since.
+            }
+            self.done[column] = true;
+            return Some((column, distance));
+        }
+        None
+    }
+
+    fn reset(&mut self) {
+        for column in self.touched.drain(..) {
+            self.distance[column] = f64::INFINITY;
+            self.done[column] = false;
+        }
+        self.queue.clear();
+    }
+}
+
+/// A path length, ordered so that it can go in a heap
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Distance(f64);
+
+impl Eq for Distance {}
+
+impl PartialOrd for Distance {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Distance {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
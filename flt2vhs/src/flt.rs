@@ -1,22 +1,38 @@
 #![allow(clippy::float_cmp)]
-//! Parses info we need from a `.flt` file
-
-use std::{io, io::prelude::*, path::Path, time::Instant};
+//! Parses info we need from a `.flt` file (and writes it back out)
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    convert::TryFrom,
+    fmt, io,
+    io::prelude::*,
+    iter::Peekable,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
 
 use anyhow::*;
 use log::*;
-use rustc_hash::{FxHashMap, FxHashSet};
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use serde_derive::Serialize;
+
+use acmitape::primitives::*;
+pub use acmitape::CallsignRecord;
 
-use crate::primitives::*;
+use crate::assignment;
 
 /// Information parsed from a .flt file, needed to make a .vhs file
 #[derive(Debug, Clone, Default)]
 pub struct Flight {
-    /// True if something went wrong reading the `.flt`
+    /// Set if something went wrong reading the `.flt`
     /// (ran out of bytes), bad reads, etc.
     ///
     /// We'll warn the user but still do the best with what we have.
-    pub corrupted: bool,
+    pub corruption: Option<Corruption>,
 
     /// Time of day offset
     pub tod_offset: f32,
@@ -53,32 +69,330 @@ pub struct Flight {
 }
 
 impl Flight {
-    pub fn parse<R: Read>(mut r: R) -> Self {
-        let mut flight = Self {
-            start_time: f32::NEG_INFINITY,
-            end_time: f32::NEG_INFINITY,
-            ..Default::default()
-        };
+    /// Parses a `.flt` record stream.
+    ///
+    /// This doesn't fail - if the file is corrupted, we keep everything
+    /// up to the bad record and describe what happened in [`Flight::corruption`].
+    pub fn parse<R: Read>(r: R) -> Self {
+        let mut flight = Self::empty();
+
+        let mut r = OffsetRead::new(r);
+        let mut records_read = [0; REC_TYPE_COUNT];
 
         // A .flt is a flat stream of events of different types,
         // discriminated by a leading byte.
         loop {
             match read_record(&mut flight, &mut r) {
-                Ok(true) => { /* continue */ }
-                Ok(false) => break, // EOF
+                Ok(Some(type_byte)) => records_read[type_byte as usize] += 1,
+                Ok(None) => break, // EOF
                 Err(e) => {
-                    warn_on(e);
-                    flight.corrupted = true;
+                    debug!("{}", e);
+                    // See how much we're leaving on the table.
+                    let _ = io::copy(&mut r, &mut io::sink());
+                    flight.corruption = Some(Corruption::new(e, r.offset, records_read));
+                    break;
+                }
+            }
+        }
+
+        flight.chuck_undefined_entities();
+        flight
+    }
+
+    /// Like [`parse()`](Flight::parse), but decodes records straight out of
+    /// the file's bytes (e.g., a memory map) instead of copying them out of a reader.
+    pub fn parse_bytes(bytes: &[u8]) -> Self {
+        Self::parse_slice(bytes, false)
+    }
+
+    /// Like [`parse_bytes()`](Flight::parse_bytes), but splits big files into chunks
+    /// (one per thread) and parses them in parallel.
+    pub fn parse_parallel(bytes: &[u8]) -> Self {
+        let chunk_size = std::cmp::max(
+            bytes.len() / rayon::current_num_threads(),
+            MIN_PARALLEL_CHUNK_SIZE,
+        );
+        Self::parse_in_chunks(bytes, chunk_size)
+    }
+
+    /// Like [`parse_parallel()`](Flight::parse_parallel),
+    /// but with chunks of (at least) `chunk_size` bytes.
+    ///
+    /// We find where each chunk starts by hopping from record to record,
+    /// parse each one into a partial flight, then stitch those together.
+    /// The result is the same as [`parse_bytes()`](Flight::parse_bytes)'s.
+    pub fn parse_in_chunks(bytes: &[u8], chunk_size: usize) -> Self {
+        let chunks = chunk_boundaries(bytes, chunk_size);
+        if chunks.len() == 1 {
+            return Self::parse_bytes(bytes);
+        }
+        debug!("Parsing in {} chunks", chunks.len());
+
+        let partials = chunks
+            .into_par_iter()
+            .map(|chunk| PartialFlight::parse(bytes, chunk))
+            .collect::<Vec<_>>();
+        Self::from_partials(partials, bytes.len() as u64)
+    }
+
+    /// Stitches together flights parsed from consecutive chunks of a `.flt`
+    fn from_partials(partials: Vec<PartialFlight>, file_length: u64) -> Self {
+        let mut flight = Self::empty();
+        let mut stitching = Stitching::new(file_length);
+        for partial in partials {
+            if !flight.stitch(partial, &mut stitching) {
+                break;
+            }
+        }
+        flight.finish_stitching(stitching);
+        flight
+    }
+
+    /// Parses the `.flt` a chunk (of at least `chunk_size` bytes) at a time,
+    /// so that memory use doesn't grow with the length of the recording.
+    ///
+    /// Each chunk's position updates, entity events, general events, and feature events
+    /// are handed to `on_chunk` (in a flight with nothing else), then dropped.
+    /// Returns everything else - entities (without their position updates or events),
+    /// features, callsigns, times, and corruption -
+    /// just like [`parse_bytes()`](Flight::parse_bytes) would.
+    ///
+    /// Entities in a chunk might be chucked by the end (see [`Flight::parse()`]),
+    /// so check the returned flight before using them.
+    pub fn parse_streaming<F: FnMut(Flight)>(
+        bytes: &[u8],
+        chunk_size: usize,
+        mut on_chunk: F,
+    ) -> Self {
+        let mut flight = Self::empty();
+        let mut stitching = Stitching::new(bytes.len() as u64);
+        for chunk in chunk_boundaries(bytes, chunk_size) {
+            let keep_going = flight.stitch(PartialFlight::parse(bytes, chunk), &mut stitching);
+            on_chunk(flight.take_records());
+            if !keep_going {
+                break;
+            }
+        }
+        flight.finish_stitching(stitching);
+        flight
+    }
+
+    /// Stitches a chunk onto the end of the flight.
+    /// Returns false if the chunk ended in an error, so there's nothing after it to stitch.
+    fn stitch(&mut self, partial: PartialFlight, stitching: &mut Stitching) -> bool {
+        let part = partial.flight;
+        for (total, read) in stitching
+            .records_read
+            .iter_mut()
+            .zip(partial.records_read.iter())
+        {
+            *total += read;
+        }
+
+        // Same as note_time(), but for the chunk's first and last records.
+        // (Skip chunks that never set a start time, like ones with only TOD records.)
+        if self.start_time < 0.0 && part.start_time != f32::NEG_INFINITY {
+            self.start_time = part.start_time;
+        }
+        if self.end_time < part.end_time {
+            self.end_time = part.end_time;
+        }
+        if partial.records_read[REC_TYPE_TOD_OFFSET as usize] > 0 {
+            self.tod_offset = part.tod_offset;
+        }
+
+        // Check against the features from earlier chunks
+        // before we add this one's.
+        for (event, defined) in partial.feature_events {
+            if defined || self.features.contains_key(&event.feature_uid) {
+                self.feature_events.push(event);
+            } else {
+                trace!("No feature for {:?}", event);
+            }
+        }
+        for (uid, feature) in part.features {
+            // The first definition wins.
+            self.features.entry(uid).or_insert(feature);
+        }
+
+        for (uid, entity) in part.entities {
+            let merged = self.entities.entry(uid).or_default();
+            append(&mut merged.events, entity.events);
+            match (&mut merged.position_data, entity.position_data) {
+                (Some(merged), Some(data)) => {
+                    append(&mut merged.position_updates, data.position_updates)
+                }
+                (merged @ None, data) => *merged = data,
+                (Some(_), None) => {}
+            }
+        }
+
+        append(&mut self.general_events, part.general_events);
+        stitching.callsign_lists.extend(partial.callsign_lists);
+
+        match partial.error {
+            Some(e) => {
+                debug!("{}", e);
+                self.corruption = Some(Corruption::new(
+                    e,
+                    stitching.file_length,
+                    stitching.records_read,
+                ));
+                // Anything after was never parsed (see chunk_boundaries()).
+                false
+            }
+            None => true,
+        }
+    }
+
+    fn finish_stitching(&mut self, stitching: Stitching) {
+        // Callsign lists come last, once we know every entity and feature.
+        for callsign_array in stitching.callsign_lists {
+            self.resolve_callsigns(callsign_array);
+        }
+
+        self.chuck_undefined_entities();
+    }
+
+    /// Moves out every record that piles up as the recording goes on
+    /// (see [`parse_streaming()`](Flight::parse_streaming)).
+    fn take_records(&mut self) -> Self {
+        let entities = self
+            .entities
+            .iter_mut()
+            .map(|(uid, entity)| {
+                let position_data = entity
+                    .position_data
+                    .as_mut()
+                    .map(|data| EntityPositionData {
+                        kind: data.kind,
+                        flags: data.flags,
+                        position_updates: std::mem::take(&mut data.position_updates),
+                    });
+                let events = std::mem::take(&mut entity.events);
+                (
+                    *uid,
+                    EntityData {
+                        position_data,
+                        events,
+                    },
+                )
+            })
+            .filter(|(_uid, entity)| {
+                !entity.events.is_empty()
+                    || entity
+                        .position_data
+                        .as_ref()
+                        .is_some_and(|data| !data.position_updates.is_empty())
+            })
+            .collect();
+
+        Self {
+            entities,
+            general_events: std::mem::take(&mut self.general_events),
+            feature_events: std::mem::take(&mut self.feature_events),
+            ..Self::empty()
+        }
+    }
+
+    /// Like [`parse_bytes()`](Flight::parse_bytes), but instead of stopping at the first bad record,
+    /// skips ahead to the next spot that looks like a good one and keeps going.
+    ///
+    /// A spot looks good if a few records in a row there have valid type bytes,
+    /// times close after the last good record, and sane coordinates.
+    /// What we skipped is noted in [`Flight::corruption`].
+    pub fn parse_recovering(bytes: &[u8]) -> Self {
+        Self::parse_slice(bytes, true)
+    }
+
+    fn parse_slice(bytes: &[u8], recover: bool) -> Self {
+        let mut flight = Self::empty();
+
+        let mut at = 0;
+        let mut records_read = [0; REC_TYPE_COUNT];
+        // The time of the last record that looked good.
+        // (Garbage can look enough like a record to parse, so don't trust `flight.end_time`.)
+        let mut last_time = None;
+
+        loop {
+            let record = if recover {
+                record_size(&bytes[at..]).and_then(|size| bytes.get(at..at + size))
+            } else {
+                None
+            };
+            let result = match record {
+                Some(record) if !plausible_record(record, last_time) => {
+                    Err(FltError::Implausible {
+                        type_byte: record[0],
+                        offset: at as u64,
+                    })
+                }
+                // If we can't size it up, let decode_record() complain
+                // about the bad type byte or the file ending.
+                _ => decode_record(&mut flight, bytes, at),
+            };
+
+            match result {
+                Ok(Some(decoded)) => {
+                    let type_byte = decoded.type_byte;
+                    at += decoded.size;
+                    flight.add_record(decoded.time, decoded.record);
+                    last_time = record.and_then(record_time).or(last_time);
+                    match &mut flight.corruption {
+                        None => records_read[type_byte as usize] += 1,
+                        Some(corruption) => corruption.records_recovered += 1,
+                    }
+                }
+                Ok(None) => break, // EOF
+                Err(e) if !recover => {
+                    debug!("{}", e);
+                    flight.corruption = Some(Corruption::new(e, bytes.len() as u64, records_read));
                     break;
                 }
+                Err(e) => {
+                    debug!("{}", e);
+                    let bad_offset = e.offset();
+                    let resume_at = find_resync(bytes, bad_offset as usize + 1, last_time);
+
+                    let corruption = flight.corruption.get_or_insert_with(|| {
+                        Corruption::new(e, bytes.len() as u64, records_read)
+                    });
+                    let skip_to = resume_at.unwrap_or(bytes.len());
+                    corruption.skipped.push(bad_offset..skip_to as u64);
+
+                    match resume_at {
+                        Some(resume_at) => {
+                            debug!("Skipped to what looks like a record at byte {}", resume_at);
+                            at = resume_at;
+                        }
+                        None => {
+                            debug!("Couldn't find any good records after byte {}", bad_offset);
+                            break;
+                        }
+                    }
+                }
             }
         }
 
+        flight.chuck_undefined_entities();
+        flight
+    }
+
+    /// A flight with nothing parsed into it yet
+    fn empty() -> Self {
+        Self {
+            start_time: f32::NEG_INFINITY,
+            end_time: f32::NEG_INFINITY,
+            ..Default::default()
+        }
+    }
+
+    fn chuck_undefined_entities(&mut self) {
         // Some entities get events, but never any position updates to place
         // them in the world and provide their other needed state.
         // Very strange, but let's throw them out since we can't do anything
         // with events for an entity that was never defined.
-        let entities_to_chuck = flight
+        let entities_to_chuck = self
             .entities
             .iter()
             .filter(|(_uid, data)| data.position_data.is_none())
@@ -94,167 +408,546 @@ impl Flight {
             );
         }
         for (uid, _) in entities_to_chuck {
-            assert!(flight.entities.remove(&uid).is_some());
+            self.entities.remove(&uid);
         }
+    }
 
-        flight
+    /// True if something went wrong reading the `.flt` (see [`Flight::corruption`])
+    pub fn corrupted(&self) -> bool {
+        self.corruption.is_some()
+    }
+
+    /// Writes the flight back out as a `.flt` record stream
+    /// that [`parse()`](Flight::parse) can read.
+    ///
+    /// Like BMS, we lead with the time of day offset, write everything else
+    /// chronologically, and finish with the callsign list.
+    /// Each entity's position updates and events stay in the order they're in here,
+    /// as do general and feature events.
+    ///
+    /// The callsign list is indexed by ID, so this fails if any callsign
+    /// has an ID above [`MAX_CALLSIGN_ID`].
+    pub fn write<W: Write>(&self, w: W) -> Result<()> {
+        // Check before we've written anything.
+        if let Some(max) = self.callsigns.keys().max() {
+            ensure!(
+                *max <= MAX_CALLSIGN_ID,
+                "Callsign ID {} is too big to write to a FLT (the most is {})",
+                max,
+                MAX_CALLSIGN_ID
+            );
+        }
+
+        let mut w = io::BufWriter::new(w);
+
+        write_record_head(REC_TYPE_TOD_OFFSET, self.tod_offset, &mut w)?;
+
+        // Every list of things we're writing is (hopefully) already chronological.
+        // Merge them all together, BMS-style.
+        let mut streams = self.record_streams();
+        let mut heap = BinaryHeap::with_capacity(streams.len());
+        for (stream, records) in streams.iter_mut().enumerate() {
+            if let Some(next) = records.peek() {
+                heap.push(Reverse(RecordKey::new(next, stream, self)));
+            }
+        }
+
+        let mut last_time = None;
+        while let Some(Reverse(key)) = heap.pop() {
+            let records = &mut streams[key.stream];
+            let record = records.next().unwrap();
+            record.write(&mut w)?;
+            last_time = Some(record.time());
+
+            if let Some(next) = records.peek() {
+                heap.push(Reverse(RecordKey::new(next, key.stream, self)));
+            }
+        }
+
+        // BMS writes the callsigns as it closes the file,
+        // so they're usually what sets the end time.
+        let callsign_time = match last_time {
+            Some(last) if self.end_time.is_finite() => last.max(self.end_time),
+            Some(last) => last,
+            None if self.end_time.is_finite() => self.end_time,
+            None => 0.0,
+        };
+        self.write_callsigns(callsign_time, &mut w)?;
+
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Each chronological list of records to write
+    fn record_streams(&self) -> Vec<Peekable<Box<dyn Iterator<Item = RecordRef<'_>> + '_>>> {
+        let mut streams: Vec<Box<dyn Iterator<Item = RecordRef<'_>> + '_>> = Vec::new();
+
+        let mut features = self.features.iter().collect::<Vec<_>>();
+        features.sort_by(|(left_id, left), (right_id, right)| {
+            left.time
+                .total_cmp(&right.time)
+                .then_with(|| left_id.cmp(right_id))
+        });
+        streams.push(Box::new(
+            features
+                .into_iter()
+                .map(|(id, feature)| RecordRef::Feature(*id, feature)),
+        ));
+
+        // Sort entities so that the output doesn't depend on hash map order.
+        let mut entities = self
+            .entities
+            .iter()
+            // The parser chucks entities with no position data; don't bother.
+            .filter_map(|(id, e)| e.position_data.as_ref().map(|data| (*id, data, e)))
+            .collect::<Vec<_>>();
+        entities.sort_by_key(|(id, _, _)| *id);
+
+        for (id, data, entity) in entities {
+            streams.push(Box::new(
+                data.position_updates
+                    .iter()
+                    .map(move |update| RecordRef::Position(id, data, update)),
+            ));
+            streams.push(Box::new(
+                entity
+                    .events
+                    .iter()
+                    .map(move |event| RecordRef::Event(id, data.kind, event)),
+            ));
+        }
+
+        streams.push(Box::new(self.general_events.iter().map(RecordRef::General)));
+        streams.push(Box::new(
+            self.feature_events.iter().map(RecordRef::FeatureStatus),
+        ));
+
+        streams.into_iter().map(Iterator::peekable).collect()
+    }
+
+    fn write_callsigns<W: Write>(&self, time: f32, w: &mut W) -> Result<()> {
+        if self.callsigns.is_empty() {
+            return Ok(());
+        }
+
+        // Callsign data is a sparse array where indexing by ID
+        // gives you name and faction.
+        let negative_ids = self.callsigns.keys().filter(|id| **id < 0).count();
+        if negative_ids > 0 {
+            debug!(
+                "Dropping {} callsigns with negative IDs; they can't be written to a FLT",
+                negative_ids
+            );
+        }
+        let callsign_count = self
+            .callsigns
+            .keys()
+            .filter(|id| **id >= 0)
+            .max()
+            .map_or(0, |max| *max as usize + 1);
+        assert!(callsign_count <= MAX_CALLSIGN_ID as usize + 1);
+
+        let mut callsign_array = vec![CallsignRecord::default(); callsign_count];
+        for (id, callsign) in self.callsigns.iter().filter(|(id, _)| **id >= 0) {
+            callsign_array[*id as usize] = *callsign;
+        }
+
+        write_record_head(REC_TYPE_CALLSIGN_LIST, time, w)?;
+        write_i32(callsign_count as i32, w)?;
+        for callsign in &callsign_array {
+            callsign.write(w)?;
+        }
+        Ok(())
     }
 
+    /// Merges `next_flight` into this one if it picks up where this one left off,
+    /// returning true if it did. (See [`merge_reporting()`](Self::merge_reporting).)
     pub fn merge(
         &mut self,
         next_flight: &Flight,
         previous_flight_path: &Path,
         next_flight_path: &Path,
     ) -> bool {
+        self.merge_reporting(
+            next_flight,
+            previous_flight_path,
+            next_flight_path,
+            &MergePolicy::default(),
+        )
+        .decision
+            == MergeDecision::Merged
+    }
+
+    /// Merges `next_flight` into this one if `policy` says it picks up
+    /// where this one left off, returning what was decided
+    /// and how each entity and feature was merged.
+    pub fn merge_reporting(
+        &mut self,
+        next_flight: &Flight,
+        previous_flight_path: &Path,
+        next_flight_path: &Path,
+        policy: &MergePolicy,
+    ) -> MergeReport {
+        let mut report = MergeReport {
+            previous: previous_flight_path.to_owned(),
+            next: next_flight_path.to_owned(),
+            gap: next_flight.start_time - self.end_time,
+            previous_tod_offset: self.tod_offset,
+            next_tod_offset: next_flight.tod_offset,
+            decision: MergeDecision::Merged,
+            entities: Vec::new(),
+            features: Vec::new(),
+        };
+
         let previous_flight_path = previous_flight_path.display();
         let next_flight_path = next_flight_path.display();
+        let forced = policy.mode == MergeMode::Force;
+        let overlapping = policy.mode == MergeMode::Overlapping;
+        if overlapping {
+            report.gap = overlap_gap(self, next_flight);
+        }
 
         debug!(
             "Considering if {} and {} should be merged...",
             previous_flight_path, next_flight_path
         );
 
-        if self.corrupted {
+        if policy.mode == MergeMode::Never {
+            debug!("...no, merging is turned off");
+            report.decision = MergeDecision::Disabled;
+            return report;
+        }
+
+        // Recordings of the same mission line up however each ended,
+        // so only consecutive ones need to know where the previous one really ended.
+        if self.corrupted() && !overlapping {
             debug!("...no, {} is corrupted", previous_flight_path);
-            return false;
+            report.decision = MergeDecision::PreviousCorrupted;
+            return report;
         }
 
+        let rebased;
+        let mut next_flight = next_flight;
         if self.tod_offset != next_flight.tod_offset {
+            match policy.tod_mismatch {
+                TodMismatch::Refuse if !forced => {
+                    debug!(
+                        "...no, {} and {} are at different times of day",
+                        previous_flight_path, next_flight_path
+                    );
+                    report.decision = MergeDecision::DifferentTimeOfDay;
+                    return report;
+                }
+                TodMismatch::Refuse | TodMismatch::Warn => {
+                    warn!(
+                        "{} and {} are at different times of day ({}s vs. {}s)",
+                        previous_flight_path,
+                        next_flight_path,
+                        self.tod_offset,
+                        next_flight.tod_offset
+                    );
+                }
+                TodMismatch::Rebase => {
+                    // Line up the next flight's timeline with ours
+                    // so that the same time means the same time of day.
+                    let shift = next_flight.tod_offset - self.tod_offset;
+                    debug!(
+                        "{} and {} are at different times of day; shifting {} by {}s",
+                        previous_flight_path, next_flight_path, next_flight_path, shift
+                    );
+                    let mut shifted = next_flight.clone();
+                    shifted.shift_times(shift);
+                    rebased = shifted;
+                    next_flight = &rebased;
+                    report.gap = if overlapping {
+                        overlap_gap(self, next_flight)
+                    } else {
+                        next_flight.start_time - self.end_time
+                    };
+                }
+            }
+        }
+
+        if report.gap > policy.max_gap && !forced {
             debug!(
-                "...no, {} and {} are at different times of day",
-                previous_flight_path, next_flight_path
+                "...no, {} and {} are more than {}s apart",
+                previous_flight_path, next_flight_path, policy.max_gap
             );
+            report.decision = MergeDecision::TooFarApart;
+            return report;
         }
 
-        let dt = next_flight.start_time - self.end_time;
-        if dt > 1.0 {
+        // An unused ID in self that we can use for new entities in next_flight.
+        // (Callsigns can outlive entities the parser threw out, so skip past those too.)
+        let mut unique_id = self
+            .entities
+            .keys()
+            .chain(self.features.keys())
+            .chain(self.callsigns.keys())
+            .max()
+            .map_or(0, |max| max.saturating_add(1));
+
+        // IDs are 32 bits, and a garbage file could already be using the top of that.
+        let ids_needed = next_flight.entities.len() + next_flight.features.len();
+        if unique_id as i64 + ids_needed as i64 > i32::MAX as i64 {
             debug!(
-                "...no, {} and {} are more than a second apart",
+                "...no, {} doesn't have enough free IDs left for {}",
                 previous_flight_path, next_flight_path
             );
-            return false;
+            report.decision = MergeDecision::OutOfIds;
+            return report;
         }
 
         debug!("...yes!");
         info!("Merging {} into {}", next_flight_path, previous_flight_path);
         let start_time = Instant::now();
 
+        if overlapping {
+            self.merge_overlapping(next_flight, policy, &mut unique_id, &mut report);
+            crate::print_timing("Merge", &start_time);
+            return report;
+        }
+
         // If we're adding corrupted data to uncorrupted, propagate that.
-        self.corrupted |= next_flight.corrupted;
+        self.corruption = next_flight.corruption.clone();
         self.end_time = next_flight.end_time;
 
-        // An unused ID in self that we can use for new entities in next_flight
-        let mut unique_id = std::cmp::max(
-            *self.entities.keys().max().unwrap(),
-            *self.features.keys().max().unwrap(),
-        ) + 1;
-
-        self.merge_entities(next_flight, &mut unique_id);
-        self.merge_features(next_flight, &mut unique_id);
+        report.entities = self.merge_entities(next_flight, policy, &mut unique_id);
+        report.features = self.merge_features(next_flight, None, &mut unique_id);
 
         self.general_events
             .extend_from_slice(&next_flight.general_events);
 
         crate::print_timing("Merge", &start_time);
-        true
+        report
+    }
+
+    /// Adds `by` seconds to every time in the flight.
+    fn shift_times(&mut self, by: f32) {
+        self.start_time += by;
+        self.end_time += by;
+        for entity in self.entities.values_mut() {
+            if let Some(data) = &mut entity.position_data {
+                for update in &mut data.position_updates {
+                    update.time += by;
+                }
+            }
+            for event in &mut entity.events {
+                event.time += by;
+            }
+        }
+        for feature in self.features.values_mut() {
+            feature.time += by;
+        }
+        for event in &mut self.general_events {
+            event.start += by;
+            event.stop += by;
+        }
+        for event in &mut self.feature_events {
+            event.time += by;
+        }
     }
 
-    fn merge_entities(self: &mut Flight, next_flight: &Flight, unique_id: &mut i32) {
+    fn merge_entities(
+        self: &mut Flight,
+        next_flight: &Flight,
+        policy: &MergePolicy,
+        unique_id: &mut i32,
+    ) -> Vec<MergedId> {
         let starting_uid = *unique_id;
+        let mut merged_ids = Vec::with_capacity(next_flight.entities.len());
 
-        let mut used_previous_entities: FxHashSet<i32> =
-            FxHashSet::with_capacity_and_hasher(self.entities.len(), Default::default());
         let mut next_to_previous_ids: FxHashMap<i32, i32> =
             FxHashMap::with_capacity_and_hasher(next_flight.entities.len(), Default::default());
 
-        for (next_id, next_entity) in &next_flight.entities {
-            let mut closest_entity: Option<i32> = None;
-            let mut closest_distance = f32::INFINITY;
-
-            let next_data = next_entity.position_data.as_ref().unwrap();
-
-            for (previous_id, previous_entity) in &self.entities {
-                let previous_data = previous_entity.position_data.as_ref().unwrap();
-
-                // Entities don't (shouldn't!) change type from file to file.
-                // Skip over ones that don't match.
-                if next_data.kind != previous_data.kind {
-                    continue;
+        // Entities don't (shouldn't!) change type from file to file,
+        // so only entities of the same kind can match.
+        // Sort by ID so that hash map order doesn't change the results.
+        let mut candidates: BTreeMap<i32, MatchCandidates> = BTreeMap::new();
+
+        // Where each entity in self was headed when the flight ended...
+        for (id, entity) in &self.entities {
+            if let Some(data) = &entity.position_data {
+                if let Some(track) = Track::end(&data.position_updates) {
+                    let kind = candidates.entry(data.kind).or_default();
+                    kind.radius = policy.match_radius.for_flags(data.flags);
+                    kind.previous.push((*id, track));
                 }
-
-                // Don't consider entities we've already matched up.
-                //
-                // Along with trying to get some perf wins (are we? profile!),
-                // we want to make sure that entities right on top of each other
-                // (happens to ground units occasionally) get some 1 to 1 mapping.
-                if used_previous_entities.contains(previous_id) {
-                    continue;
+            }
+        }
+        // ...and where each in next_flight was headed when it started.
+        // The parser throws out entities without any positions,
+        // but flights made some other way might still have them.
+        // There's nothing to match them against, so they get new IDs below.
+        for (id, entity) in &next_flight.entities {
+            if let Some(data) = &entity.position_data {
+                if let Some(track) = Track::start(&data.position_updates) {
+                    let kind = candidates.entry(data.kind).or_default();
+                    kind.radius = policy.match_radius.for_flags(data.flags);
+                    kind.next.push((*id, track));
                 }
+            }
+        }
 
-                let next_position = next_data.position_updates.first().unwrap();
-                let previous_position = previous_data.position_updates.last().unwrap();
-
-                let distance = ((next_position.x - previous_position.x).powi(2)
-                    + (next_position.y - previous_position.y).powi(2)
-                    + (next_position.z - previous_position.z).powi(2))
-                .sqrt();
+        for MatchCandidates {
+            radius,
+            previous,
+            next,
+        } in candidates.values_mut()
+        {
+            if next.is_empty() {
+                continue;
+            }
+            previous.sort_unstable_by_key(|(id, _)| *id);
+            next.sort_unstable_by_key(|(id, _)| *id);
+
+            // Callsigns help tell entities apart (see below).
+            let previous_identities = previous
+                .iter()
+                .map(|(id, _)| Identity::of(self.callsigns.get(id)))
+                .collect::<Vec<_>>();
+            let next_identities = next
+                .iter()
+                .map(|(id, _)| Identity::of(next_flight.callsigns.get(id)))
+                .collect::<Vec<_>>();
+
+            // Greedily giving each entity the best match left over
+            // can steal a match from a better pairing, especially when lots
+            // of things are close together (ground convoys, formations, etc.).
+            // Find the pairing that's best overall instead.
+            //
+            // This also gives us a 1 to 1 mapping between entities
+            // right on top of each other (happens to ground units occasionally).
+            //
+            // Only score pairs close enough to possibly match;
+            // campaigns can have thousands of entities of the same kind.
+            let nearby = Grid::new(*radius, next.iter().map(|(_, track)| track.position));
+            let mut pairs = Vec::new();
+            for (column, (_, previous_track)) in previous.iter().enumerate() {
+                let (low, high) = previous_track.reach();
+                for row in nearby.near(low, high) {
+                    if let Some(mut score) = match_score(previous_track, &next[row].1, *radius) {
+                        // Callsigns can change between files, but usually don't.
+                        if let (Some(p), Some(n)) =
+                            (previous_identities[column], next_identities[row])
+                        {
+                            if p != n {
+                                score += CALLSIGN_MISMATCH_COST;
+                            }
+                        }
+                        pairs.push((row, column, score));
+                    }
+                }
+            }
 
-                if distance < closest_distance {
-                    closest_entity = Some(*previous_id);
-                    closest_distance = distance;
+            // A player's jet labeled the same in both flights is the same jet,
+            // even if it respawned or jumped further than we'd otherwise believe.
+            // (Unless other things have the same callsign - then we can't tell.)
+            let previous_labeled = unique_identities(&previous_identities);
+            let next_labeled = unique_identities(&next_identities);
+            for (row, identity) in next_identities.iter().enumerate() {
+                let identity = match identity {
+                    Some(i) => i,
+                    None => continue,
+                };
+                if next_labeled.get(identity) != Some(&Some(row)) {
+                    continue;
                 }
-                if distance == 0.0 {
-                    // We're not gonna do any better.
-                    break;
+                if let Some(Some(column)) = previous_labeled.get(identity) {
+                    // Pairs in range were already scored above.
+                    if match_score(&previous[*column].1, &next[row].1, *radius).is_none() {
+                        pairs.push((row, *column, CALLSIGN_JUMP_COST));
+                    }
                 }
             }
 
-            if closest_distance < 5280.0 {
-                let closest_id = closest_entity.unwrap();
+            let matches = assignment::assign(next.len(), previous.len(), &pairs, NEW_ENTITY_COST);
+            for (row, matched) in matches.into_iter().enumerate() {
+                let previous_index = match matched {
+                    Some(m) => m,
+                    None => continue,
+                };
+                // We found an entity of the same kind in self
+                // that could have gotten to where this one starts.
+                let (next_id, next_track) = &next[row];
+                let (previous_id, previous_track) = &previous[previous_index];
+                next_to_previous_ids.insert(*next_id, *previous_id);
+
+                let callsign_changed = merge_callsign(
+                    &mut self.callsigns,
+                    *previous_id,
+                    previous_identities[previous_index],
+                    &next_flight.callsigns,
+                    *next_id,
+                    next_identities[row],
+                );
 
-                // We found an entity of the same kind nearby (< 1 mi) in self.
-                assert!(used_previous_entities.insert(closest_id));
-                assert!(next_to_previous_ids.insert(*next_id, closest_id).is_none());
-            } else {
-                // We couldn't find anything close in self.
-                // Create a brand new entity there.
-                assert!(next_to_previous_ids.insert(*next_id, *unique_id).is_none());
+                merged_ids.push(MergedId {
+                    next_id: *next_id,
+                    outcome: MergeOutcome::Matched {
+                        id: *previous_id,
+                        distance: miss(previous_track, next_track),
+                        callsign_changed,
+                    },
+                });
+            }
+        }
 
-                // Add a callsign record.
-                if let Some(callsign) = next_flight.callsigns.get(next_id) {
-                    assert!(self.callsigns.insert(*unique_id, *callsign).is_none());
-                }
+        let mut next_ids = next_flight.entities.keys().copied().collect::<Vec<_>>();
+        next_ids.sort_unstable();
+        for next_id in next_ids {
+            if next_to_previous_ids.contains_key(&next_id) {
+                continue;
+            }
+            // We couldn't find anything close in self.
+            // Create a brand new entity there.
+            next_to_previous_ids.insert(next_id, *unique_id);
+            merged_ids.push(MergedId {
+                next_id,
+                outcome: MergeOutcome::New { id: *unique_id },
+            });
 
-                *unique_id += 1;
+            // Add a callsign record.
+            if let Some(callsign) = next_flight.callsigns.get(&next_id) {
+                self.callsigns.insert(*unique_id, *callsign);
             }
+
+            *unique_id += 1;
         }
 
         // Now that we know how all entities in next_flight map to self,
         // fix up their radar targets and copy them over.
-        assert_eq!(next_to_previous_ids.len(), next_flight.entities.len());
         for (next_id, next_entity) in &next_flight.entities {
             let previous_id = next_to_previous_ids[next_id];
 
-            let from = next_entity.position_data.as_ref().unwrap();
-
-            let to = &mut self
-                .entities
-                .entry(previous_id)
-                .or_insert(EntityData {
-                    position_data: Some(EntityPositionData {
-                        position_updates: Vec::with_capacity(from.position_updates.len()),
-                        ..*from
-                    }),
-                    events: next_entity.events.clone(),
-                })
+            let from = match &next_entity.position_data {
+                Some(data) => data,
+                None => continue,
+            };
+
+            let to = self.entities.entry(previous_id).or_insert(EntityData {
+                position_data: None,
+                events: next_entity.events.clone(),
+            });
+            let to = &mut to
                 .position_data
-                .as_mut()
-                .unwrap()
+                .get_or_insert_with(|| EntityPositionData {
+                    position_updates: Vec::with_capacity(from.position_updates.len()),
+                    ..*from
+                })
                 .position_updates;
 
             let from = &from.position_updates;
             to.reserve(from.len());
 
+            // Entities we matched already have updates from self.
+            // Fill in the seam between those and next_flight's if asked to.
+            if let (Some(step), Some(last), Some(first)) =
+                (policy.seam_step, to.last().copied(), from.first())
+            {
+                smooth_seam(&last, first, step, to);
+            }
+
             for position in from {
                 let radar_target = *next_to_previous_ids
                     .get(&position.radar_target)
@@ -273,79 +966,372 @@ impl Flight {
             new_entities,
             next_flight.entities.len() - new_entities
         );
+
+        merged_ids.sort_unstable_by_key(|m| m.next_id);
+        merged_ids
+    }
+
+    /// Merges `next_flight`, which another player recorded during the same mission,
+    /// into this one. (See [`MergeMode::Overlapping`].)
+    fn merge_overlapping(
+        &mut self,
+        next_flight: &Flight,
+        policy: &MergePolicy,
+        unique_id: &mut i32,
+        report: &mut MergeReport,
+    ) {
+        let recorded = (self.start_time, self.end_time);
+        let next_recorded = (next_flight.start_time, next_flight.end_time);
+
+        if self.corruption.is_none() {
+            self.corruption = next_flight.corruption.clone();
+        }
+        self.start_time = self.start_time.min(next_flight.start_time);
+        self.end_time = self.end_time.max(next_flight.end_time);
+
+        report.entities = self.merge_overlapping_entities(
+            next_flight,
+            policy,
+            recorded,
+            next_recorded,
+            unique_id,
+        );
+
+        // Everyone sees the same buildings blow up and the same tracers fly,
+        // so only take those from next_flight when we weren't recording.
+        report.features = self.merge_features(next_flight, Some(recorded), unique_id);
+        self.general_events.extend(
+            next_flight
+                .general_events
+                .iter()
+                .filter(|e| !during(recorded, e.start)),
+        );
+
+        // next_flight might have started first.
+        self.general_events
+            .sort_by(|a, b| a.start.total_cmp(&b.start));
+        self.feature_events
+            .sort_by(|a, b| a.time.total_cmp(&b.time));
     }
 
-    fn merge_features(self: &mut Flight, next_flight: &Flight, unique_id: &mut i32) {
+    /// Like [`merge_entities()`](Self::merge_entities), but matches entities
+    /// by where they were while both flights were recording.
+    fn merge_overlapping_entities(
+        &mut self,
+        next_flight: &Flight,
+        policy: &MergePolicy,
+        recorded: (f32, f32),
+        next_recorded: (f32, f32),
+        unique_id: &mut i32,
+    ) -> Vec<MergedId> {
         let starting_uid = *unique_id;
+        let mut merged_ids = Vec::with_capacity(next_flight.entities.len());
 
         let mut next_to_previous_ids: FxHashMap<i32, i32> =
-            FxHashMap::with_capacity_and_hasher(next_flight.features.len(), Default::default());
+            FxHashMap::with_capacity_and_hasher(next_flight.entities.len(), Default::default());
 
-        for (next_id, next_feature) in &next_flight.features {
-            let mut matching_previous = None;
-
-            for (previous_id, previous_feature) in &self.features {
-                if next_feature.kind != previous_feature.kind
-                    || next_feature.slot != previous_feature.slot
-                    || next_feature.special_flags != previous_feature.special_flags
-                    || next_feature.x != previous_feature.x
-                    || next_feature.y != previous_feature.y
-                    || next_feature.z != previous_feature.z
-                    || next_feature.pitch != previous_feature.pitch
-                    || next_feature.roll != previous_feature.roll
-                    || next_feature.yaw != previous_feature.yaw
-                {
-                    continue;
+        let mut candidates: BTreeMap<i32, OverlapCandidates> = BTreeMap::new();
+        for (id, entity) in &self.entities {
+            if let Some(data) = &entity.position_data {
+                if !data.position_updates.is_empty() {
+                    let kind = candidates.entry(data.kind).or_default();
+                    kind.radius = policy.match_radius.for_flags(data.flags);
+                    kind.previous.push((*id, &data.position_updates));
                 }
-
-                // Everything but the time and lead IDs
-                // (which can change between files) matches.
-                // It's probably the same thing.
-                // (Unlike entities, we don't really care about a 1 to 1 mapping
-                // since features don't move.)
-                matching_previous = Some(*previous_id);
-                break;
             }
-            if let Some(previous_id) = matching_previous {
-                // If the feature already existed in self,
-                // no need to do anything to it.
-                next_to_previous_ids.insert(*next_id, previous_id);
-            } else {
-                // If the feature is new to next_flight,
-                // create a new ID for it
-                next_to_previous_ids.insert(*next_id, *unique_id);
-
-                // Add a callsign record.
-                if let Some(callsign) = next_flight.callsigns.get(next_id) {
-                    assert!(self.callsigns.insert(*unique_id, *callsign).is_none());
+        }
+        for (id, entity) in &next_flight.entities {
+            if let Some(data) = &entity.position_data {
+                if !data.position_updates.is_empty() {
+                    let kind = candidates.entry(data.kind).or_default();
+                    kind.radius = policy.match_radius.for_flags(data.flags);
+                    kind.next.push((*id, &data.position_updates));
                 }
-                *unique_id += 1;
             }
         }
 
-        // Now that we know how all features in next_flight map to self,
-        // fix up their parent IDs and copy them over.
-        assert_eq!(next_to_previous_ids.len(), next_flight.features.len());
-        for (next_id, next_feature) in &next_flight.features {
-            let previous_id = next_to_previous_ids[next_id];
-            if previous_id < starting_uid {
-                continue; // It's already in self.
+        for OverlapCandidates {
+            radius,
+            previous,
+            next,
+        } in candidates.values_mut()
+        {
+            if next.is_empty() {
+                continue;
+            }
+            previous.sort_unstable_by_key(|(id, _)| *id);
+            next.sort_unstable_by_key(|(id, _)| *id);
+
+            let previous_identities = previous
+                .iter()
+                .map(|(id, _)| Identity::of(self.callsigns.get(id)))
+                .collect::<Vec<_>>();
+            let next_identities = next
+                .iter()
+                .map(|(id, _)| Identity::of(next_flight.callsigns.get(id)))
+                .collect::<Vec<_>>();
+
+            // While both flights were recording, the same entity should be
+            // in the same place at the same time in both.
+            // Look for entities in self near where each one in next_flight
+            // was when it first showed up while we were recording,
+            // then see how closely they followed each other.
+            //
+            // Round those times to the second so entities showing up together
+            // can share a grid of where everything in self was.
+            let mut probes: BTreeMap<i32, Vec<usize>> = BTreeMap::new();
+            for (row, (_, updates)) in next.iter().enumerate() {
+                if let Some(first) = updates.iter().find(|u| during(recorded, u.time)) {
+                    probes
+                        .entry(first.time.round() as i32)
+                        .or_default()
+                        .push(row);
+                }
             }
 
-            let to_copy = FeatureData {
-                lead_uid: next_to_previous_ids[next_id],
-                ..*next_feature
-            };
-            assert!(self.features.insert(previous_id, to_copy).is_none());
-        }
-
+            let mut pairs = Vec::new();
+            for (second, rows) in probes {
+                let time = second as f32;
+                let around = previous
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(column, (_, updates))| {
+                        position_at(updates, time).map(|position| (column, position))
+                    })
+                    .collect::<Vec<_>>();
+                let nearby = Grid::new(*radius, around.iter().map(|(_, position)| *position));
+
+                for row in rows {
+                    let position = match position_at(next[row].1, time) {
+                        Some(p) => p,
+                        None => continue,
+                    };
+                    for i in nearby.near(position, position) {
+                        let column = around[i].0;
+                        if let Some(mut score) =
+                            trajectory_score(previous[column].1, next[row].1, *radius)
+                        {
+                            if let (Some(p), Some(n)) =
+                                (previous_identities[column], next_identities[row])
+                            {
+                                if p != n {
+                                    score += CALLSIGN_MISMATCH_COST;
+                                }
+                            }
+                            pairs.push((row, column, score));
+                        }
+                    }
+                }
+            }
+
+            // Two players' recordings of the same jet with the same callsign
+            // are the same jet, even if they disagree about where it was.
+            let previous_labeled = unique_identities(&previous_identities);
+            let next_labeled = unique_identities(&next_identities);
+            for (row, identity) in next_identities.iter().enumerate() {
+                let identity = match identity {
+                    Some(i) => i,
+                    None => continue,
+                };
+                if next_labeled.get(identity) != Some(&Some(row)) {
+                    continue;
+                }
+                if let Some(Some(column)) = previous_labeled.get(identity) {
+                    if trajectory_score(previous[*column].1, next[row].1, *radius).is_none() {
+                        pairs.push((row, *column, CALLSIGN_JUMP_COST));
+                    }
+                }
+            }
+
+            let matches = assignment::assign(next.len(), previous.len(), &pairs, NEW_ENTITY_COST);
+            for (row, matched) in matches.into_iter().enumerate() {
+                let previous_index = match matched {
+                    Some(m) => m,
+                    None => continue,
+                };
+                let (next_id, next_updates) = next[row];
+                let (previous_id, previous_updates) = previous[previous_index];
+                next_to_previous_ids.insert(next_id, previous_id);
+
+                let callsign_changed = merge_callsign(
+                    &mut self.callsigns,
+                    previous_id,
+                    previous_identities[previous_index],
+                    &next_flight.callsigns,
+                    next_id,
+                    next_identities[row],
+                );
+
+                // They might not have been around at the same time
+                // if we matched them by callsign.
+                let distance = trajectory_distance(previous_updates, next_updates)
+                    .or_else(|| {
+                        Some(miss(
+                            &Track::end(previous_updates)?,
+                            &Track::start(next_updates)?,
+                        ))
+                    })
+                    .unwrap_or_default();
+
+                merged_ids.push(MergedId {
+                    next_id,
+                    outcome: MergeOutcome::Matched {
+                        id: previous_id,
+                        distance,
+                        callsign_changed,
+                    },
+                });
+            }
+        }
+
+        let mut next_ids = next_flight.entities.keys().copied().collect::<Vec<_>>();
+        next_ids.sort_unstable();
+        for next_id in next_ids {
+            if next_to_previous_ids.contains_key(&next_id) {
+                continue;
+            }
+            next_to_previous_ids.insert(next_id, *unique_id);
+            merged_ids.push(MergedId {
+                next_id,
+                outcome: MergeOutcome::New { id: *unique_id },
+            });
+            if let Some(callsign) = next_flight.callsigns.get(&next_id) {
+                self.callsigns.insert(*unique_id, *callsign);
+            }
+            *unique_id += 1;
+        }
+
+        for (next_id, next_entity) in &next_flight.entities {
+            let previous_id = next_to_previous_ids[next_id];
+
+            let mut from = match &next_entity.position_data {
+                Some(data) => data.clone(),
+                None => continue,
+            };
+            for position in &mut from.position_updates {
+                position.radar_target = *next_to_previous_ids
+                    .get(&position.radar_target)
+                    .unwrap_or(&-1);
+            }
+            let from = EntityData {
+                position_data: Some(from),
+                events: next_entity.events.clone(),
+            };
+
+            match self.entities.get_mut(&previous_id) {
+                Some(to) => combine_entity(to, recorded, from, next_recorded),
+                None => {
+                    self.entities.insert(previous_id, from);
+                }
+            }
+        }
+
+        let new_entities = (*unique_id - starting_uid) as usize;
+        debug!(
+            "{} new entities, {} merged",
+            new_entities,
+            next_flight.entities.len() - new_entities
+        );
+
+        merged_ids.sort_unstable_by_key(|m| m.next_id);
+        merged_ids
+    }
+
+    /// Skips feature events in `next_flight` during `recorded` (see [`during()`]).
+    fn merge_features(
+        self: &mut Flight,
+        next_flight: &Flight,
+        recorded: Option<(f32, f32)>,
+        unique_id: &mut i32,
+    ) -> Vec<MergedId> {
+        let starting_uid = *unique_id;
+        let mut merged_ids = Vec::with_capacity(next_flight.features.len());
+
+        let mut next_to_previous_ids: FxHashMap<i32, i32> =
+            FxHashMap::with_capacity_and_hasher(next_flight.features.len(), Default::default());
+
+        // Features don't move, so one in next_flight is probably the same as
+        // one in self if everything but the time and lead IDs
+        // (which can change between files) matches.
+        // (Unlike entities, we don't really care about a 1 to 1 mapping.)
+        let mut previous_features: FxHashMap<FeatureKey, i32> =
+            FxHashMap::with_capacity_and_hasher(self.features.len(), Default::default());
+        for (previous_id, previous_feature) in &self.features {
+            if let Some(key) = FeatureKey::of(previous_feature) {
+                // Prefer the lowest ID so that hash map order doesn't change the results.
+                previous_features
+                    .entry(key)
+                    .and_modify(|id| *id = (*id).min(*previous_id))
+                    .or_insert(*previous_id);
+            }
+        }
+
+        for (next_id, next_feature) in &next_flight.features {
+            let matching_previous =
+                FeatureKey::of(next_feature).and_then(|key| previous_features.get(&key).copied());
+            if let Some(previous_id) = matching_previous {
+                // If the feature already existed in self,
+                // no need to do anything to it.
+                next_to_previous_ids.insert(*next_id, previous_id);
+                merged_ids.push(MergedId {
+                    next_id: *next_id,
+                    outcome: MergeOutcome::Matched {
+                        id: previous_id,
+                        distance: 0.0,
+                        callsign_changed: false,
+                    },
+                });
+            } else {
+                // If the feature is new to next_flight,
+                // create a new ID for it
+                next_to_previous_ids.insert(*next_id, *unique_id);
+                merged_ids.push(MergedId {
+                    next_id: *next_id,
+                    outcome: MergeOutcome::New { id: *unique_id },
+                });
+
+                // Add a callsign record.
+                if let Some(callsign) = next_flight.callsigns.get(next_id) {
+                    self.callsigns.insert(*unique_id, *callsign);
+                }
+                *unique_id += 1;
+            }
+        }
+
+        // Now that we know how all features in next_flight map to self,
+        // fix up their parent IDs and copy them over.
+        for (next_id, next_feature) in &next_flight.features {
+            let previous_id = next_to_previous_ids[next_id];
+            if previous_id < starting_uid {
+                continue; // It's already in self.
+            }
+
+            let to_copy = FeatureData {
+                lead_uid: next_to_previous_ids[next_id],
+                ..*next_feature
+            };
+            self.features.insert(previous_id, to_copy);
+        }
+
         // While we have next_to_previous_ids,
         // let's copy over all the feature events, fixing up their IDs.
         self.feature_events
             .reserve(next_flight.feature_events.len());
         for feature_event in &next_flight.feature_events {
+            if recorded.is_some_and(|r| during(r, feature_event.time)) {
+                continue;
+            }
+            // The parser drops events for features it hasn't seen,
+            // but don't choke on flights made some other way.
+            let feature_uid = match next_to_previous_ids.get(&feature_event.feature_uid) {
+                Some(uid) => *uid,
+                None => {
+                    trace!("No feature for {:?}", feature_event);
+                    continue;
+                }
+            };
             self.feature_events.push(FeatureEvent {
-                feature_uid: next_to_previous_ids[&feature_event.feature_uid],
+                feature_uid,
                 ..*feature_event
             });
         }
@@ -356,440 +1342,2016 @@ impl Flight {
             new_features,
             next_flight.features.len() - new_features
         );
+
+        merged_ids.sort_unstable_by_key(|m| m.next_id);
+        merged_ids
+    }
+}
+
+/// When and how to merge flights (see [`Flight::merge_reporting()`])
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergePolicy {
+    pub mode: MergeMode,
+    /// How far apart (in seconds) one flight's end and the next's start
+    /// can be for them to be merged
+    pub max_gap: f32,
+    pub match_radius: MatchRadius,
+    pub tod_mismatch: TodMismatch,
+    /// If set, fill in the gap between where each matched entity left off
+    /// in the previous flight and where it picks up in the next
+    /// with updates this many seconds apart, so it doesn't jump between them.
+    pub seam_step: Option<f32>,
+}
+
+impl Default for MergePolicy {
+    fn default() -> Self {
+        Self {
+            mode: MergeMode::Auto,
+            max_gap: 1.0,
+            match_radius: MatchRadius::default(),
+            tod_mismatch: TodMismatch::Warn,
+            seam_step: None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MergeMode {
+    /// Merge flights that pick up where the last left off
+    Auto,
+    /// Don't merge anything.
+    Never,
+    /// Merge flights no matter how far apart they are in time
+    /// or whether their times of day match.
+    /// (We still can't merge into corrupted flights.)
+    Force,
+    /// Merge flights different players recorded of the same mission
+    /// at the same time, so that what they both saw only shows up once.
+    /// Flights still need to overlap (or be within [`MergePolicy::max_gap`])
+    /// to be merged.
+    Overlapping,
+}
+
+/// How far (in feet) an entity in the next flight can start from where we expect
+/// an entity in the previous one to be and still be considered the same thing,
+/// by what kind of entity it is
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MatchRadius {
+    pub aircraft: f32,
+    pub missile: f32,
+    /// Chaff and flares
+    pub countermeasure: f32,
+    /// Ground vehicles, ships, and everything else
+    pub ground: f32,
+}
+
+impl Default for MatchRadius {
+    fn default() -> Self {
+        // A mile
+        let radius = 5280.0;
+        Self {
+            aircraft: radius,
+            missile: radius,
+            countermeasure: radius,
+            ground: radius,
+        }
+    }
+}
+
+impl MatchRadius {
+    /// The radius for entities with the given [`EntityPositionData::flags`]
+    pub fn for_flags(&self, flags: u32) -> f32 {
+        if flags & ENTITY_FLAG_AIRCRAFT != 0 {
+            self.aircraft
+        } else if flags & ENTITY_FLAG_MISSILE != 0 {
+            self.missile
+        } else if flags & (ENTITY_FLAG_CHAFF | ENTITY_FLAG_FLARE) != 0 {
+            self.countermeasure
+        } else {
+            self.ground
+        }
+    }
+}
+
+/// What to do with flights with different time of day offsets
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TodMismatch {
+    /// Don't merge them.
+    Refuse,
+    /// Merge them anyways, but warn about it.
+    Warn,
+    /// Shift the next flight's times so that its times of day
+    /// line up with the previous flight's, then see if they should be merged.
+    Rebase,
+}
+
+impl std::str::FromStr for TodMismatch {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "refuse" => Ok(Self::Refuse),
+            "warn" => Ok(Self::Warn),
+            "rebase" => Ok(Self::Rebase),
+            _ => bail!("Expected refuse, warn, or rebase, not {}", s),
+        }
+    }
+}
+
+/// What happened when merging one flight into another
+/// (see [`Flight::merge_reporting()`])
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeReport {
+    pub previous: PathBuf,
+    pub next: PathBuf,
+    /// Seconds between the end of the previous flight and the start of the next
+    /// (after lining up their times of day, if [`TodMismatch::Rebase`] did that).
+    /// For [`MergeMode::Overlapping`], negative how long they overlap.
+    pub gap: f32,
+    pub previous_tod_offset: f32,
+    pub next_tod_offset: f32,
+    pub decision: MergeDecision,
+    /// How each entity in the next flight was merged, by ID.
+    /// Empty if the flights weren't merged.
+    pub entities: Vec<MergedId>,
+    /// Ditto for features
+    pub features: Vec<MergedId>,
+}
+
+/// Whether we merged two flights, and if not, why
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeDecision {
+    Merged,
+    /// The policy said not to merge anything.
+    Disabled,
+    /// We don't know where the corrupted flight really ended.
+    PreviousCorrupted,
+    /// The flights have different time of day offsets.
+    DifferentTimeOfDay,
+    /// The next flight starts too long after the previous one ends.
+    TooFarApart,
+    /// There aren't enough unused IDs left in the previous flight
+    /// for everything in the next one.
+    OutOfIds,
+}
+
+/// What an entity or feature in the next flight became in the merged one
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct MergedId {
+    /// Its ID in the next flight
+    pub next_id: i32,
+    #[serde(flatten)]
+    pub outcome: MergeOutcome,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum MergeOutcome {
+    /// It continues the previous flight's `id`, starting `distance` feet
+    /// from where we expected it to be. (Features must match exactly.)
+    /// For [`MergeMode::Overlapping`], `distance` is how far apart
+    /// they were on average while both flights were recording.
+    /// The merged flight keeps the previous flight's callsign
+    /// if it changed between them.
+    Matched {
+        id: i32,
+        distance: f32,
+        callsign_changed: bool,
+    },
+    /// It's something new, given `id`.
+    New { id: i32 },
+}
+
+/// How many of an entity's position updates we look at to see where it's going
+const TRACK_UPDATES: usize = 4;
+
+/// Updates further apart than this (in seconds) say little about
+/// where an entity is going _now_.
+const TRACK_WINDOW: f32 = 10.0;
+
+/// How far (in seconds) past its last update we'll guess where an entity went.
+/// BMS stops recording things once they stop moving,
+/// so one that's been quiet for a while is probably parked.
+const MAX_EXTRAPOLATION: f32 = 5.0;
+
+/// How much heading and speed matter when matching entities between flights,
+/// relative to how close they are (see [`match_score()`])
+const HEADING_WEIGHT: f32 = 0.5;
+const SPEED_WEIGHT: f32 = 0.5;
+
+/// What it costs (on top of their [`match_score()`]) to match entities
+/// with different callsigns. They can change between files, but usually don't.
+const CALLSIGN_MISMATCH_COST: f32 = 1.0;
+
+/// What it costs to match entities too far apart to match otherwise,
+/// but with the same callsign (one nothing else of their kind has).
+/// Less than [`CALLSIGN_MISMATCH_COST`] so that a different callsign
+/// right where we expected the entity doesn't beat the same one elsewhere.
+const CALLSIGN_JUMP_COST: f32 = 0.5;
+
+/// What it costs (in [`match_score()`] terms) to call an entity in the next flight
+/// something new instead of matching it to one in the previous flight.
+/// Every match within the match radius scores less than this,
+/// so we match as many entities as we can, as well as we can.
+const NEW_ENTITY_COST: f32 = 1.0 + HEADING_WEIGHT + SPEED_WEIGHT;
+
+/// Where an entity was and where it was headed at one end of its timeline,
+/// used to match entities between flights when merging them.
+#[derive(Debug, Copy, Clone)]
+struct Track {
+    time: f32,
+    position: [f32; 3],
+    /// In feet per second, if we have enough updates to tell.
+    velocity: Option<[f32; 3]>,
+    yaw: f32,
+}
+
+impl Track {
+    /// Where an entity was at its last position update, and where it was going
+    fn end(updates: &[EntityPositionUpdate]) -> Option<Self> {
+        let last = updates.last()?;
+        let earliest = updates
+            .iter()
+            .rev()
+            .take(TRACK_UPDATES)
+            .take_while(|u| last.time - u.time <= TRACK_WINDOW)
+            .last()
+            .unwrap_or(last);
+        Some(Self::new(last, earliest, last))
+    }
+
+    /// Where an entity was at its first position update, and where it was going
+    fn start(updates: &[EntityPositionUpdate]) -> Option<Self> {
+        let first = updates.first()?;
+        let latest = updates
+            .iter()
+            .take(TRACK_UPDATES)
+            .take_while(|u| u.time - first.time <= TRACK_WINDOW)
+            .last()
+            .unwrap_or(first);
+        Some(Self::new(first, first, latest))
+    }
+
+    /// The track at `at`, with the velocity it took to get from `from` to `to`
+    fn new(
+        at: &EntityPositionUpdate,
+        from: &EntityPositionUpdate,
+        to: &EntityPositionUpdate,
+    ) -> Self {
+        let dt = to.time - from.time;
+        let velocity = if dt > 0.0 {
+            Some([
+                (to.x - from.x) / dt,
+                (to.y - from.y) / dt,
+                (to.z - from.z) / dt,
+            ])
+        } else {
+            None
+        };
+        Self {
+            time: at.time,
+            position: [at.x, at.y, at.z],
+            velocity,
+            yaw: at.yaw,
+        }
+    }
+
+    /// Where we'd expect the entity to be at the given time
+    fn predict(&self, time: f32) -> [f32; 3] {
+        let dt = (time - self.time).clamp(0.0, MAX_EXTRAPOLATION);
+        match self.velocity {
+            Some(v) => [
+                self.position[0] + v[0] * dt,
+                self.position[1] + v[1] * dt,
+                self.position[2] + v[2] * dt,
+            ],
+            None => self.position,
+        }
+    }
+
+    /// Corners of a box around everywhere [`predict()`](Self::predict)
+    /// could put the entity
+    fn reach(&self) -> ([f32; 3], [f32; 3]) {
+        let furthest = self.predict(self.time + MAX_EXTRAPOLATION);
+        let mut low = self.position;
+        let mut high = self.position;
+        for i in 0..3 {
+            low[i] = low[i].min(furthest[i]);
+            high[i] = high[i].max(furthest[i]);
+        }
+        (low, high)
+    }
+
+    fn speed(&self) -> Option<f32> {
+        self.velocity
+            .map(|v| (v[0].powi(2) + v[1].powi(2) + v[2].powi(2)).sqrt())
+    }
+}
+
+/// Everything about a feature but its time and lead ID,
+/// for finding the same feature in another flight
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct FeatureKey {
+    kind: i32,
+    slot: i32,
+    special_flags: u32,
+    /// Bits of the position and orientation
+    placement: [u32; 6],
+}
+
+impl FeatureKey {
+    /// Returns `None` for features with NaNs, which don't match anything.
+    fn of(feature: &FeatureData) -> Option<Self> {
+        let mut placement = [0; 6];
+        let values = [
+            feature.x,
+            feature.y,
+            feature.z,
+            feature.pitch,
+            feature.roll,
+            feature.yaw,
+        ];
+        for (bits, value) in placement.iter_mut().zip(&values) {
+            if value.is_nan() {
+                return None;
+            }
+            // Adding zero turns -0 into 0, so they match like they would with ==.
+            *bits = (value + 0.0).to_bits();
+        }
+        Some(Self {
+            kind: feature.kind,
+            slot: feature.slot,
+            special_flags: feature.special_flags,
+            placement,
+        })
+    }
+}
+
+/// Buckets points into cubes `radius` on a side
+/// so we can find the ones near somewhere without checking every single one.
+struct Grid {
+    radius: f32,
+    cells: FxHashMap<[i32; 3], Vec<usize>>,
+}
+
+impl Grid {
+    /// Indexes the given points by their position in the iterator
+    fn new<I: Iterator<Item = [f32; 3]>>(radius: f32, points: I) -> Self {
+        let mut grid = Self {
+            radius,
+            cells: FxHashMap::default(),
+        };
+        for (i, point) in points.enumerate() {
+            grid.cells.entry(grid.cell(point)).or_default().push(i);
+        }
+        grid
+    }
+
+    fn cell(&self, point: [f32; 3]) -> [i32; 3] {
+        // Saturates for garbage (huge, infinite, NaN) coordinates,
+        // which won't match anything anyways.
+        let c = |v: f32| (v / self.radius).floor() as i32;
+        [c(point[0]), c(point[1]), c(point[2])]
+    }
+
+    /// Points within `radius` of the box from `low` to `high`
+    /// (and probably a few more)
+    fn near(&self, low: [f32; 3], high: [f32; 3]) -> Vec<usize> {
+        let r = self.radius;
+        let low = self.cell([low[0] - r, low[1] - r, low[2] - r]);
+        let high = self.cell([high[0] + r, high[1] + r, high[2] + r]);
+        let span = |i: usize| (i64::from(high[i]) - i64::from(low[i]) + 1).max(0);
+        let volume = span(0).saturating_mul(span(1)).saturating_mul(span(2));
+
+        // If the box covers more cells than we have, just check everything.
+        if volume > self.cells.len() as i64 {
+            return self.cells.values().flatten().copied().collect();
+        }
+
+        let mut found = Vec::new();
+        for x in low[0]..=high[0] {
+            for y in low[1]..=high[1] {
+                for z in low[2]..=high[2] {
+                    if let Some(points) = self.cells.get(&[x, y, z]) {
+                        found.extend_from_slice(points);
+                    }
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Who an entity is, going by its callsign
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct Identity {
+    /// The label, with anything after its first NUL zeroed out
+    label: [u8; 16],
+    team_color: i32,
+}
+
+impl Identity {
+    /// Returns `None` for missing and blank callsigns.
+    fn of(callsign: Option<&CallsignRecord>) -> Option<Self> {
+        let callsign = callsign?;
+        let length = callsign
+            .label
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(callsign.label.len());
+        if length == 0 {
+            return None;
+        }
+        let mut label = [0; 16];
+        label[..length].copy_from_slice(&callsign.label[..length]);
+        Some(Self {
+            label,
+            team_color: callsign.team_color,
+        })
+    }
+}
+
+/// Maps each identity to its index in the given list,
+/// or to `None` if more than one thing has it.
+fn unique_identities(identities: &[Option<Identity>]) -> FxHashMap<Identity, Option<usize>> {
+    let mut unique: FxHashMap<Identity, Option<usize>> = FxHashMap::default();
+    for (i, identity) in identities.iter().enumerate() {
+        if let Some(identity) = identity {
+            unique
+                .entry(*identity)
+                .and_modify(|seen| *seen = None)
+                .or_insert(Some(i));
+        }
+    }
+    unique
+}
+
+/// Keeps the callsign entity `previous_id` had, or picks up the one
+/// `next_id` has in `next_callsigns` if it didn't have one.
+/// Returns true if they had different callsigns.
+fn merge_callsign(
+    callsigns: &mut FxHashMap<i32, CallsignRecord>,
+    previous_id: i32,
+    previous_identity: Option<Identity>,
+    next_callsigns: &FxHashMap<i32, CallsignRecord>,
+    next_id: i32,
+    next_identity: Option<Identity>,
+) -> bool {
+    match (previous_identity, next_identity) {
+        (None, Some(_)) => {
+            callsigns.insert(previous_id, next_callsigns[&next_id]);
+            false
+        }
+        (Some(p), Some(n)) if p != n => {
+            debug!(
+                "Entity {} changed callsigns from {} to {}",
+                previous_id,
+                callsigns[&previous_id].label_lossy(),
+                next_callsigns[&next_id].label_lossy()
+            );
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Entities (by ID) of one kind that could be matched up when merging flights
+#[derive(Debug, Default)]
+struct MatchCandidates {
+    /// How far (in feet) apart they can be and still match,
+    /// from [`MatchRadius::for_flags()`]
+    radius: f32,
+    /// How entities in the previous flight ended
+    previous: Vec<(i32, Track)>,
+    /// How entities in the next flight started
+    next: Vec<(i32, Track)>,
+}
+
+/// Seams longer than this (in seconds) aren't smoothed
+/// (see [`MergePolicy::seam_step`]); who knows what happened in between.
+const MAX_SMOOTHED_SEAM: f32 = 60.0;
+
+/// Adds updates every `step` seconds between `last` and `first` to `into`,
+/// moving in a straight line and turning the shortest way around.
+fn smooth_seam(
+    last: &EntityPositionUpdate,
+    first: &EntityPositionUpdate,
+    step: f32,
+    into: &mut Vec<EntityPositionUpdate>,
+) {
+    let gap = first.time - last.time;
+    if !(step > 0.0 && gap > step && gap <= MAX_SMOOTHED_SEAM) {
+        return;
+    }
+
+    let lerp = |from: f32, to: f32, t: f32| from + (to - from) * t;
+    let turn = |from: f32, to: f32, t: f32| {
+        use std::f32::consts::{PI, TAU};
+        let shortest = (to - from + PI).rem_euclid(TAU) - PI;
+        (from + shortest * t + PI).rem_euclid(TAU) - PI
+    };
+
+    let steps = (gap / step).ceil() as usize;
+    into.reserve(steps);
+    for i in 1..steps {
+        let time = last.time + i as f32 * step;
+        let t = (time - last.time) / gap;
+        into.push(EntityPositionUpdate {
+            time,
+            x: lerp(last.x, first.x, t),
+            y: lerp(last.y, first.y, t),
+            z: lerp(last.z, first.z, t),
+            pitch: turn(last.pitch, first.pitch, t),
+            roll: turn(last.roll, first.roll, t),
+            yaw: turn(last.yaw, first.yaw, t),
+            radar_target: last.radar_target,
+        });
+    }
+}
+
+/// Entities (by ID) of one kind that could be the same thing
+/// in flights recorded at the same time (see [`MergeMode::Overlapping`])
+#[derive(Debug, Default)]
+struct OverlapCandidates<'a> {
+    /// How far (in feet) apart they can be and still match,
+    /// from [`MatchRadius::for_flags()`]
+    radius: f32,
+    previous: Vec<(i32, &'a [EntityPositionUpdate])>,
+    next: Vec<(i32, &'a [EntityPositionUpdate])>,
+}
+
+/// How far apart two flights are in time, or negative how long they overlap
+fn overlap_gap(a: &Flight, b: &Flight) -> f32 {
+    a.start_time.max(b.start_time) - a.end_time.min(b.end_time)
+}
+
+/// Whether `time` falls in the (inclusive) `window`
+fn during(window: (f32, f32), time: f32) -> bool {
+    time >= window.0 && time <= window.1
+}
+
+/// Combines what two flights recorded of the same entity.
+///
+/// Only the player flying an entity records its switch and DOF events,
+/// and they get its position more often than anyone else.
+/// So the flight with the most of those has the final say
+/// while it was recording (`recorded`), and the other fills in the rest.
+fn combine_entity(
+    into: &mut EntityData,
+    into_recorded: (f32, f32),
+    from: EntityData,
+    from_recorded: (f32, f32),
+) {
+    let detail = |e: &EntityData| {
+        (
+            e.events.len(),
+            e.position_data
+                .as_ref()
+                .map_or(0, |d| d.position_updates.len()),
+        )
+    };
+    let (mut owner, recorded, other) = if detail(&from) > detail(into) {
+        (from, from_recorded, std::mem::take(into))
+    } else {
+        (std::mem::take(into), into_recorded, from)
+    };
+
+    owner.events.extend(
+        other
+            .events
+            .into_iter()
+            .filter(|e| !during(recorded, e.time)),
+    );
+    owner.events.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    if let (Some(to), Some(from)) = (&mut owner.position_data, other.position_data) {
+        to.position_updates.extend(
+            from.position_updates
+                .into_iter()
+                .filter(|u| !during(recorded, u.time)),
+        );
+        to.position_updates
+            .sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    *into = owner;
+}
+
+/// Where an entity with the given position updates was at `time`,
+/// or `None` if it wasn't around then
+/// (give or take [`MAX_EXTRAPOLATION`] seconds).
+fn position_at(updates: &[EntityPositionUpdate], time: f32) -> Option<[f32; 3]> {
+    let first = updates.first()?;
+    let last = updates.last()?;
+    if time.is_nan()
+        || time < first.time - MAX_EXTRAPOLATION
+        || time > last.time + MAX_EXTRAPOLATION
+    {
+        return None;
+    }
+
+    let after = updates.partition_point(|u| u.time <= time);
+    if after == 0 {
+        return Some([first.x, first.y, first.z]);
+    }
+    if after == updates.len() {
+        return Track::end(updates).map(|track| track.predict(time));
+    }
+    let (from, to) = (&updates[after - 1], &updates[after]);
+    let t = (time - from.time) / (to.time - from.time);
+    Some([
+        from.x + (to.x - from.x) * t,
+        from.y + (to.y - from.y) * t,
+        from.z + (to.z - from.z) * t,
+    ])
+}
+
+/// How many of an entity's position updates we compare to another's
+/// to see how closely they followed each other
+const TRAJECTORY_SAMPLES: usize = 16;
+
+/// How far apart (in feet, on average) two entities were
+/// while both were around, or `None` if they never were.
+fn trajectory_distance(
+    previous: &[EntityPositionUpdate],
+    next: &[EntityPositionUpdate],
+) -> Option<f32> {
+    let from = previous.first()?.time - MAX_EXTRAPOLATION;
+    let to = previous.last()?.time + MAX_EXTRAPOLATION;
+    let shared =
+        next.get(next.partition_point(|u| u.time < from)..next.partition_point(|u| u.time <= to))?;
+    if shared.is_empty() {
+        return None;
+    }
+
+    let mut total = 0.0;
+    let mut count = 0;
+    for update in shared
+        .iter()
+        .step_by(shared.len().div_ceil(TRAJECTORY_SAMPLES))
+    {
+        if let Some(position) = position_at(previous, update.time) {
+            total += ((update.x - position[0]).powi(2)
+                + (update.y - position[1]).powi(2)
+                + (update.z - position[2]).powi(2))
+            .sqrt();
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return None;
+    }
+    Some(total / count as f32)
+}
+
+/// How closely an entity in one flight followed one in another
+/// recorded at the same time. Lower is better, and `None` means
+/// they were more than `radius` feet apart on average
+/// (or never around at the same time), so they're not the same thing.
+fn trajectory_score(
+    previous: &[EntityPositionUpdate],
+    next: &[EntityPositionUpdate],
+    radius: f32,
+) -> Option<f32> {
+    let distance = trajectory_distance(previous, next)?;
+    if distance.is_nan() || distance >= radius {
+        return None;
+    }
+    Some(distance / radius)
+}
+
+/// How far (in feet) an entity starting at `next` is
+/// from where we'd expect one that ended at `previous` to be
+fn miss(previous: &Track, next: &Track) -> f32 {
+    let predicted = previous.predict(next.time);
+    ((next.position[0] - predicted[0]).powi(2)
+        + (next.position[1] - predicted[1]).powi(2)
+        + (next.position[2] - predicted[2]).powi(2))
+    .sqrt()
+}
+
+/// How well an entity starting at `next` continues one that ended at `previous`.
+/// Lower is better, and `None` means it's more than `radius` feet off
+/// from where we expected, so it's not the same thing.
+fn match_score(previous: &Track, next: &Track, radius: f32) -> Option<f32> {
+    let miss = miss(previous, next);
+    // NaNs from garbage files shouldn't match anything.
+    if miss.is_nan() || miss >= radius {
+        return None;
+    }
+    let mut score = miss / radius;
+
+    // In a furball, lots of things are close together.
+    // Which way they're pointed and how fast they're going helps tell them apart.
+    let turn = (next.yaw - previous.yaw).rem_euclid(std::f32::consts::TAU);
+    score += HEADING_WEIGHT * turn.min(std::f32::consts::TAU - turn) / std::f32::consts::PI;
+
+    if let (Some(previous_speed), Some(next_speed)) = (previous.speed(), next.speed()) {
+        let fastest = previous_speed.max(next_speed);
+        if fastest > 0.0 {
+            score += SPEED_WEIGHT * (previous_speed - next_speed).abs() / fastest;
+        }
+    }
+
+    // Ditto for NaN headings and speeds.
+    if score.is_nan() {
+        return None;
+    }
+    Some(score)
+}
+
+// Stored in `EntityPositionData::flags`, based on the position record type.
+pub const ENTITY_FLAG_MISSILE: u32 = 0x00000001;
+// Features don't store flags; this is used by the vhs module when writing them.
+pub const ENTITY_FLAG_FEATURE: u32 = 0x00000002;
+pub const ENTITY_FLAG_AIRCRAFT: u32 = 0x00000004;
+pub const ENTITY_FLAG_CHAFF: u32 = 0x00000008;
+pub const ENTITY_FLAG_FLARE: u32 = 0x00000010;
+
+#[derive(Debug, Copy, Clone)]
+pub struct EntityPositionUpdate {
+    pub time: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub pitch: f32,
+    pub roll: f32,
+    pub yaw: f32,
+
+    /// UID of the current radar target, or -1 if the entity doesn't have one.
+    pub radar_target: i32,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct EntityEvent {
+    pub time: f32,
+    pub payload: EntityEventPayload,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum EntityEventPayload {
+    SwitchEvent(SwitchEvent),
+    DofEvent(DofEvent),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SwitchEvent {
+    pub switch_number: i32,
+    pub new_switch_value: i32,
+    pub previous_switch_value: i32,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DofEvent {
+    pub dof_number: i32,
+    pub new_dof_value: f32,
+    pub previous_dof_value: f32,
+}
+
+/// Data gleaned from the entity's first position record.
+/// (Contains things besides the position)
+#[derive(Debug, Clone)]
+pub struct EntityPositionData {
+    pub kind: i32,
+    /// Stores The type of entity (see `ENTITY_FLAG_...`)
+    pub flags: u32,
+    pub position_updates: Vec<EntityPositionUpdate>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EntityData {
+    /// Sometimes events start arriving before the position data,
+    /// so fill that in when it arrives.
+    pub position_data: Option<EntityPositionData>,
+    pub events: Vec<EntityEvent>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct FeatureEvent {
+    pub time: f32,
+    pub feature_uid: i32,
+    pub new_status: i32,
+    pub previous_status: i32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeatureData {
+    pub kind: i32,
+    pub lead_uid: i32,
+    pub slot: i32,
+    pub special_flags: u32,
+    pub time: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub pitch: f32,
+    pub roll: f32,
+    pub yaw: f32,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct GeneralEvent {
+    pub type_byte: u8,
+    pub start: f32,
+    pub stop: f32,
+    pub kind: i32,
+    pub user: i32,
+    pub flags: u32,
+    pub scale: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub dx: f32,
+    pub dy: f32,
+    pub dz: f32,
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+// Each record in a `.flt` starts with one of these type bytes.
+// General events store theirs (tracers & sound effects) in `GeneralEvent::type_byte`.
+pub const REC_TYPE_GENERAL_POSITION: u8 = 0;
+pub const REC_TYPE_MISSILE_POSITION: u8 = 1;
+pub const REC_TYPE_FEATURE_POSITION: u8 = 2;
+pub const REC_TYPE_AIRCRAFT_POSITION: u8 = 3;
+pub const REC_TYPE_TRACER_START: u8 = 4;
+pub const REC_TYPE_STATIONARY_SFX: u8 = 5;
+pub const REC_TYPE_MOVING_SFX: u8 = 6;
+pub const REC_TYPE_SWITCH: u8 = 7;
+pub const REC_TYPE_DOF: u8 = 8;
+pub const REC_TYPE_CHAFF_POSITION: u8 = 9;
+pub const REC_TYPE_FLARE_POSITION: u8 = 10;
+pub const REC_TYPE_TOD_OFFSET: u8 = 11;
+pub const REC_TYPE_FEATURE_STATUS: u8 = 12;
+pub const REC_TYPE_CALLSIGN_LIST: u8 = 13;
+/// How many record types there are (and one past the last valid type byte)
+pub const REC_TYPE_COUNT: usize = 14;
+
+/// A human-readable name for each record type, for diagnostics
+pub fn record_type_name(type_byte: u8) -> &'static str {
+    match type_byte {
+        REC_TYPE_GENERAL_POSITION => "general position",
+        REC_TYPE_MISSILE_POSITION => "missile position",
+        REC_TYPE_FEATURE_POSITION => "feature position",
+        REC_TYPE_AIRCRAFT_POSITION => "aircraft position",
+        REC_TYPE_TRACER_START => "tracer start",
+        REC_TYPE_STATIONARY_SFX => "stationary sound",
+        REC_TYPE_MOVING_SFX => "moving sound",
+        REC_TYPE_SWITCH => "switch",
+        REC_TYPE_DOF => "DOF",
+        REC_TYPE_CHAFF_POSITION => "chaff position",
+        REC_TYPE_FLARE_POSITION => "flare position",
+        REC_TYPE_TOD_OFFSET => "time of day offset",
+        REC_TYPE_FEATURE_STATUS => "feature status",
+        REC_TYPE_CALLSIGN_LIST => "callsign list",
+        _ => "unknown",
+    }
+}
+
+/// Something went wrong reading a record from a `.flt` file.
+///
+/// Each error notes the type byte of the record we were reading
+/// and the byte offset where that record started.
+#[derive(Debug)]
+pub enum FltError {
+    /// The file ended in the middle of a record.
+    Truncated { type_byte: u8, offset: u64 },
+    /// The record's type byte isn't one we know about.
+    UnknownType { type_byte: u8, offset: u64 },
+    /// The record has a strange time or coordinates,
+    /// so it's probably garbage. (Only [`Flight::parse_recovering()`] checks.)
+    Implausible { type_byte: u8, offset: u64 },
+    /// A callsign list claims to have a negative number of callsigns.
+    NegativeCallsignCount { count: i32, offset: u64 },
+    /// Reading failed for some other reason.
+    /// (`type_byte` is `None` if we couldn't even read that.)
+    Io {
+        type_byte: Option<u8>,
+        offset: u64,
+        source: io::Error,
+    },
+}
+
+impl FltError {
+    fn from_io(source: io::Error, type_byte: u8, offset: u64) -> Self {
+        if source.kind() == io::ErrorKind::UnexpectedEof {
+            FltError::Truncated { type_byte, offset }
+        } else {
+            FltError::Io {
+                type_byte: Some(type_byte),
+                offset,
+                source,
+            }
+        }
+    }
+
+    /// The type byte of the record that couldn't be read
+    pub fn type_byte(&self) -> Option<u8> {
+        match self {
+            FltError::Truncated { type_byte, .. }
+            | FltError::UnknownType { type_byte, .. }
+            | FltError::Implausible { type_byte, .. } => Some(*type_byte),
+            FltError::NegativeCallsignCount { .. } => Some(REC_TYPE_CALLSIGN_LIST),
+            FltError::Io { type_byte, .. } => *type_byte,
+        }
+    }
+
+    /// The byte offset (from the start of the file) of the record that couldn't be read
+    pub fn offset(&self) -> u64 {
+        match self {
+            FltError::Truncated { offset, .. }
+            | FltError::UnknownType { offset, .. }
+            | FltError::Implausible { offset, .. }
+            | FltError::NegativeCallsignCount { offset, .. }
+            | FltError::Io { offset, .. } => *offset,
+        }
+    }
+}
+
+impl fmt::Display for FltError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FltError::Truncated { type_byte, offset } => write!(
+                f,
+                "Reached end of file in the middle of a record (type {}, at byte {})",
+                type_byte, offset
+            ),
+            FltError::UnknownType { type_byte, offset } => write!(
+                f,
+                "Unknown record type {} at byte {} (0-13 are valid)",
+                type_byte, offset
+            ),
+            FltError::Implausible { type_byte, offset } => write!(
+                f,
+                "Record (type {}, at byte {}) has a strange time or coordinates",
+                type_byte, offset
+            ),
+            FltError::NegativeCallsignCount { count, offset } => write!(
+                f,
+                "Negative ({}) callsign count in the callsign list at byte {}",
+                count, offset
+            ),
+            FltError::Io {
+                type_byte: Some(type_byte),
+                offset,
+                source,
+            } => write!(
+                f,
+                "Couldn't read record (type {}, at byte {}): {}",
+                type_byte, offset, source
+            ),
+            FltError::Io {
+                type_byte: None,
+                offset,
+                source,
+            } => write!(f, "Couldn't read record at byte {}: {}", offset, source),
+        }
+    }
+}
+
+impl std::error::Error for FltError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FltError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// What went wrong reading a corrupted `.flt`, and how far we got first.
+#[derive(Debug, Clone)]
+pub struct Corruption {
+    /// The error that stopped parsing
+    /// (which knows where the bad record starts and its type byte).
+    pub error: Arc<FltError>,
+
+    /// How many bytes were left in the file, starting at the bad record
+    pub bytes_left: u64,
+
+    /// How many records of each type (indexed by type byte)
+    /// parsed successfully before the bad one.
+    pub records_read: [u64; REC_TYPE_COUNT],
+
+    /// Byte ranges skipped over by [`Flight::parse_recovering()`]
+    /// looking for good records. The first starts at the bad record.
+    pub skipped: Vec<Range<u64>>,
+
+    /// How many records [`Flight::parse_recovering()`] read after the bad one
+    pub records_recovered: u64,
+}
+
+impl Corruption {
+    /// `file_length` is where the file ended (or at least where we stopped reading it).
+    fn new(error: FltError, file_length: u64, records_read: [u64; REC_TYPE_COUNT]) -> Self {
+        Self {
+            bytes_left: file_length - error.offset(),
+            error: Arc::new(error),
+            records_read,
+            skipped: Vec::new(),
+            records_recovered: 0,
+        }
+    }
+
+    /// The byte offset (from the start of the file) of the bad record
+    pub fn offset(&self) -> u64 {
+        self.error.offset()
+    }
+
+    /// The bad record's type byte, if we could read it
+    pub fn type_byte(&self) -> Option<u8> {
+        self.error.type_byte()
+    }
+
+    /// True if the file just stops partway through a record,
+    /// like it does when BMS crashes mid-recording.
+    ///
+    /// Otherwise, there's something besides records in the file
+    /// (like garbage in the middle of it).
+    pub fn truncated(&self) -> bool {
+        // If we recovered records after the bad one, it wasn't at the end.
+        matches!(*self.error, FltError::Truncated { .. }) && self.records_recovered == 0
+    }
+
+    /// The total number of records read before the bad one
+    pub fn total_records_read(&self) -> u64 {
+        self.records_read.iter().sum()
+    }
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} with {} bytes left. {} records were read before it",
+            self.error,
+            self.bytes_left,
+            self.total_records_read()
+        )?;
+
+        let mut first = true;
+        for (type_byte, count) in self.records_read.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let separator = if first { ": " } else { ", " };
+            first = false;
+            write!(
+                f,
+                "{}{} {}",
+                separator,
+                count,
+                record_type_name(type_byte as u8)
+            )?;
+        }
+
+        if !self.skipped.is_empty() {
+            let skipped_bytes: u64 = self.skipped.iter().map(|s| s.end - s.start).sum();
+            write!(
+                f,
+                ". Skipped {} bytes in {} place(s) to recover {} more records",
+                skipped_bytes,
+                self.skipped.len(),
+                self.records_recovered
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps track of how far into the `.flt` we've read, so errors can say where they happened.
+///
+/// (Like [`acmitape::CountedRead`], but FLT files aren't bound by the VHS's 32-bit offsets.)
+struct OffsetRead<R> {
+    inner: R,
+    offset: u64,
+}
+
+impl<R: Read> OffsetRead<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, offset: 0 }
+    }
+}
+
+impl<R: Read> Read for OffsetRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = self.inner.read(buf);
+        if let Ok(count) = res {
+            self.offset += count as u64;
+        }
+        res
+    }
+}
+
+/// Reads the next record into `flight`,
+/// returning its type byte, or `None` at the end of the file.
+fn read_record<R: Read>(
+    flight: &mut Flight,
+    r: &mut OffsetRead<R>,
+) -> Result<Option<u8>, FltError> {
+    let offset = r.offset;
+    let type_byte = match read_u8(r) {
+        Ok(b) => b,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None), // EOF
+        Err(source) => {
+            return Err(FltError::Io {
+                type_byte: None,
+                offset,
+                source,
+            })
+        }
+    };
+    let io_err = |e| FltError::from_io(e, type_byte, offset);
+
+    let payload_size =
+        payload_size(type_byte).ok_or(FltError::UnknownType { type_byte, offset })?;
+
+    let time = read_f32(r).map_err(io_err)?;
+    flight.note_time(type_byte, time);
+
+    let mut buf = [0; MAX_PAYLOAD_SIZE];
+    let payload = &mut buf[..payload_size];
+    r.read_exact(payload).map_err(io_err)?;
+
+    let record = if type_byte == REC_TYPE_CALLSIGN_LIST {
+        let count = callsign_count(payload, offset)?;
+        Record::CallsignList(parse_callsigns(count, r).map_err(io_err)?)
+    } else {
+        Record::decode(type_byte, payload).ok_or(FltError::Truncated { type_byte, offset })?
+    };
+    flight.add_record(time, record);
+    Ok(Some(type_byte))
+}
+
+/// A record decoded by [`decode_record()`]
+struct DecodedRecord {
+    type_byte: u8,
+    time: f32,
+    /// How many bytes the record takes up in the file
+    size: usize,
+    record: Record,
+}
+
+/// Decodes the record at `offset` straight out of the file's bytes,
+/// or returns `None` at the end of the file.
+///
+/// Stretches the flight's start and end times to cover the record
+/// (see [`Flight::note_time()`]), but leaves adding it up to the caller.
+fn decode_record(
+    flight: &mut Flight,
+    bytes: &[u8],
+    offset: usize,
+) -> Result<Option<DecodedRecord>, FltError> {
+    let type_byte = match bytes.get(offset) {
+        Some(b) => *b,
+        None => return Ok(None), // EOF
+    };
+    let truncated = || FltError::Truncated {
+        type_byte,
+        offset: offset as u64,
+    };
+
+    let payload_size = payload_size(type_byte).ok_or(FltError::UnknownType {
+        type_byte,
+        offset: offset as u64,
+    })?;
+
+    let time = f32_at(bytes, offset + 1).ok_or_else(truncated)?;
+    flight.note_time(type_byte, time);
+
+    // Check the bounds once for the whole record, then decode it.
+    let payload_start = offset + 5;
+    let payload = bytes
+        .get(payload_start..payload_start + payload_size)
+        .ok_or_else(truncated)?;
+    let mut size = 5 + payload_size;
+
+    let record = if type_byte == REC_TYPE_CALLSIGN_LIST {
+        let count = callsign_count(payload, offset as u64)?;
+        let list_size = count
+            .checked_mul(CallsignRecord::SIZE as usize)
+            .ok_or_else(truncated)?;
+        let list = bytes[payload_start + payload_size..]
+            .get(..list_size)
+            .ok_or_else(truncated)?;
+        size += list_size;
+        Record::CallsignList(decode_callsigns(list))
+    } else {
+        Record::decode(type_byte, payload).ok_or_else(truncated)?
+    };
+    Ok(Some(DecodedRecord {
+        type_byte,
+        time,
+        size,
+        record,
+    }))
+}
+
+/// A record's payload (everything after its type byte and time), decoded
+enum Record {
+    /// A position update for anything but features
+    Position {
+        flags: u32,
+        record: PositionRecord,
+        radar_target: i32,
+    },
+    FeaturePosition(FeaturePositionRecord),
+    TracerStart(TracerStartRecord),
+    StationarySound(StationarySoundRecord),
+    MovingSound(MovingSoundRecord),
+    Switch(SwitchRecord),
+    Dof(DofRecord),
+    TodOffset,
+    FeatureStatus(FeatureEventRecord),
+    CallsignList(Vec<CallsignRecord>),
+}
+
+impl Record {
+    /// Decodes a fixed-size payload, sized by [`payload_size()`].
+    /// (Callsign lists aren't fixed-size, so their callers decode them.)
+    ///
+    /// Returns `None` if the payload is too short.
+    fn decode(type_byte: u8, payload: &[u8]) -> Option<Self> {
+        let position = |flags| {
+            Some(Record::Position {
+                flags,
+                record: PositionRecord::decode(fixed(payload)?),
+                radar_target: -1,
+            })
+        };
+
+        match type_byte {
+            REC_TYPE_GENERAL_POSITION => position(0),
+            REC_TYPE_MISSILE_POSITION => position(ENTITY_FLAG_MISSILE),
+            REC_TYPE_CHAFF_POSITION => position(ENTITY_FLAG_CHAFF),
+            REC_TYPE_FLARE_POSITION => position(ENTITY_FLAG_FLARE),
+            REC_TYPE_AIRCRAFT_POSITION => {
+                // Aircraft positions are followed by their radar target.
+                let b: &[u8; 36] = fixed(payload)?;
+                Some(Record::Position {
+                    flags: ENTITY_FLAG_AIRCRAFT,
+                    record: PositionRecord::decode(fixed(b)?),
+                    radar_target: le_i32(b, 32),
+                })
+            }
+            REC_TYPE_FEATURE_POSITION => Some(Record::FeaturePosition(
+                FeaturePositionRecord::decode(fixed(payload)?),
+            )),
+            REC_TYPE_TRACER_START => Some(Record::TracerStart(TracerStartRecord::decode(fixed(
+                payload,
+            )?))),
+            REC_TYPE_STATIONARY_SFX => Some(Record::StationarySound(
+                StationarySoundRecord::decode(fixed(payload)?),
+            )),
+            REC_TYPE_MOVING_SFX => Some(Record::MovingSound(MovingSoundRecord::decode(fixed(
+                payload,
+            )?))),
+            REC_TYPE_SWITCH => Some(Record::Switch(SwitchRecord::decode(fixed(payload)?))),
+            REC_TYPE_DOF => Some(Record::Dof(DofRecord::decode(fixed(payload)?))),
+            REC_TYPE_TOD_OFFSET => Some(Record::TodOffset),
+            REC_TYPE_FEATURE_STATUS => Some(Record::FeatureStatus(FeatureEventRecord::decode(
+                fixed(payload)?,
+            ))),
+            _ => None,
+        }
+    }
+}
+
+impl Flight {
+    /// Stretches the flight's start and end times to cover a record at the given time.
+    fn note_time(&mut self, type_byte: u8, time: f32) {
+        if type_byte != REC_TYPE_TOD_OFFSET {
+            if self.start_time < 0.0 {
+                self.start_time = time;
+            }
+            if self.end_time < time {
+                self.end_time = time;
+            }
+        }
+    }
+
+    /// Adds a decoded record to the flight.
+    fn add_record(&mut self, time: f32, record: Record) {
+        match record {
+            Record::Position {
+                flags,
+                record,
+                radar_target,
+            } => {
+                // Find the existing entity or create a new one, then append
+                // this position update.
+                let entity_data = self.entities.entry(record.uid).or_default();
+
+                if let Some(posit_data) = &entity_data.position_data {
+                    if posit_data.kind != record.kind {
+                        trace!(
+                            "Position update for entity {} switched kinds from {} to {}",
+                            record.uid,
+                            posit_data.kind,
+                            record.kind
+                        );
+                    }
+
+                    if posit_data.flags != flags {
+                        trace!(
+                            "Position update for entity {} switched flags from {} to {}",
+                            record.uid,
+                            posit_data.flags,
+                            flags
+                        );
+                    }
+                }
+
+                let posit_data = entity_data.position_data.get_or_insert_with(|| {
+                    trace!(
+                        "New entity {}: kind {}, flags {}",
+                        record.uid,
+                        record.kind,
+                        flags
+                    );
+                    EntityPositionData {
+                        kind: record.kind,
+                        flags,
+                        position_updates: Vec::new(),
+                    }
+                });
+
+                let posit_update = EntityPositionUpdate {
+                    time,
+                    x: record.x,
+                    y: record.y,
+                    z: record.z,
+                    pitch: record.pitch,
+                    roll: record.roll,
+                    yaw: record.yaw,
+                    radar_target,
+                };
+                trace!("{}: {:?}", record.uid, posit_update);
+                posit_data.position_updates.push(posit_update);
+            }
+            Record::FeaturePosition(record) => {
+                let feature = FeatureData {
+                    kind: record.kind,
+                    lead_uid: record.lead_uid,
+                    slot: record.slot,
+                    special_flags: record.special_flags,
+                    time,
+                    x: record.x,
+                    y: record.y,
+                    z: record.z,
+                    pitch: record.pitch,
+                    roll: record.roll,
+                    yaw: record.yaw,
+                };
+
+                if let Some(first_def) = self.features.get(&record.uid) {
+                    if *first_def != feature {
+                        trace!(
+                        "Feature {} defined multiple times ({:?} -> {:?})! Ignoring subsequent ones",
+                        record.uid,
+                        first_def,
+                        feature
+                    );
+                    }
+                    return;
+                }
+
+                trace!("New feature {}: {:?}", record.uid, feature);
+                self.features.insert(record.uid, feature);
+            }
+            Record::TracerStart(record) => {
+                let event = GeneralEvent {
+                    type_byte: REC_TYPE_TRACER_START,
+                    start: time,
+                    stop: time + 5.0, // Should we make this configurable?
+                    x: record.x,
+                    y: record.y,
+                    z: record.z,
+                    dx: record.dx,
+                    dy: record.dy,
+                    dz: record.dz,
+                    ..Default::default()
+                };
+                trace!("Tracer start: {:?}", event);
+                self.general_events.push(event);
+            }
+            Record::StationarySound(record) => {
+                let event = GeneralEvent {
+                    type_byte: REC_TYPE_STATIONARY_SFX,
+                    start: time,
+                    stop: time + record.ttl,
+                    kind: record.kind,
+                    x: record.x,
+                    y: record.y,
+                    z: record.z,
+                    scale: record.scale,
+                    ..Default::default()
+                };
+                trace!("Stationary sound: {:?}", event);
+                self.general_events.push(event);
+            }
+            Record::MovingSound(record) => {
+                let event = GeneralEvent {
+                    type_byte: REC_TYPE_MOVING_SFX,
+                    start: time,
+                    stop: time + record.ttl,
+                    kind: record.kind,
+                    user: record.user,
+                    flags: record.flags,
+                    x: record.x,
+                    y: record.y,
+                    z: record.z,
+                    dx: record.dx,
+                    dy: record.dy,
+                    dz: record.dz,
+                    scale: record.scale,
+                    ..Default::default()
+                };
+                trace!("Moving sound: {:?}", event);
+                self.general_events.push(event);
+            }
+            Record::Switch(record) => {
+                let entity = self.entities.entry(record.uid).or_default();
+
+                let payload = EntityEventPayload::SwitchEvent(SwitchEvent {
+                    switch_number: record.switch_number,
+                    new_switch_value: record.new_switch_value,
+                    previous_switch_value: record.previous_switch_value,
+                });
+
+                let event = EntityEvent { time, payload };
+                trace!("{} (kind {}): {:?}", record.uid, record.kind, event);
+                entity.events.push(event);
+            }
+            Record::Dof(record) => {
+                let entity = self.entities.entry(record.uid).or_default();
+
+                let payload = EntityEventPayload::DofEvent(DofEvent {
+                    dof_number: record.dof_number,
+                    new_dof_value: record.new_dof_value,
+                    previous_dof_value: record.previous_dof_value,
+                });
+
+                let event = EntityEvent { time, payload };
+                trace!("{} (kind {}): {:?}", record.uid, record.kind, event);
+                entity.events.push(event);
+            }
+            Record::TodOffset => self.tod_offset = time,
+            Record::FeatureStatus(record) => {
+                let event = FeatureEvent {
+                    time,
+                    feature_uid: record.uid,
+                    new_status: record.new_status,
+                    previous_status: record.previous_status,
+                };
+                // Look up the feature by its UID
+                if !self.features.contains_key(&record.uid) {
+                    trace!("No feature for {:?}", event);
+                    return;
+                }
+                trace!("Feature event: {:?}", event);
+                self.feature_events.push(event);
+            }
+            Record::CallsignList(callsign_array) => self.resolve_callsigns(callsign_array),
+        }
+    }
+
+    /// Looks up the callsign of each entity and feature in the flight
+    /// from a callsign list.
+    fn resolve_callsigns(&mut self, callsign_array: Vec<CallsignRecord>) {
+        if !self.callsigns.is_empty() {
+            warn!("Multiple callsign lists found, using the latest");
+            self.callsigns.clear();
+        }
+
+        // Callsign data is a sparse array where indexing by ID
+        // gives you name and faction.
+        //
+        // Callsigns are always written at the end,
+        // so we can safely assume the entity and feature maps are filled out.
+        for feature_key in self.features.keys() {
+            let index = *feature_key as usize;
+            if index >= callsign_array.len() {
+                continue;
+            }
+            let callsign = callsign_array[index];
+            if callsign == CallsignRecord::default() {
+                continue;
+            }
+            self.callsigns.insert(*feature_key, callsign);
+        }
+
+        for entity_key in self.entities.keys() {
+            let index = *entity_key as usize;
+            if index >= callsign_array.len() {
+                continue;
+            }
+            let callsign = callsign_array[index];
+            if callsign == CallsignRecord::default() {
+                continue;
+            }
+
+            // We're inserting entities second so that if features and entities
+            // share "unique" IDs, and callsign data _doesn't_ match,
+            // we defer to the entity's.
+            // We probably care about it being correct more than a static feature.
+            let previous = self.callsigns.insert(*entity_key, callsign);
+            if let Some(p) = previous {
+                warn!(
+                    r#"FLT file contains an entity and a feature that share a "unique" ID of {}..."#,
+                    *entity_key
+                );
+                if callsign != p {
+                    warn!(
+                        "...and the callsign data doesn't match: {:?}, {:?}",
+                        callsign, p
+                    );
+                }
+            }
+        }
     }
 }
 
-const ENTITY_FLAG_MISSILE: u32 = 0x00000001;
-// Used by the vhs module when writing features.
-// All entities store theirs, so we don't need to export the other flags.
-pub const ENTITY_FLAG_FEATURE: u32 = 0x00000002;
-const ENTITY_FLAG_AIRCRAFT: u32 = 0x00000004;
-const ENTITY_FLAG_CHAFF: u32 = 0x00000008;
-const ENTITY_FLAG_FLARE: u32 = 0x00000010;
+/// Don't bother splitting up files smaller than this.
+const MIN_PARALLEL_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Splits the `.flt` into ranges of whole records, each at least `chunk_size` bytes
+/// (except the last).
+///
+/// This only reads type bytes (and callsign counts) to hop from record to record.
+/// If it finds one it can't size up, the last range runs from there to the end of the file
+/// so that whoever parses it finds the problem.
+fn chunk_boundaries(bytes: &[u8], chunk_size: usize) -> Vec<Range<usize>> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut at = 0;
+    while at < bytes.len() {
+        if at - start >= chunk_size {
+            chunks.push(start..at);
+            start = at;
+        }
+        match record_size(&bytes[at..]) {
+            Some(size) if size <= bytes.len() - at => at += size,
+            _ => break,
+        }
+    }
+    chunks.push(start..bytes.len());
+    chunks
+}
 
-#[derive(Debug, Copy, Clone)]
-pub struct EntityPositionUpdate {
-    pub time: f32,
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
-    pub pitch: f32,
-    pub roll: f32,
-    pub yaw: f32,
+/// What we need to remember while stitching chunks together,
+/// besides the flight itself
+struct Stitching {
+    records_read: [u64; REC_TYPE_COUNT],
 
-    /// UID of the current radar target, or -1 if the entity doesn't have one.
-    pub radar_target: i32,
-}
+    /// Callsign lists, which we resolve once we've stitched together every chunk
+    callsign_lists: Vec<Vec<CallsignRecord>>,
 
-#[derive(Debug, Copy, Clone)]
-pub struct EntityEvent {
-    pub time: f32,
-    pub payload: EntityEventPayload,
+    file_length: u64,
 }
 
-#[derive(Debug, Copy, Clone)]
-pub enum EntityEventPayload {
-    SwitchEvent(SwitchEvent),
-    DofEvent(DofEvent),
+impl Stitching {
+    fn new(file_length: u64) -> Self {
+        Self {
+            records_read: [0; REC_TYPE_COUNT],
+            callsign_lists: Vec::new(),
+            file_length,
+        }
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct SwitchEvent {
-    pub switch_number: i32,
-    pub new_switch_value: i32,
-    pub previous_switch_value: i32,
-}
+/// A chunk of a `.flt`, parsed on its own (see [`Flight::parse_in_chunks()`])
+struct PartialFlight {
+    flight: Flight,
 
-#[derive(Debug, Copy, Clone)]
-pub struct DofEvent {
-    pub dof_number: i32,
-    pub new_dof_value: f32,
-    pub previous_dof_value: f32,
+    records_read: [u64; REC_TYPE_COUNT],
+
+    /// What stopped us, if something went wrong
+    error: Option<FltError>,
+
+    /// Feature events, and if their feature was defined earlier in the chunk.
+    /// If it wasn't, it might have been in an earlier chunk.
+    feature_events: Vec<(FeatureEvent, bool)>,
+
+    /// Callsign lists, which we can't resolve until we have every chunk's
+    /// entities and features.
+    callsign_lists: Vec<Vec<CallsignRecord>>,
 }
 
-/// Data gleaned from the entity's first position record.
-/// (Contains things besides the position)
-#[derive(Debug, Clone)]
-pub struct EntityPositionData {
-    pub kind: i32,
-    /// Stores The type of entity (see `ENTITY_FLAG_...`)
-    pub flags: u32,
-    pub position_updates: Vec<EntityPositionUpdate>,
+impl PartialFlight {
+    fn parse(bytes: &[u8], chunk: Range<usize>) -> Self {
+        let mut partial = Self {
+            flight: Flight::empty(),
+            records_read: [0; REC_TYPE_COUNT],
+            error: None,
+            feature_events: Vec::new(),
+            callsign_lists: Vec::new(),
+        };
+
+        let mut at = chunk.start;
+        while at < chunk.end {
+            let decoded = match decode_record(&mut partial.flight, bytes, at) {
+                Ok(Some(decoded)) => decoded,
+                Ok(None) => break, // EOF
+                Err(e) => {
+                    partial.error = Some(e);
+                    break;
+                }
+            };
+            at += decoded.size;
+            partial.records_read[decoded.type_byte as usize] += 1;
+
+            match decoded.record {
+                Record::FeatureStatus(record) => {
+                    let event = FeatureEvent {
+                        time: decoded.time,
+                        feature_uid: record.uid,
+                        new_status: record.new_status,
+                        previous_status: record.previous_status,
+                    };
+                    let defined = partial.flight.features.contains_key(&record.uid);
+                    partial.feature_events.push((event, defined));
+                }
+                Record::CallsignList(callsign_array) => partial.callsign_lists.push(callsign_array),
+                record => partial.flight.add_record(decoded.time, record),
+            }
+        }
+        partial
+    }
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct EntityData {
-    /// Sometimes events start arriving before the position data,
-    /// so fill that in when it arrives.
-    pub position_data: Option<EntityPositionData>,
-    pub events: Vec<EntityEvent>,
+/// Moves everything in `from` onto the end of `into`
+/// (without copying if `into` is empty).
+fn append<T>(into: &mut Vec<T>, mut from: Vec<T>) {
+    if into.is_empty() {
+        *into = from;
+    } else {
+        into.append(&mut from);
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct FeatureEvent {
-    pub time: f32,
-    pub feature_uid: i32,
-    pub new_status: i32,
-    pub previous_status: i32,
+/// The size of a record's payload (everything after its type byte and time),
+/// or `None` if it isn't a type we know.
+///
+/// Callsign lists are variable-length, so for them this is just the size
+/// of the count that leads the list.
+fn payload_size(type_byte: u8) -> Option<usize> {
+    Some(match type_byte {
+        REC_TYPE_GENERAL_POSITION
+        | REC_TYPE_MISSILE_POSITION
+        | REC_TYPE_CHAFF_POSITION
+        | REC_TYPE_FLARE_POSITION => 32,
+        REC_TYPE_AIRCRAFT_POSITION => 36,
+        REC_TYPE_FEATURE_POSITION => 44,
+        REC_TYPE_TRACER_START => 24,
+        REC_TYPE_STATIONARY_SFX => 24,
+        REC_TYPE_MOVING_SFX => 44,
+        REC_TYPE_SWITCH | REC_TYPE_DOF => 20,
+        REC_TYPE_TOD_OFFSET => 0,
+        REC_TYPE_FEATURE_STATUS => 12,
+        REC_TYPE_CALLSIGN_LIST => 4,
+        _ => return None,
+    })
 }
 
-#[derive(Debug, Clone, Default, PartialEq)]
-pub struct FeatureData {
-    pub kind: i32,
-    pub lead_uid: i32,
-    pub slot: i32,
-    pub special_flags: u32,
-    pub time: f32,
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
-    pub pitch: f32,
-    pub roll: f32,
-    pub yaw: f32,
+/// The highest callsign ID [`Flight::write()`] will write.
+///
+/// FLT callsign lists have an entry for every ID up to the highest one,
+/// so one callsign with a huge ID would take gigabytes.
+/// (Several million is still far more than BMS uses.)
+pub const MAX_CALLSIGN_ID: i32 = (1 << 22) - 1;
+
+/// The biggest [`payload_size()`]
+const MAX_PAYLOAD_SIZE: usize = 44;
+
+/// Reads the count from the start of a callsign list's payload.
+fn callsign_count(payload: &[u8], offset: u64) -> Result<usize, FltError> {
+    let count = i32_at(payload, 0).ok_or(FltError::Truncated {
+        type_byte: REC_TYPE_CALLSIGN_LIST,
+        offset,
+    })?;
+    usize::try_from(count).map_err(|_| FltError::NegativeCallsignCount { count, offset })
 }
 
-#[derive(Debug, Copy, Clone, Default)]
-pub struct GeneralEvent {
-    pub type_byte: u8,
-    pub start: f32,
-    pub stop: f32,
-    pub kind: i32,
-    pub user: i32,
-    pub flags: u32,
-    pub scale: f32,
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
-    pub dx: f32,
-    pub dy: f32,
-    pub dz: f32,
-    pub roll: f32,
-    pub pitch: f32,
-    pub yaw: f32,
+/// How many plausible records in a row it takes to convince [`find_resync()`]
+/// it found a record boundary (unless the file ends first).
+const RESYNC_RUN: usize = 3;
+
+/// How far back (in seconds) a record's time can be from the last one's
+/// and still look plausible. BMS writes records as they happen, give or take.
+const PLAUSIBLE_TIME_SLACK: f32 = 5.0;
+
+/// How far ahead (in seconds) a record's time can be from the last one's
+/// and still look plausible.
+const PLAUSIBLE_MAX_GAP: f32 = 60.0;
+
+/// Bigger than any BMS theater (in feet), with room to spare.
+const PLAUSIBLE_MAX_COORDINATE: f32 = 1.0e8;
+
+/// Finds the first offset (at or after `from`) where a few records in a row
+/// look plausible (see [`plausible_record()`]).
+///
+/// `last_time` is the time of the last good record, if we've seen one.
+fn find_resync(bytes: &[u8], from: usize, last_time: Option<f32>) -> Option<usize> {
+    (from..bytes.len()).find(|start| {
+        let mut at = *start;
+        let mut last_time = last_time;
+        for _ in 0..RESYNC_RUN {
+            if at == bytes.len() {
+                break; // Running into the end of the file is good enough.
+            }
+            let record = match record_size(&bytes[at..]).and_then(|size| bytes.get(at..at + size)) {
+                Some(record) => record,
+                None => return false,
+            };
+            if !plausible_record(record, last_time) {
+                return false;
+            }
+            at += record.len();
+            last_time = record_time(record).or(last_time);
+        }
+        true
+    })
 }
 
-const REC_TYPE_GENERAL_POSITION: u8 = 0;
-const REC_TYPE_MISSILE_POSITION: u8 = 1;
-const REC_TYPE_FEATURE_POSITION: u8 = 2;
-const REC_TYPE_AIRCRAFT_POSITION: u8 = 3;
-const REC_TYPE_TRACER_START: u8 = 4;
-const REC_TYPE_STATIONARY_SFX: u8 = 5;
-const REC_TYPE_MOVING_SFX: u8 = 6;
-const REC_TYPE_SWITCH: u8 = 7;
-const REC_TYPE_DOF: u8 = 8;
-const REC_TYPE_CHAFF_POSITION: u8 = 9;
-const REC_TYPE_FLARE_POSITION: u8 = 10;
-const REC_TYPE_TOD_OFFSET: u8 = 11;
-const REC_TYPE_FEATURE_STATUS: u8 = 12;
-const REC_TYPE_CALLSIGN_LIST: u8 = 13;
-
-fn read_record<R: Read>(flight: &mut Flight, r: &mut R) -> Result<bool> {
-    let mut type_byte: [u8; 1] = [0];
-    match r.read(&mut type_byte) {
-        Ok(0) => return Ok(false), // EOF
-        Ok(1) => {}
-        Ok(_) => unreachable!(),
-        Err(e) => return Err(Error::new(e)),
-    };
-    let type_byte = type_byte[0];
+/// The size of the record at the start of `bytes`,
+/// if it has a valid type byte (and for callsign lists, a sane count).
+fn record_size(bytes: &[u8]) -> Option<usize> {
+    // Every record is a type byte and a time, then its payload.
+    let type_byte = *bytes.first()?;
+    let mut payload_size = payload_size(type_byte)?;
+    if type_byte == REC_TYPE_CALLSIGN_LIST {
+        let count = usize::try_from(i32_at(bytes, 5)?).ok()?;
+        payload_size = count
+            .checked_mul(CallsignRecord::SIZE as usize)?
+            .checked_add(payload_size)?;
+    }
+    Some(5 + payload_size)
+}
 
-    let time = read_f32(r)?;
+/// The time of the given record, unless it's the time of day offset
+/// (which isn't a time we can compare against the others).
+fn record_time(record: &[u8]) -> Option<f32> {
+    if *record.first()? == REC_TYPE_TOD_OFFSET {
+        return None;
+    }
+    f32_at(record, 1)
+}
 
-    if type_byte != REC_TYPE_TOD_OFFSET {
-        if flight.start_time < 0.0 {
-            flight.start_time = time;
-        }
-        if flight.end_time < time {
-            flight.end_time = time;
+/// True if the given record (sized by [`record_size()`]) looks good:
+/// its time is close after `last_time` (if we have one),
+/// and any coordinates it has are sane.
+fn plausible_record(record: &[u8], last_time: Option<f32>) -> bool {
+    if !f32_at(record, 1).is_some_and(f32::is_finite) {
+        return false;
+    }
+    if let (Some(time), Some(last)) = (record_time(record), last_time) {
+        if time < last - PLAUSIBLE_TIME_SLACK || time > last + PLAUSIBLE_MAX_GAP {
+            return false;
         }
     }
 
-    match type_byte {
+    // Where in the record its x, y, and z coordinates are, if it has them
+    let coordinates = match record[0] {
         REC_TYPE_GENERAL_POSITION
         | REC_TYPE_MISSILE_POSITION
         | REC_TYPE_AIRCRAFT_POSITION
         | REC_TYPE_CHAFF_POSITION
-        | REC_TYPE_FLARE_POSITION => {
-            let record = PositionRecord::parse(r)?;
-            let radar_target = if type_byte == REC_TYPE_AIRCRAFT_POSITION {
-                read_i32(r)?
-            } else {
-                -1
-            };
-
-            // Find the existing entity or create a new one, then append
-            // this position update.
-            let flags = match type_byte {
-                REC_TYPE_GENERAL_POSITION => 0,
-                REC_TYPE_MISSILE_POSITION => ENTITY_FLAG_MISSILE,
-                REC_TYPE_AIRCRAFT_POSITION => ENTITY_FLAG_AIRCRAFT,
-                REC_TYPE_CHAFF_POSITION => ENTITY_FLAG_CHAFF,
-                REC_TYPE_FLARE_POSITION => ENTITY_FLAG_FLARE,
-                _ => unreachable!(),
-            };
-            let entity_data = flight
-                .entities
-                .entry(record.uid)
-                .or_insert_with(Default::default);
-
-            if let Some(posit_data) = &entity_data.position_data {
-                if posit_data.kind != record.kind {
-                    trace!(
-                        "Position update for entity {} switched kinds from {} to {}",
-                        record.uid,
-                        posit_data.kind,
-                        record.kind
-                    );
-                }
+        | REC_TYPE_FLARE_POSITION => 5 + 8,
+        REC_TYPE_FEATURE_POSITION => 5 + 20,
+        REC_TYPE_TRACER_START => 5,
+        REC_TYPE_STATIONARY_SFX => 5 + 4,
+        REC_TYPE_MOVING_SFX => 5 + 12,
+        _ => return true,
+    };
+    (0..3).all(|i| match f32_at(record, coordinates + i * 4) {
+        Some(c) => c.is_finite() && c.abs() <= PLAUSIBLE_MAX_COORDINATE,
+        None => false,
+    })
+}
 
-                if posit_data.flags != flags {
-                    trace!(
-                        "Position update for entity {} switched flags from {} to {}",
-                        record.uid,
-                        posit_data.flags,
-                        flags
-                    );
-                }
-            } else {
-                trace!(
-                    "New entity {}: kind {}, flags {}",
-                    record.uid,
-                    record.kind,
-                    flags
-                );
-                entity_data.position_data = Some(EntityPositionData {
-                    kind: record.kind,
-                    flags,
-                    position_updates: Vec::new(),
-                });
-            }
+fn f32_at(bytes: &[u8], at: usize) -> Option<f32> {
+    let b = bytes.get(at..at + 4)?;
+    Some(f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
 
-            let posit_update = EntityPositionUpdate {
-                time,
-                x: record.x,
-                y: record.y,
-                z: record.z,
-                pitch: record.pitch,
-                roll: record.roll,
-                yaw: record.yaw,
-                radar_target,
-            };
-            trace!("{}: {:?}", record.uid, posit_update);
-            entity_data
-                .position_data
-                .as_mut()
-                .unwrap()
-                .position_updates
-                .push(posit_update);
-        }
-        REC_TYPE_FEATURE_POSITION => {
-            let record = FeaturePositionRecord::parse(r)?;
-
-            let feature = FeatureData {
-                kind: record.kind,
-                lead_uid: record.lead_uid,
-                slot: record.slot,
-                special_flags: record.special_flags,
-                time,
-                x: record.x,
-                y: record.y,
-                z: record.z,
-                pitch: record.pitch,
-                roll: record.roll,
-                yaw: record.yaw,
-            };
+fn i32_at(bytes: &[u8], at: usize) -> Option<i32> {
+    let b = bytes.get(at..at + 4)?;
+    Some(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
 
-            if let Some(first_def) = flight.features.get(&record.uid) {
-                if *first_def != feature {
-                    trace!(
-                        "Feature {} defined multiple times ({:?} -> {:?})! Ignoring subsequent ones",
-                        record.uid,
-                        first_def,
-                        feature
-                    );
-                }
-                return Ok(true);
-            }
-
-            trace!("New feature {}: {:?}", record.uid, feature);
-            assert!(flight.features.insert(record.uid, feature).is_none());
-        }
-        REC_TYPE_TRACER_START => {
-            let record = TracerStartRecord::parse(r)?;
-            let event = GeneralEvent {
-                type_byte,
-                start: time,
-                stop: time + 5.0, // Should we make this configurable?
-                x: record.x,
-                y: record.y,
-                z: record.z,
-                dx: record.dx,
-                dy: record.dy,
-                dz: record.dz,
-                ..Default::default()
-            };
-            trace!("Tracer start: {:?}", event);
-            flight.general_events.push(event);
-        }
-        REC_TYPE_STATIONARY_SFX => {
-            let record = StationarySoundRecord::parse(r)?;
-            let event = GeneralEvent {
-                type_byte,
-                start: time,
-                stop: time + record.ttl,
-                kind: record.kind,
-                x: record.x,
-                y: record.y,
-                z: record.z,
-                scale: record.scale,
-                ..Default::default()
-            };
-            trace!("Stationary sound: {:?}", event);
-            flight.general_events.push(event);
-        }
-        REC_TYPE_MOVING_SFX => {
-            let record = MovingSoundRecord::parse(r)?;
-            let event = GeneralEvent {
-                type_byte,
-                start: time,
-                stop: time + record.ttl,
-                kind: record.kind,
-                user: record.user,
-                flags: record.flags,
-                x: record.x,
-                y: record.y,
-                z: record.z,
-                dx: record.dx,
-                dy: record.dy,
-                dz: record.dz,
-                scale: record.scale,
-                ..Default::default()
-            };
-            trace!("Moving sound: {:?}", event);
-            flight.general_events.push(event);
-        }
-        REC_TYPE_SWITCH => {
-            let record = SwitchRecord::parse(r)?;
-            let entity = flight
-                .entities
-                .entry(record.uid)
-                .or_insert_with(Default::default);
-
-            let payload = EntityEventPayload::SwitchEvent(SwitchEvent {
-                switch_number: record.switch_number,
-                new_switch_value: record.new_switch_value,
-                previous_switch_value: record.previous_switch_value,
-            });
+fn write_record_head<W: Write>(type_byte: u8, time: f32, w: &mut W) -> Result<()> {
+    write_u8(type_byte, w)?;
+    write_f32(time, w)?;
+    Ok(())
+}
 
-            let event = EntityEvent { time, payload };
-            trace!("{}: {:?}", record.uid, event);
-            entity.events.push(event);
-        }
-        REC_TYPE_DOF => {
-            let record = DofRecord::parse(r)?;
-            let entity = flight
-                .entities
-                .entry(record.uid)
-                .or_insert_with(Default::default);
-
-            let payload = EntityEventPayload::DofEvent(DofEvent {
-                dof_number: record.dof_number,
-                new_dof_value: record.new_dof_value,
-                previous_dof_value: record.previous_dof_value,
-            });
+/// A record to write (see [`Flight::write()`]), borrowed from the flight.
+#[derive(Debug, Copy, Clone)]
+enum RecordRef<'a> {
+    Feature(i32, &'a FeatureData),
+    Position(i32, &'a EntityPositionData, &'a EntityPositionUpdate),
+    Event(i32, i32, &'a EntityEvent),
+    General(&'a GeneralEvent),
+    FeatureStatus(&'a FeatureEvent),
+}
 
-            let event = EntityEvent { time, payload };
-            trace!("{}: {:?}", record.uid, event);
-            entity.events.push(event);
-        }
-        REC_TYPE_TOD_OFFSET => flight.tod_offset = time,
-        REC_TYPE_FEATURE_STATUS => {
-            let record = FeatureEventRecord::read(r)?;
-            let event = FeatureEvent {
-                time,
-                feature_uid: record.uid,
-                new_status: record.new_status,
-                previous_status: record.previous_status,
-            };
-            // Look up the feature by its UID
-            if !flight.features.contains_key(&record.uid) {
-                trace!("No feature for {:?}", event);
-                return Ok(true);
-            }
-            trace!("Feature event: {:?}", event);
-            flight.feature_events.push(event);
+impl RecordRef<'_> {
+    fn time(&self) -> f32 {
+        match self {
+            RecordRef::Feature(_, feature) => feature.time,
+            RecordRef::Position(_, _, update) => update.time,
+            RecordRef::Event(_, _, event) => event.time,
+            RecordRef::General(event) => event.start,
+            RecordRef::FeatureStatus(event) => event.time,
         }
-        REC_TYPE_CALLSIGN_LIST => {
-            if !flight.callsigns.is_empty() {
-                warn!("Multiple callsign lists found, using the latest");
-                flight.callsigns.clear();
-            }
-
-            // Callsign data is a sparse array where indexing by ID
-            // gives you name and faction.
-            let callsign_array = parse_callsigns(r)?;
-
-            // Callsigns are always written at the end,
-            // so we can safely assume the entity and feature maps are filled out.
+    }
 
-            for feature_key in flight.features.keys() {
-                let index = *feature_key as usize;
-                if index >= callsign_array.len() {
-                    continue;
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        match *self {
+            RecordRef::Feature(uid, feature) => {
+                write_record_head(REC_TYPE_FEATURE_POSITION, feature.time, w)?;
+                FeaturePositionRecord {
+                    kind: feature.kind,
+                    uid,
+                    lead_uid: feature.lead_uid,
+                    slot: feature.slot,
+                    special_flags: feature.special_flags,
+                    x: feature.x,
+                    y: feature.y,
+                    z: feature.z,
+                    yaw: feature.yaw,
+                    pitch: feature.pitch,
+                    roll: feature.roll,
                 }
-                let callsign = callsign_array[index];
-                if callsign == CallsignRecord::default() {
-                    continue;
+                .write(w)?;
+            }
+            RecordRef::Position(uid, data, update) => {
+                let type_byte = match data.flags {
+                    0 => REC_TYPE_GENERAL_POSITION,
+                    ENTITY_FLAG_MISSILE => REC_TYPE_MISSILE_POSITION,
+                    ENTITY_FLAG_AIRCRAFT => REC_TYPE_AIRCRAFT_POSITION,
+                    ENTITY_FLAG_CHAFF => REC_TYPE_CHAFF_POSITION,
+                    ENTITY_FLAG_FLARE => REC_TYPE_FLARE_POSITION,
+                    wut => bail!(
+                        "Entity {} has flags {:#x}, which FLT files can't store",
+                        uid,
+                        wut
+                    ),
+                };
+                write_record_head(type_byte, update.time, w)?;
+                PositionRecord {
+                    kind: data.kind,
+                    uid,
+                    x: update.x,
+                    y: update.y,
+                    z: update.z,
+                    yaw: update.yaw,
+                    pitch: update.pitch,
+                    roll: update.roll,
+                }
+                .write(w)?;
+                if type_byte == REC_TYPE_AIRCRAFT_POSITION {
+                    write_i32(update.radar_target, w)?;
                 }
-                assert!(flight.callsigns.insert(*feature_key, callsign).is_none());
             }
-
-            for entity_key in flight.entities.keys() {
-                let index = *entity_key as usize;
-                if index >= callsign_array.len() {
-                    continue;
+            RecordRef::Event(uid, kind, event) => match event.payload {
+                EntityEventPayload::SwitchEvent(switch) => {
+                    write_record_head(REC_TYPE_SWITCH, event.time, w)?;
+                    SwitchRecord {
+                        kind,
+                        uid,
+                        switch_number: switch.switch_number,
+                        new_switch_value: switch.new_switch_value,
+                        previous_switch_value: switch.previous_switch_value,
+                    }
+                    .write(w)?;
                 }
-                let callsign = callsign_array[index];
-                if callsign == CallsignRecord::default() {
-                    continue;
+                EntityEventPayload::DofEvent(dof) => {
+                    write_record_head(REC_TYPE_DOF, event.time, w)?;
+                    DofRecord {
+                        kind,
+                        uid,
+                        dof_number: dof.dof_number,
+                        new_dof_value: dof.new_dof_value,
+                        previous_dof_value: dof.previous_dof_value,
+                    }
+                    .write(w)?;
                 }
-
-                // We're inserting entities second so that if features and entities
-                // share "unique" IDs, and callsign data _doesn't_ match,
-                // we defer to the entity's.
-                // We probably care about it being correct more than a static feature.
-                let previous = flight.callsigns.insert(*entity_key, callsign);
-                if let Some(p) = previous {
-                    warn!(
-                        r#"FLT file contains an entity and a feature that share a "unique" ID of {}..."#,
-                        *entity_key
-                    );
-                    if callsign != p {
-                        warn!(
-                            "...and the callsign data doesn't match: {:?}, {:?}",
-                            callsign, p
-                        );
+            },
+            RecordRef::General(event) => {
+                write_record_head(event.type_byte, event.start, w)?;
+                match event.type_byte {
+                    // Tracers always last five seconds (see `read_record()`),
+                    // so there's no stop time to write.
+                    REC_TYPE_TRACER_START => TracerStartRecord {
+                        x: event.x,
+                        y: event.y,
+                        z: event.z,
+                        dx: event.dx,
+                        dy: event.dy,
+                        dz: event.dz,
+                    }
+                    .write(w)?,
+                    REC_TYPE_STATIONARY_SFX => StationarySoundRecord {
+                        kind: event.kind,
+                        x: event.x,
+                        y: event.y,
+                        z: event.z,
+                        ttl: event.stop - event.start,
+                        scale: event.scale,
                     }
+                    .write(w)?,
+                    REC_TYPE_MOVING_SFX => MovingSoundRecord {
+                        kind: event.kind,
+                        user: event.user,
+                        flags: event.flags,
+                        x: event.x,
+                        y: event.y,
+                        z: event.z,
+                        dx: event.dx,
+                        dy: event.dy,
+                        dz: event.dz,
+                        ttl: event.stop - event.start,
+                        scale: event.scale,
+                    }
+                    .write(w)?,
+                    wut => bail!("General event type {} can't be written to a FLT", wut),
                 }
             }
-        }
-        wut => {
-            bail!("Unknown enity type {} (0-13 are valid)", wut);
-        }
-    };
-    Ok(true)
+            RecordRef::FeatureStatus(event) => {
+                write_record_head(REC_TYPE_FEATURE_STATUS, event.time, w)?;
+                FeatureEventRecord {
+                    uid: event.feature_uid,
+                    new_status: event.new_status,
+                    previous_status: event.previous_status,
+                }
+                .write(w)?;
+            }
+        };
+        Ok(())
+    }
 }
 
-fn warn_on(e: Error) {
-    if let Some(io_err) = e.downcast_ref::<io::Error>() {
-        if io_err.kind() == io::ErrorKind::UnexpectedEof {
-            warn!("Reached end of file in the middle of a record");
-            return;
+/// Orders records from different streams when merging them in [`Flight::write()`]
+#[derive(Debug, Copy, Clone)]
+struct RecordKey {
+    time: f32,
+    /// Breaks ties between records at the same time,
+    /// e.g., so features are defined before their events.
+    priority: u8,
+    stream: usize,
+}
+
+impl RecordKey {
+    fn new(record: &RecordRef, stream: usize, flight: &Flight) -> Self {
+        let (time, priority) = match record {
+            RecordRef::Feature(..) => (record.time(), 0),
+            RecordRef::Position(..) => (record.time(), 1),
+            RecordRef::Event(..) => (record.time(), 2),
+            RecordRef::General(..) => (record.time(), 3),
+            // The parser ignores feature events for features it hasn't seen yet.
+            // Make sure they come after their feature.
+            RecordRef::FeatureStatus(event) => {
+                let time = match flight.features.get(&event.feature_uid) {
+                    Some(feature) if feature.time > event.time => feature.time,
+                    _ => event.time,
+                };
+                (time, 4)
+            }
+        };
+        Self {
+            time,
+            priority,
+            stream,
         }
     }
-    warn!("Error reading flight: {}", e);
 }
 
+impl Ord for RecordKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.time
+            .total_cmp(&other.time)
+            .then_with(|| self.priority.cmp(&other.priority))
+            .then_with(|| self.stream.cmp(&other.stream))
+    }
+}
+
+impl PartialOrd for RecordKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RecordKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for RecordKey {}
+
 // Records read out of the `.flt` file.
 
 #[derive(Debug)]
@@ -805,26 +3367,29 @@ struct PositionRecord {
 }
 
 impl PositionRecord {
-    fn parse<R: Read>(r: &mut R) -> Result<Self> {
-        let kind = read_i32(r)?;
-        let uid = read_i32(r)?;
-        let x = read_f32(r)?;
-        let y = read_f32(r)?;
-        let z = read_f32(r)?;
-        let yaw = read_f32(r)?;
-        let pitch = read_f32(r)?;
-        let roll = read_f32(r)?;
-
-        Ok(Self {
-            kind,
-            uid,
-            x,
-            y,
-            z,
-            yaw,
-            pitch,
-            roll,
-        })
+    fn decode(b: &[u8; 32]) -> Self {
+        Self {
+            kind: le_i32(b, 0),
+            uid: le_i32(b, 4),
+            x: le_f32(b, 8),
+            y: le_f32(b, 12),
+            z: le_f32(b, 16),
+            yaw: le_f32(b, 20),
+            pitch: le_f32(b, 24),
+            roll: le_f32(b, 28),
+        }
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_i32(self.kind, w)?;
+        write_i32(self.uid, w)?;
+        write_f32(self.x, w)?;
+        write_f32(self.y, w)?;
+        write_f32(self.z, w)?;
+        write_f32(self.yaw, w)?;
+        write_f32(self.pitch, w)?;
+        write_f32(self.roll, w)?;
+        Ok(())
     }
 }
 
@@ -843,32 +3408,35 @@ struct FeaturePositionRecord {
 }
 
 impl FeaturePositionRecord {
-    fn parse<R: Read>(r: &mut R) -> Result<Self> {
-        let kind = read_i32(r)?;
-        let uid = read_i32(r)?;
-        let lead_uid = read_i32(r)?;
-        let slot = read_i32(r)?;
-        let special_flags = read_u32(r)?;
-        let x = read_f32(r)?;
-        let y = read_f32(r)?;
-        let z = read_f32(r)?;
-        let yaw = read_f32(r)?;
-        let pitch = read_f32(r)?;
-        let roll = read_f32(r)?;
-
-        Ok(Self {
-            kind,
-            uid,
-            lead_uid,
-            slot,
-            special_flags,
-            x,
-            y,
-            z,
-            yaw,
-            pitch,
-            roll,
-        })
+    fn decode(b: &[u8; 44]) -> Self {
+        Self {
+            kind: le_i32(b, 0),
+            uid: le_i32(b, 4),
+            lead_uid: le_i32(b, 8),
+            slot: le_i32(b, 12),
+            special_flags: le_u32(b, 16),
+            x: le_f32(b, 20),
+            y: le_f32(b, 24),
+            z: le_f32(b, 28),
+            yaw: le_f32(b, 32),
+            pitch: le_f32(b, 36),
+            roll: le_f32(b, 40),
+        }
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_i32(self.kind, w)?;
+        write_i32(self.uid, w)?;
+        write_i32(self.lead_uid, w)?;
+        write_i32(self.slot, w)?;
+        write_u32(self.special_flags, w)?;
+        write_f32(self.x, w)?;
+        write_f32(self.y, w)?;
+        write_f32(self.z, w)?;
+        write_f32(self.yaw, w)?;
+        write_f32(self.pitch, w)?;
+        write_f32(self.roll, w)?;
+        Ok(())
     }
 }
 #[derive(Debug)]
@@ -882,22 +3450,25 @@ struct TracerStartRecord {
 }
 
 impl TracerStartRecord {
-    fn parse<R: Read>(r: &mut R) -> Result<Self> {
-        let x = read_f32(r)?;
-        let y = read_f32(r)?;
-        let z = read_f32(r)?;
-        let dx = read_f32(r)?;
-        let dy = read_f32(r)?;
-        let dz = read_f32(r)?;
-
-        Ok(Self {
-            x,
-            y,
-            z,
-            dx,
-            dy,
-            dz,
-        })
+    fn decode(b: &[u8; 24]) -> Self {
+        Self {
+            x: le_f32(b, 0),
+            y: le_f32(b, 4),
+            z: le_f32(b, 8),
+            dx: le_f32(b, 12),
+            dy: le_f32(b, 16),
+            dz: le_f32(b, 20),
+        }
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_f32(self.x, w)?;
+        write_f32(self.y, w)?;
+        write_f32(self.z, w)?;
+        write_f32(self.dx, w)?;
+        write_f32(self.dy, w)?;
+        write_f32(self.dz, w)?;
+        Ok(())
     }
 }
 
@@ -912,22 +3483,25 @@ struct StationarySoundRecord {
 }
 
 impl StationarySoundRecord {
-    fn parse<R: Read>(r: &mut R) -> Result<Self> {
-        let kind = read_i32(r)?;
-        let x = read_f32(r)?;
-        let y = read_f32(r)?;
-        let z = read_f32(r)?;
-        let ttl = read_f32(r)?;
-        let scale = read_f32(r)?;
-
-        Ok(Self {
-            kind,
-            x,
-            y,
-            z,
-            ttl,
-            scale,
-        })
+    fn decode(b: &[u8; 24]) -> Self {
+        Self {
+            kind: le_i32(b, 0),
+            x: le_f32(b, 4),
+            y: le_f32(b, 8),
+            z: le_f32(b, 12),
+            ttl: le_f32(b, 16),
+            scale: le_f32(b, 20),
+        }
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_i32(self.kind, w)?;
+        write_f32(self.x, w)?;
+        write_f32(self.y, w)?;
+        write_f32(self.z, w)?;
+        write_f32(self.ttl, w)?;
+        write_f32(self.scale, w)?;
+        Ok(())
     }
 }
 
@@ -947,32 +3521,35 @@ struct MovingSoundRecord {
 }
 
 impl MovingSoundRecord {
-    fn parse<R: Read>(r: &mut R) -> Result<Self> {
-        let kind = read_i32(r)?;
-        let user = read_i32(r)?;
-        let flags = read_u32(r)?;
-        let x = read_f32(r)?;
-        let y = read_f32(r)?;
-        let z = read_f32(r)?;
-        let dx = read_f32(r)?;
-        let dy = read_f32(r)?;
-        let dz = read_f32(r)?;
-        let ttl = read_f32(r)?;
-        let scale = read_f32(r)?;
-
-        Ok(Self {
-            kind,
-            user,
-            flags,
-            x,
-            y,
-            z,
-            dx,
-            dy,
-            dz,
-            ttl,
-            scale,
-        })
+    fn decode(b: &[u8; 44]) -> Self {
+        Self {
+            kind: le_i32(b, 0),
+            user: le_i32(b, 4),
+            flags: le_u32(b, 8),
+            x: le_f32(b, 12),
+            y: le_f32(b, 16),
+            z: le_f32(b, 20),
+            dx: le_f32(b, 24),
+            dy: le_f32(b, 28),
+            dz: le_f32(b, 32),
+            ttl: le_f32(b, 36),
+            scale: le_f32(b, 40),
+        }
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_i32(self.kind, w)?;
+        write_i32(self.user, w)?;
+        write_u32(self.flags, w)?;
+        write_f32(self.x, w)?;
+        write_f32(self.y, w)?;
+        write_f32(self.z, w)?;
+        write_f32(self.dx, w)?;
+        write_f32(self.dy, w)?;
+        write_f32(self.dz, w)?;
+        write_f32(self.ttl, w)?;
+        write_f32(self.scale, w)?;
+        Ok(())
     }
 }
 
@@ -984,15 +3561,19 @@ struct FeatureEventRecord {
 }
 
 impl FeatureEventRecord {
-    fn read<R: Read>(r: &mut R) -> Result<Self> {
-        let uid = read_i32(r)?;
-        let new_status = read_i32(r)?;
-        let previous_status = read_i32(r)?;
-        Ok(Self {
-            uid,
-            new_status,
-            previous_status,
-        })
+    fn decode(b: &[u8; 12]) -> Self {
+        Self {
+            uid: le_i32(b, 0),
+            new_status: le_i32(b, 4),
+            previous_status: le_i32(b, 8),
+        }
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_i32(self.uid, w)?;
+        write_i32(self.new_status, w)?;
+        write_i32(self.previous_status, w)?;
+        Ok(())
     }
 }
 
@@ -1006,20 +3587,23 @@ struct SwitchRecord {
 }
 
 impl SwitchRecord {
-    fn parse<R: Read>(r: &mut R) -> Result<Self> {
-        let kind = read_i32(r)?;
-        let uid = read_i32(r)?;
-        let switch_number = read_i32(r)?;
-        let new_switch_value = read_i32(r)?;
-        let previous_switch_value = read_i32(r)?;
-
-        Ok(Self {
-            kind,
-            uid,
-            switch_number,
-            new_switch_value,
-            previous_switch_value,
-        })
+    fn decode(b: &[u8; 20]) -> Self {
+        Self {
+            kind: le_i32(b, 0),
+            uid: le_i32(b, 4),
+            switch_number: le_i32(b, 8),
+            new_switch_value: le_i32(b, 12),
+            previous_switch_value: le_i32(b, 16),
+        }
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_i32(self.kind, w)?;
+        write_i32(self.uid, w)?;
+        write_i32(self.switch_number, w)?;
+        write_i32(self.new_switch_value, w)?;
+        write_i32(self.previous_switch_value, w)?;
+        Ok(())
     }
 }
 
@@ -1033,50 +3617,71 @@ struct DofRecord {
 }
 
 impl DofRecord {
-    fn parse<R: Read>(r: &mut R) -> Result<Self> {
-        let kind = read_i32(r)?;
-        let uid = read_i32(r)?;
-        let dof_number = read_i32(r)?;
-        let new_dof_value = read_f32(r)?;
-        let previous_dof_value = read_f32(r)?;
-
-        Ok(Self {
-            kind,
-            uid,
-            dof_number,
-            new_dof_value,
-            previous_dof_value,
-        })
+    fn decode(b: &[u8; 20]) -> Self {
+        Self {
+            kind: le_i32(b, 0),
+            uid: le_i32(b, 4),
+            dof_number: le_i32(b, 8),
+            new_dof_value: le_f32(b, 12),
+            previous_dof_value: le_f32(b, 16),
+        }
     }
-}
 
-fn parse_callsigns<R: Read>(r: &mut R) -> Result<Vec<CallsignRecord>> {
-    let callsign_count = read_i32(r)?;
-    ensure!(
-        callsign_count >= 0,
-        "Negative ({}) callsign count!",
-        callsign_count
-    );
+    fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_i32(self.kind, w)?;
+        write_i32(self.uid, w)?;
+        write_i32(self.dof_number, w)?;
+        write_f32(self.new_dof_value, w)?;
+        write_f32(self.previous_dof_value, w)?;
+        Ok(())
+    }
+}
 
-    let mut callsigns = Vec::with_capacity(callsign_count as usize);
+fn parse_callsigns<R: Read>(callsign_count: usize, r: &mut R) -> io::Result<Vec<CallsignRecord>> {
+    // Don't trust the count for allocation size - a garbage file could
+    // have us reserving gigabytes.
+    let mut callsigns = Vec::with_capacity(std::cmp::min(callsign_count, 64 * 1024));
 
     for _ in 0..callsign_count {
-        callsigns.push(CallsignRecord::read(r)?);
+        // Same as CallsignRecord::read(), but keeping the io::Error.
+        let mut label: [u8; 16] = [0; 16];
+        r.read_exact(&mut label)?;
+        let team_color = read_i32(r)?;
+        callsigns.push(CallsignRecord { label, team_color });
     }
     Ok(callsigns)
 }
 
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
-pub struct CallsignRecord {
-    pub label: [u8; 16],
-    pub team_color: i32,
+fn decode_callsigns(list: &[u8]) -> Vec<CallsignRecord> {
+    list.chunks_exact(CallsignRecord::SIZE as usize)
+        .map(|b| {
+            let mut label: [u8; 16] = [0; 16];
+            label.copy_from_slice(&b[..16]);
+            let team_color = i32::from_le_bytes([b[16], b[17], b[18], b[19]]);
+            CallsignRecord { label, team_color }
+        })
+        .collect()
 }
 
-impl CallsignRecord {
-    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
-        let mut label: [u8; 16] = [0; 16];
-        r.read_exact(&mut label)?;
-        let team_color = read_i32(r)?;
-        Ok(Self { label, team_color })
-    }
+// The records decode straight from fixed-size arrays, so once we have one,
+// the compiler can check every field's bounds up front.
+
+/// The first `N` bytes of `b`, if it has that many
+fn fixed<const N: usize>(b: &[u8]) -> Option<&[u8; N]> {
+    <&[u8; N]>::try_from(b.get(..N)?).ok()
+}
+
+#[inline]
+fn le_i32<const N: usize>(b: &[u8; N], at: usize) -> i32 {
+    i32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+#[inline]
+fn le_u32<const N: usize>(b: &[u8; N], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+#[inline]
+fn le_f32<const N: usize>(b: &[u8; N], at: usize) -> f32 {
+    f32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
 }
@@ -0,0 +1,113 @@
+//! Converts Falcon BMS flight recordings (`.flt`) into their replay format (`.vhs`)
+//!
+//! The usual pipeline is:
+//!
+//! 1. [`Flight::parse()`](flt::Flight::parse) each FLT file from a reader,
+//!    or [`Flight::parse_bytes()`](flt::Flight::parse_bytes) from a byte slice
+//!    like a memory mapping. Big files parse faster with
+//!    [`Flight::parse_parallel()`](flt::Flight::parse_parallel).
+//!
+//! 2. [`merge_flights()`] to stitch FLT files BMS chunked up
+//!    back into single flights. [`chronological_order()`] says what order
+//!    to give them in if they might not be in the order they were recorded.
+//!
+//! 3. [`vhs::write()`] (to a file) or [`vhs::write_to()`] (to any writer)
+//!    each resulting flight. [`vhs::write_split()`] splits flights too big
+//!    for one VHS file into several. [`acmi::write_to()`] writes
+//!    Tacview's text ACMI format instead.
+//!
+//! For recordings too long to comfortably hold in memory,
+//! [`vhs::StreamingWrite`] does steps 1 and 3 (but not 2) in two passes over the FLT.
+//!
+//! Existing VHS files can be loaded back into a [`Flight`] with [`vhs::read()`],
+//! and any flight can be written back out as a FLT with
+//! [`Flight::write()`](flt::Flight::write).
+
+use std::{ops::Range, path::PathBuf, time::Instant};
+
+use log::*;
+
+pub mod acmi;
+mod assignment;
+pub mod flt;
+pub mod vhs;
+
+pub use flt::Flight;
+
+pub fn print_timing(msg: &str, start: &Instant) {
+    info!("{} took {:.3}s", msg, start.elapsed().as_secs_f32());
+}
+
+/// One or more consecutive flights merged together
+#[derive(Debug, Clone)]
+pub struct MergedFlight {
+    /// Indexes of the flights (passed to [`merge_flights()`])
+    /// that were merged into this one.
+    pub inputs: Range<usize>,
+
+    pub flight: Flight,
+}
+
+/// Returns the order (as indexes into `flights`) they were recorded in:
+/// by when they start, then by when they end.
+/// Flights with the same times keep the order they were given in.
+pub fn chronological_order(flights: &[Flight]) -> Vec<usize> {
+    let mut order = (0..flights.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| {
+        let (a, b) = (&flights[*a], &flights[*b]);
+        a.start_time
+            .total_cmp(&b.start_time)
+            .then(a.end_time.total_cmp(&b.end_time))
+    });
+    order
+}
+
+/// Merges each flight into the one before it, when possible.
+/// (See [`Flight::merge()`](flt::Flight::merge).)
+///
+/// `paths` names each flight for logging, and should be the same length as `flights`.
+/// Returns the merged flights in the order they were given.
+pub fn merge_flights(flights: Vec<Flight>, paths: &[PathBuf]) -> Vec<MergedFlight> {
+    merge_flights_reporting(flights, paths, &flt::MergePolicy::default()).0
+}
+
+/// Like [`merge_flights()`], but merges as `policy` says
+/// and also returns what happened between each pair of consecutive flights.
+/// (See [`Flight::merge_reporting()`](flt::Flight::merge_reporting).)
+pub fn merge_flights_reporting(
+    flights: Vec<Flight>,
+    paths: &[PathBuf],
+    policy: &flt::MergePolicy,
+) -> (Vec<MergedFlight>, Vec<flt::MergeReport>) {
+    assert_eq!(flights.len(), paths.len());
+
+    let mut merged = Vec::new();
+    let mut reports = Vec::new();
+    let mut flights = flights.into_iter().enumerate();
+
+    let (mut starting_index, mut starting) = match flights.next() {
+        Some(first) => first,
+        None => return (merged, reports),
+    };
+
+    for (next_index, next) in flights {
+        let report =
+            starting.merge_reporting(&next, &paths[next_index - 1], &paths[next_index], policy);
+        let was_merged = report.decision == flt::MergeDecision::Merged;
+        reports.push(report);
+        if !was_merged {
+            merged.push(MergedFlight {
+                inputs: starting_index..next_index,
+                flight: starting,
+            });
+            starting_index = next_index;
+            starting = next;
+        }
+    }
+    merged.push(MergedFlight {
+        inputs: starting_index..paths.len(),
+        flight: starting,
+    });
+
+    (merged, reports)
+}
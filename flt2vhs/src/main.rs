@@ -1,5 +1,6 @@
 use std::{
     fs::{self, File},
+    io::{self, Write},
     path::{Path, PathBuf},
     time::Instant,
 };
@@ -7,11 +8,10 @@ use std::{
 use anyhow::*;
 use humansize::{file_size_opts as Sizes, FileSize};
 use log::*;
+use serde_derive::Serialize;
 use structopt::StructOpt;
 
-mod flt;
-mod primitives;
-mod vhs;
+use flt2vhs::{acmi, flt, print_timing, vhs};
 
 /// Converts a FLT file to VHS
 #[derive(Debug, StructOpt)]
@@ -38,13 +38,90 @@ struct Args {
     #[structopt(short, long)]
     force: bool,
 
-    /// The FLT file to read
-    #[structopt(name = "input.flt")]
-    inputs: Vec<PathBuf>,
-}
+    /// Skip over garbage in corrupted FLT files
+    /// instead of stopping at it.
+    #[structopt(short, long, verbatim_doc_comment)]
+    recover: bool,
+
+    /// Convert each FLT file in two passes, so memory use
+    /// doesn't grow with the length of the recording.
+    /// Slower, and doesn't merge FLT files
+    /// or split ones too big for a single VHS.
+    #[structopt(short, long, verbatim_doc_comment, conflicts_with = "recover")]
+    low_memory: bool,
+
+    /// Write Tacview's text ACMI format (.txt.acmi) instead of VHS
+    #[structopt(long, conflicts_with = "low-memory")]
+    acmi: bool,
+
+    /// Don't merge FLT files.
+    #[structopt(long, conflicts_with = "force-merge")]
+    no_merge: bool,
+
+    /// Merge consecutive FLT files no matter how far apart they are in time
+    /// or whether their times of day match.
+    #[structopt(long, verbatim_doc_comment)]
+    force_merge: bool,
+
+    /// Merge FLT files different players recorded of the same mission
+    /// at the same time, instead of ones picking up where the last left off.
+    #[structopt(
+        long,
+        verbatim_doc_comment,
+        conflicts_with_all = &["no-merge", "force-merge"]
+    )]
+    overlapping: bool,
+
+    /// Merge FLT files up to this many seconds apart [default: 1]
+    #[structopt(long, value_name = "seconds")]
+    max_gap: Option<f32>,
+
+    /// How far (in feet) an aircraft can be from where we expected it
+    /// and still match one in the last FLT file [default: 5280]
+    #[structopt(long, value_name = "feet", verbatim_doc_comment)]
+    aircraft_radius: Option<f32>,
+
+    /// Ditto for missiles [default: 5280]
+    #[structopt(long, value_name = "feet")]
+    missile_radius: Option<f32>,
 
-pub fn print_timing(msg: &str, start: &Instant) {
-    info!("{} took {:.3}s", msg, start.elapsed().as_secs_f32());
+    /// Ditto for chaff and flares [default: 5280]
+    #[structopt(long, value_name = "feet")]
+    countermeasure_radius: Option<f32>,
+
+    /// Ditto for ground vehicles, ships, and everything else [default: 5280]
+    #[structopt(long, value_name = "feet")]
+    ground_radius: Option<f32>,
+
+    /// What to do when consecutive FLT files have different times of day:
+    /// refuse to merge them, warn but merge them, or rebase the second
+    /// onto the first's time of day and then see if they should be merged.
+    #[structopt(long, value_name = "refuse/warn/rebase", verbatim_doc_comment)]
+    #[structopt(default_value = "warn")]
+    tod_mismatch: flt::TodMismatch,
+
+    /// Fill in where entities cross from one FLT file to the next
+    /// with updates this many seconds apart, so they don't jump
+    /// between where they left off and where they picked up.
+    #[structopt(long, value_name = "seconds", verbatim_doc_comment)]
+    smooth_seams: Option<f32>,
+
+    /// Write what happened when merging FLT files to the given JSON file:
+    /// which FLT files went into each VHS, whether each consecutive pair
+    /// was merged, and if so, what each entity and feature in the second
+    /// was matched to.
+    #[structopt(
+        long,
+        name = "file.json",
+        verbatim_doc_comment,
+        conflicts_with = "low-memory"
+    )]
+    merge_report: Option<PathBuf>,
+
+    /// The FLT files to read, in any order.
+    /// They're merged in the order they were recorded.
+    #[structopt(name = "input.flt", verbatim_doc_comment)]
+    inputs: Vec<PathBuf>,
 }
 
 fn main() {
@@ -60,18 +137,37 @@ fn run() -> Result<()> {
     let args = Args::from_args();
     logsetup::init_logger(args.verbose, args.timestamps, args.color);
 
+    if args.low_memory {
+        for input in &args.inputs {
+            convert_streaming(input, &args)?;
+        }
+        info!(
+            "All files converted in {:.3}s",
+            start_time.elapsed().as_secs_f32(),
+        );
+        return Ok(());
+    }
+
     let parse_start = Instant::now();
 
-    let mut flights: Vec<_> = args
+    let flights: Vec<_> = args
         .inputs
         .iter()
         .map(|input| {
             info!("Parsing {}", input.display());
 
             let mapping = open_flt(input)?;
-            let parsed_flight = flt::Flight::parse(&*mapping);
+            let parsed_flight = if args.recover {
+                flt::Flight::parse_recovering(&mapping)
+            } else {
+                flt::Flight::parse_parallel(&mapping)
+            };
             drop(mapping);
 
+            if let Some(corruption) = &parsed_flight.corruption {
+                warn_corrupted(input, corruption);
+            }
+
             Ok(parsed_flight)
         })
         .collect::<Result<Vec<_>>>()?;
@@ -81,27 +177,50 @@ fn run() -> Result<()> {
         &parse_start,
     );
 
-    let mut starting_index = 0;
-    let mut next_index = 1;
-
-    while starting_index < flights.len() {
-        // All hail the borrow checker
-        let (left, right) = flights.split_at_mut(next_index);
-        let starting = &mut left[starting_index];
-
-        if right.is_empty()
-            || !starting.merge(
-                &right[0],
-                &args.inputs[starting_index],
-                &args.inputs[next_index],
-            )
-        {
-            write_flight(&args.inputs[starting_index..next_index], starting, &args)?;
-            starting_index = next_index;
-            next_index = starting_index + 1;
-        } else {
-            next_index += 1;
-        }
+    // Merge flights in the order they were recorded, whatever order we got them in.
+    let order = flt2vhs::chronological_order(&flights);
+    let inputs = order
+        .iter()
+        .map(|i| args.inputs[*i].clone())
+        .collect::<Vec<_>>();
+    if inputs != args.inputs {
+        info!(
+            "Merging in the order the files were recorded: {}",
+            inputs
+                .iter()
+                .map(|i| i.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    let mut flights = flights.into_iter().map(Some).collect::<Vec<_>>();
+    let flights = order
+        .iter()
+        .map(|i| flights[*i].take().unwrap())
+        .collect::<Vec<_>>();
+
+    let policy = merge_policy(&args)?;
+    if policy.mode == flt::MergeMode::Auto || policy.mode == flt::MergeMode::Force {
+        warn_overlaps(&flights, &inputs);
+    }
+
+    let (merged_flights, merge_reports) =
+        flt2vhs::merge_flights_reporting(flights, &inputs, &policy);
+    if let Some(report_path) = &args.merge_report {
+        write_merge_report(report_path, &inputs, &merged_flights, &merge_reports, &args)?;
+    }
+
+    for merged in merged_flights {
+        let flight = &merged.flight;
+        write_flight(&inputs[merged.inputs], flight, &args, |open| {
+            if args.acmi {
+                let fh = open(0)?;
+                acmi::write_to(flight, io::BufWriter::new(&fh))?;
+                return Ok(vec![fh.metadata()?.len()]);
+            }
+            let sizes = vhs::write_split(flight, vhs::MAX_FILE_LENGTH, open)?;
+            Ok(sizes.into_iter().map(u64::from).collect())
+        })?;
     }
 
     info!(
@@ -111,7 +230,152 @@ fn run() -> Result<()> {
     Ok(())
 }
 
-fn output_name(input: &Path) -> Result<PathBuf> {
+fn merge_policy(args: &Args) -> Result<flt::MergePolicy> {
+    let mut policy = flt::MergePolicy {
+        tod_mismatch: args.tod_mismatch,
+        ..Default::default()
+    };
+    if args.no_merge {
+        policy.mode = flt::MergeMode::Never;
+    } else if args.force_merge {
+        policy.mode = flt::MergeMode::Force;
+    } else if args.overlapping {
+        policy.mode = flt::MergeMode::Overlapping;
+    }
+
+    if let Some(max_gap) = args.max_gap {
+        ensure!(max_gap >= 0.0, "--max-gap can't be negative");
+        policy.max_gap = max_gap;
+    }
+
+    if let Some(step) = args.smooth_seams {
+        ensure!(step > 0.0, "--smooth-seams must be positive");
+        policy.seam_step = Some(step);
+    }
+
+    let radii = [
+        (
+            args.aircraft_radius,
+            &mut policy.match_radius.aircraft,
+            "--aircraft-radius",
+        ),
+        (
+            args.missile_radius,
+            &mut policy.match_radius.missile,
+            "--missile-radius",
+        ),
+        (
+            args.countermeasure_radius,
+            &mut policy.match_radius.countermeasure,
+            "--countermeasure-radius",
+        ),
+        (
+            args.ground_radius,
+            &mut policy.match_radius.ground,
+            "--ground-radius",
+        ),
+    ];
+    for (arg, radius, name) in radii {
+        if let Some(arg) = arg {
+            ensure!(arg > 0.0, "{} must be positive", name);
+            *radius = arg;
+        }
+    }
+    Ok(policy)
+}
+
+/// Warns about flights that overlap in time,
+/// since BMS doesn't split recordings that way.
+fn warn_overlaps(flights: &[flt::Flight], inputs: &[PathBuf]) {
+    for (pair, names) in flights.windows(2).zip(inputs.windows(2)) {
+        let overlap = pair[0].end_time - pair[1].start_time;
+        if overlap > 0.0 {
+            warn!(
+                "{} and {} overlap by {:.1}s. If different players recorded them \
+                 during the same mission, try --overlapping",
+                names[0].display(),
+                names[1].display(),
+                overlap
+            );
+        }
+    }
+}
+
+/// What goes in the file from `--merge-report`
+#[derive(Serialize)]
+struct MergeReportFile<'a> {
+    /// Which FLT files went into each output file
+    outputs: Vec<OutputReport<'a>>,
+    /// What happened between each pair of consecutive FLT files
+    merges: &'a [flt::MergeReport],
+}
+
+#[derive(Serialize)]
+struct OutputReport<'a> {
+    /// The VHS (or the first of them, if it had to be split) or ACMI file
+    output: PathBuf,
+    inputs: &'a [PathBuf],
+}
+
+fn write_merge_report(
+    path: &Path,
+    inputs: &[PathBuf],
+    merged_flights: &[flt2vhs::MergedFlight],
+    merges: &[flt::MergeReport],
+    args: &Args,
+) -> Result<()> {
+    let outputs = merged_flights
+        .iter()
+        .map(|merged| {
+            let inputs = &inputs[merged.inputs.clone()];
+            Ok(OutputReport {
+                output: output_name(&inputs[0], args)?,
+                inputs,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let report = MergeReportFile { outputs, merges };
+
+    let fh = File::create(path)
+        .with_context(|| format!("Couldn't create merge report {}", path.display()))?;
+    let mut w = io::BufWriter::new(fh);
+    serde_json::to_writer_pretty(&mut w, &report)
+        .map_err(io::Error::from)
+        .and_then(|()| w.flush())
+        .with_context(|| format!("Couldn't write merge report {}", path.display()))?;
+    info!("Wrote merge report to {}", path.display());
+    Ok(())
+}
+
+/// Converts the given FLT file without holding the whole flight in memory
+/// (see [`vhs::StreamingWrite`]).
+fn convert_streaming(input: &Path, args: &Args) -> Result<()> {
+    info!("Scanning {}", input.display());
+    let scan_start = Instant::now();
+
+    let mapping = open_flt(input)?;
+    let streaming = vhs::StreamingWrite::scan(&mapping);
+    if let Some(corruption) = &streaming.flight().corruption {
+        warn_corrupted(input, corruption);
+    }
+    print_timing(&format!("Scanning {}", input.display()), &scan_start);
+
+    write_flight(&[input.to_owned()], streaming.flight(), args, |open| {
+        Ok(vec![streaming.write(open(0)?)?.into()])
+    })
+}
+
+fn warn_corrupted(input: &Path, corruption: &flt::Corruption) {
+    warn!("{} is corrupted: {}", input.display(), corruption);
+    if corruption.truncated() {
+        warn!("It ends partway through a record (did BMS crash while recording?)");
+    } else if corruption.skipped.is_empty() {
+        warn!("There's garbage in the middle of it, so everything after that is lost");
+        warn!("(Try --recover to skip over the garbage)");
+    }
+}
+
+fn output_name(input: &Path, args: &Args) -> Result<PathBuf> {
     // Path::with_extension just replaces the last one.
     // Replace ALL THE EXTENISONS!
     let name = input
@@ -121,7 +385,7 @@ fn output_name(input: &Path) -> Result<PathBuf> {
         .to_str()
         .ok_or_else(|| anyhow!("Can't remove the extension from {}", input.display()))?;
     Ok(PathBuf::from(
-        as_str.split('.').next().unwrap().to_owned() + ".vhs",
+        as_str.split('.').next().unwrap().to_owned() + if args.acmi { ".txt.acmi" } else { ".vhs" },
     ))
 }
 
@@ -139,20 +403,37 @@ fn open_vhs(to: &Path) -> Result<File> {
         .read(true)
         .write(true)
         .create(true)
+        .truncate(true)
         .open(to)
         .with_context(|| format!("Couldn't open {} to write", to.display()))?;
     Ok(fh)
 }
 
-fn write_flight(inputs: &[PathBuf], flight: &flt::Flight, args: &Args) -> Result<()> {
-    let output = output_name(&inputs[0])?;
+/// The name of the `part`th (counting from 0) VHS a flight is split into:
+/// `output` itself, then `output-2.vhs`, `output-3.vhs`, and so on.
+fn part_name(output: &Path, part: usize) -> PathBuf {
+    if part == 0 {
+        return output.to_owned();
+    }
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!("{}-{}.vhs", stem, part + 1))
+}
+
+/// Writes the given flight to VHS (or ACMI) files named after the first input,
+/// using `write` to do the actual writing.
+///
+/// `write` gets a function to open the `i`th file,
+/// and returns the number of bytes written to each.
+fn write_flight<F>(inputs: &[PathBuf], flight: &flt::Flight, args: &Args, write: F) -> Result<()>
+where
+    F: FnOnce(&mut dyn FnMut(usize) -> Result<File>) -> Result<Vec<u64>>,
+{
+    let output = output_name(&inputs[0], args)?;
 
     let flt_size = inputs
         .iter()
         .map(fs::metadata)
-        .fold(Ok(0), |acc, meta| -> Result<u64, Error> {
-            acc.and_then(|sum| Ok(sum + meta?.len()))
-        })?;
+        .try_fold(0, |sum, meta| -> Result<u64> { Ok(sum + meta?.len()) })?;
 
     let mut size_options = Sizes::CONVENTIONAL;
     size_options.space = false;
@@ -168,43 +449,57 @@ fn write_flight(inputs: &[PathBuf], flight: &flt::Flight, args: &Args) -> Result
         output.display()
     );
 
-    if flight.corrupted {
+    if flight.corrupted() {
         warn!("Flight file is corrupted! Doing what we can with what we have...");
     }
-    if !args.force && flight.corrupted {
-        if inputs[0] == output {
-            bail!(
-                "{} looks like a VHS file! Quitting before we overwrite it",
-                inputs[0].display()
-            );
-        } else if output.exists() {
-            bail!(
-                "Refusing to overwrite {} with a corrupted recording without --force",
-                output.display()
-            );
-        }
-    }
 
     let write_start = Instant::now();
-    let vhs = open_vhs(&output)?;
-    let vhs_size = vhs::write(flight, vhs)?;
+    let mut outputs = Vec::new();
+    let mut open = |part| {
+        let path = part_name(&output, part);
+        if !args.force && flight.corrupted() {
+            if inputs.contains(&path) {
+                bail!(
+                    "{} looks like a VHS file! Quitting before we overwrite it",
+                    path.display()
+                );
+            } else if path.exists() {
+                bail!(
+                    "Refusing to overwrite {} with a corrupted recording without --force",
+                    path.display()
+                );
+            }
+        }
+        let vhs = open_vhs(&path)?;
+        outputs.push(path);
+        Ok(vhs)
+    };
+    let vhs_sizes = write(&mut open)?;
     print_timing(
         &format!(
-            "{} ({}) write",
-            output.display(),
-            vhs_size.file_size(&size_options).unwrap(),
+            "{} write",
+            outputs
+                .iter()
+                .zip(vhs_sizes)
+                .map(|(path, size)| format!(
+                    "{} ({})",
+                    path.display(),
+                    size.file_size(&size_options).unwrap()
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
         ),
         &write_start,
     );
 
-    if flight.corrupted {
+    if flight.corrupted() {
         warn!("Converted corrupted FLT file, resulting VHS may be incomplete");
         std::process::exit(2); // Use a different error code than normal failure
     } else {
         if args.delete {
             for input in inputs {
                 debug!("Deleting {} after its conversion", input.display());
-                fs::remove_file(&input)
+                fs::remove_file(input)
                     .with_context(|| format!("Couldn't remove {}", input.display()))?;
             }
         }
@@ -1,24 +1,31 @@
-//! Writes a flight parsed from a `.flt` file into a `.vhs` file
+//! Writes a flight parsed from a `.flt` file into a `.vhs` file,
+//! and reads `.vhs` files back into flights.
 
+use std::convert::TryFrom;
 use std::io;
 use std::io::prelude::*;
+use std::ops::Range;
 
 use anyhow::*;
 use log::*;
 use rayon::prelude::*;
 use rustc_hash::FxHashMap;
 
+use acmitape::{
+    primitives::*, CountedRead, Dof, GeneralEventHeader, GeneralEventTrailer, Position, Switch,
+    TapeHeader, TimelineEntry, TimelineEntryPayload,
+};
+
 use crate::flt::{self, Flight};
-use crate::primitives::*;
 
-/// The header is 80 bytes long; entities start after.
-const ENTITY_OFFSET: u32 = 80;
-const ENTITY_SIZE: u32 = 36;
-const ENTITY_UPDATE_SIZE: u32 = 41;
-const GENERAL_EVENT_SIZE: u32 = 65;
-const GENERAL_EVENT_TRAILER_SIZE: u32 = 8;
-const FEATURE_EVENT_SIZE: u32 = 16;
-const CALLSIGN_RECORD_SIZE: u32 = 20;
+/// Entities start right after the header.
+const ENTITY_OFFSET: u32 = TapeHeader::SIZE;
+const ENTITY_SIZE: u32 = acmitape::Entity::SIZE;
+const ENTITY_UPDATE_SIZE: u32 = TimelineEntry::SIZE;
+const GENERAL_EVENT_SIZE: u32 = GeneralEventHeader::SIZE;
+const GENERAL_EVENT_TRAILER_SIZE: u32 = GeneralEventTrailer::SIZE;
+const FEATURE_EVENT_SIZE: u32 = acmitape::FeatureEvent::SIZE;
+const CALLSIGN_RECORD_SIZE: u32 = acmitape::CallsignRecord::SIZE;
 
 /// We'll want to check our position as we write -
 /// keep track ourselves so we don't have to make a bunch of stat() syscalls.
@@ -42,11 +49,13 @@ impl<W: Write> CountedWrite<W> {
 
 impl<W: Write> Write for CountedWrite<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let res = self.inner.write(buf);
-        if let Ok(count) = res {
-            self.posit += count as u32;
-        }
-        res
+        let count = self.inner.write(buf)?;
+        // Wrapping around would silently corrupt every offset after this one.
+        self.posit = u32::try_from(count)
+            .ok()
+            .and_then(|count| self.posit.checked_add(count))
+            .ok_or_else(|| io::Error::other("VHS offsets overflowed 32 bits"))?;
+        Ok(count)
     }
 
     #[inline]
@@ -131,22 +140,20 @@ impl IdMapping {
         let entities = all_ids
             .iter()
             .enumerate()
-            .filter_map(|(i, (id, kind))| {
-                (*kind == IdType::Entity).then(|| IdRemap {
-                    original: *id,
-                    new: i as i32,
-                })
+            .filter(|(_i, (_id, kind))| *kind == IdType::Entity)
+            .map(|(i, (id, _kind))| IdRemap {
+                original: *id,
+                new: i as i32,
             })
             .collect();
 
         let features = all_ids
             .iter()
             .enumerate()
-            .filter_map(|(i, (id, kind))| {
-                (*kind == IdType::Feature).then(|| IdRemap {
-                    original: *id,
-                    new: i as i32,
-                })
+            .filter(|(_i, (_id, kind))| *kind == IdType::Feature)
+            .map(|(i, (id, _kind))| IdRemap {
+                original: *id,
+                new: i as i32,
             })
             .collect();
 
@@ -156,18 +163,43 @@ impl IdMapping {
             callsign_ids,
         }
     }
+
+    /// Maps part of the flight this mapping was made for (see [`write_split()`]),
+    /// so that everything keeps the same UID in every part.
+    ///
+    /// The part should have all the flight's callsigns.
+    fn for_part(&self, part: &Flight) -> Self {
+        Self {
+            entities: self
+                .entities
+                .iter()
+                .filter(|remap| part.entities.contains_key(&remap.original))
+                .copied()
+                .collect(),
+            features: self
+                .features
+                .iter()
+                .filter(|remap| part.features.contains_key(&remap.original))
+                .copied()
+                .collect(),
+            callsign_ids: self.callsign_ids.clone(),
+        }
+    }
 }
 
 /// Writes out a VHS flight.
 ///
-/// Will allocate a BufWriter based on expected file size;
-/// pass "raw" writer in.
+/// Sizes the file based on the flight and memory maps it for writing;
+/// pass "raw" file in.
 /// Returns the number of bytes written on success.
 pub fn write(flight: &Flight, fh: std::fs::File) -> Result<u32> {
-    let id_map = IdMapping::new(flight);
+    check_writable(flight)?;
+    write_file(flight, &IdMapping::new(flight), fh)
+}
 
+fn write_file(flight: &Flight, id_map: &IdMapping, fh: std::fs::File) -> Result<u32> {
     // Build the header, which will give us an idea of how big the file will be.
-    let header = Header::new(flight, id_map.callsign_ids.len());
+    let header = Header::new(&SectionCounts::new(flight, id_map.callsign_ids.len()))?;
 
     // Set the file length and map it for writing.
     fh.set_len(header.file_length as u64)
@@ -175,6 +207,289 @@ pub fn write(flight: &Flight, fh: std::fs::File) -> Result<u32> {
     let mut mapped =
         unsafe { memmap::MmapMut::map_mut(&fh) }.context("Couldn't memory map output file")?;
 
+    write_sections(flight, id_map, &header, &mut mapped)?;
+    mapped.flush()?;
+
+    Ok(header.file_length)
+}
+
+/// Makes sure a flight has everything a VHS needs:
+/// a position for every entity to start at,
+/// and a feature for every feature event to happen to.
+///
+/// Parsed flights always do, but ones built by hand might not.
+fn check_writable(flight: &Flight) -> Result<()> {
+    for (id, entity) in &flight.entities {
+        ensure!(
+            entity
+                .position_data
+                .as_ref()
+                .is_some_and(|data| !data.position_updates.is_empty()),
+            "Entity {} has no position updates",
+            id
+        );
+    }
+    for event in &flight.feature_events {
+        ensure!(
+            flight.features.contains_key(&event.feature_uid),
+            "Feature event at {}s is for feature {}, which doesn't exist",
+            event.time,
+            event.feature_uid
+        );
+    }
+    Ok(())
+}
+
+/// The biggest a VHS file can be, since it's full of 32-bit offsets into itself
+pub const MAX_FILE_LENGTH: u64 = u32::MAX as u64;
+
+/// Writes out a VHS flight like [`write()`], but splits it by time
+/// into several files if it won't fit in `max_file_length` bytes
+/// (usually [`MAX_FILE_LENGTH`]).
+///
+/// `open(i)` should return the `i`th file (counting from 0) to write.
+/// Every entity and feature keeps the same UID in each file,
+/// and entities around at a split get a position update right on it
+/// at the end of one file and the start of the next,
+/// so they don't vanish or linger as ghosts between them.
+/// Returns the number of bytes written to each file on success.
+pub fn write_split<F>(flight: &Flight, max_file_length: u64, mut open: F) -> Result<Vec<u32>>
+where
+    F: FnMut(usize) -> Result<std::fs::File>,
+{
+    check_writable(flight)?;
+    let id_map = IdMapping::new(flight);
+    let seams = find_seams(flight, id_map.callsign_ids.len(), max_file_length)?;
+    if seams.is_empty() {
+        return Ok(vec![write_file(flight, &id_map, open(0)?)?]);
+    }
+    info!(
+        "Flight is too big for one VHS file; splitting it into {}",
+        seams.len() + 1
+    );
+
+    let bounds = std::iter::once(f32::NEG_INFINITY)
+        .chain(seams)
+        .chain(std::iter::once(f32::INFINITY))
+        .collect::<Vec<_>>();
+
+    bounds
+        .windows(2)
+        .enumerate()
+        .map(|(i, times)| {
+            let part = flight_part(flight, &(times[0]..times[1]));
+            debug!(
+                "Part {} runs from {} to {}",
+                i + 1,
+                part.start_time,
+                part.end_time
+            );
+            write_file(&part, &id_map.for_part(&part), open(i)?)
+        })
+        .collect()
+}
+
+/// Finds the times to split the flight at so that each part fits
+/// in `max_file_length` bytes, or none if the whole thing already does.
+fn find_seams(flight: &Flight, num_callsigns: usize, max_file_length: u64) -> Result<Vec<f32>> {
+    let fits = |times: Range<f32>| {
+        Header::new(&SectionCounts::of_part(flight, &times, num_callsigns))
+            .is_ok_and(|header| header.file_length as u64 <= max_file_length)
+    };
+
+    let mut seams = Vec::new();
+    let mut start = f32::NEG_INFINITY;
+    while !fits(start..f32::INFINITY) {
+        // Bisect for the latest seam where everything since the last one still fits.
+        // Parts only grow as they get longer, give or take a seam update.
+        let mut fitting = start.max(f32::MIN);
+        let mut too_big = f32::MAX;
+        loop {
+            let mid = fitting / 2.0 + too_big / 2.0;
+            if mid <= fitting || mid >= too_big {
+                break;
+            }
+            if fits(start..mid) {
+                fitting = mid;
+            } else {
+                too_big = mid;
+            }
+        }
+        ensure!(
+            fitting > start && fits(start..fitting),
+            "Can't split the flight into VHS files of {} bytes or less \
+             (too much happens around {})",
+            max_file_length,
+            fitting
+        );
+        seams.push(fitting);
+        start = fitting;
+    }
+    Ok(seams)
+}
+
+/// The part of the flight between the given times
+///
+/// Every part gets all the flight's features and callsigns -
+/// they're small, and it keeps [`IdMapping::for_part()`] simple.
+fn flight_part(flight: &Flight, times: &Range<f32>) -> Flight {
+    let entities = flight
+        .entities
+        .iter()
+        .filter_map(|(id, entity)| {
+            let slice = EntitySlice::new(entity, times)?;
+            let data = entity.position_data.as_ref().unwrap();
+            let updates = &data.position_updates;
+
+            let mut position_updates = Vec::with_capacity(slice.position_count() as usize);
+            if slice.enters {
+                let after = slice.positions.start;
+                position_updates.push(seam_update(
+                    &updates[after - 1],
+                    &updates[after],
+                    times.start,
+                ));
+            }
+            position_updates.extend_from_slice(&updates[slice.positions.clone()]);
+            if slice.leaves {
+                let after = slice.positions.end;
+                position_updates.push(seam_update(&updates[after - 1], &updates[after], times.end));
+            }
+
+            let part = flt::EntityData {
+                position_data: Some(flt::EntityPositionData {
+                    kind: data.kind,
+                    flags: data.flags,
+                    position_updates,
+                }),
+                events: entity.events[slice.events].to_vec(),
+            };
+            Some((*id, part))
+        })
+        .collect();
+
+    Flight {
+        corruption: flight.corruption.clone(),
+        tod_offset: flight.tod_offset,
+        start_time: flight.start_time.max(times.start),
+        end_time: flight.end_time.min(times.end),
+        callsigns: flight.callsigns.clone(),
+        entities,
+        features: flight.features.clone(),
+        general_events: flight.general_events
+            [time_range(&flight.general_events, times, |e| e.start)]
+        .to_vec(),
+        feature_events: flight.feature_events
+            [time_range(&flight.feature_events, times, |e| e.time)]
+        .to_vec(),
+    }
+}
+
+/// Which of an entity's position updates and events land in part of a split flight
+#[derive(Debug)]
+struct EntitySlice {
+    positions: Range<usize>,
+    events: Range<usize>,
+    /// The part starts with a position update on the seam before it.
+    enters: bool,
+    /// The part ends with a position update on the seam after it.
+    leaves: bool,
+}
+
+impl EntitySlice {
+    /// Slices the entity's timelines between the given times,
+    /// or returns `None` if it isn't around for any of them.
+    fn new(entity: &flt::EntityData, times: &Range<f32>) -> Option<Self> {
+        let updates = &entity.position_data.as_ref()?.position_updates;
+        let positions = time_range(updates, times, |u| u.time);
+
+        // An entity that's around on both sides of a seam gets a position update
+        // right on it in both parts, so it picks up in the next file
+        // exactly where it left off in the last one.
+        let enters = positions.start > 0
+            && positions.start < updates.len()
+            && updates[positions.start].time > times.start;
+        let leaves = positions.end > 0 && positions.end < updates.len();
+
+        if positions.is_empty() && !enters {
+            return None;
+        }
+
+        Some(Self {
+            positions,
+            events: time_range(&entity.events, times, |e| e.time),
+            enters,
+            leaves,
+        })
+    }
+
+    fn position_count(&self) -> u64 {
+        self.positions.len() as u64 + self.enters as u64 + self.leaves as u64
+    }
+}
+
+/// The range of `items` (in chronological order) between the given times.
+///
+/// BMS records everything in order, but a garbage FLT might not be.
+/// Out-of-order items could land in the wrong part (or two),
+/// but we'll still write valid files.
+fn time_range<T, F: Fn(&T) -> f32>(items: &[T], times: &Range<f32>, time: F) -> Range<usize> {
+    let start = items.partition_point(|i| time(i) < times.start);
+    let end = items.partition_point(|i| time(i) < times.end).max(start);
+    start..end
+}
+
+/// Where an entity is at the given time, between two of its position updates
+fn seam_update(
+    before: &flt::EntityPositionUpdate,
+    after: &flt::EntityPositionUpdate,
+    time: f32,
+) -> flt::EntityPositionUpdate {
+    let span = after.time - before.time;
+    let t = if span > 0.0 {
+        (time - before.time) / span
+    } else {
+        0.0
+    };
+    let lerp = |from: f32, to: f32| from + (to - from) * t;
+    flt::EntityPositionUpdate {
+        time,
+        x: lerp(before.x, after.x),
+        y: lerp(before.y, after.y),
+        z: lerp(before.z, after.z),
+        ..*before
+    }
+}
+
+/// Writes out a VHS flight to any writer.
+///
+/// Since the VHS is full of offsets into itself, the whole thing is built
+/// in memory first, then written out in one go.
+/// Prefer [`write()`] for files, which avoids that copy.
+/// Returns the number of bytes written on success.
+pub fn write_to<W: Write>(flight: &Flight, mut w: W) -> Result<u32> {
+    check_writable(flight)?;
+    let id_map = IdMapping::new(flight);
+    let header = Header::new(&SectionCounts::new(flight, id_map.callsign_ids.len()))?;
+
+    let mut buffer = vec![0u8; header.file_length as usize];
+    write_sections(flight, &id_map, &header, &mut buffer)?;
+    w.write_all(&buffer).context("Couldn't write VHS")?;
+    w.flush()?;
+
+    Ok(header.file_length)
+}
+
+/// Writes each section of the VHS into `mapped`,
+/// which must be exactly `header.file_length` bytes.
+fn write_sections(
+    flight: &Flight,
+    id_map: &IdMapping,
+    header: &Header,
+    mapped: &mut [u8],
+) -> Result<()> {
+    assert_eq!(mapped.len(), header.file_length as usize);
+
     // We know in advance how large each section of the file will be - we did
     // that math to build the header. Slice the file mapping into mutable slices
     // for each section, which we can write out in parallel below.
@@ -192,13 +507,12 @@ pub fn write(flight: &Flight, fh: std::fs::File) -> Result<u32> {
     let (mut feature_events_slice, mut callsigns_slice) =
         rest.split_at_mut((header.text_event_offset - header.feature_event_offset) as usize);
 
-    // Some of the feature fields refer to the index of other features.
-    // Let's put those in hash map so we can get constant time lookup.
-    let mut feature_indexes: FxHashMap<i32, i32> =
-        FxHashMap::with_capacity_and_hasher(flight.features.len(), Default::default());
-    for (i, id) in id_map.features.iter().map(|m| m.original).enumerate() {
-        feature_indexes.insert(id, i as i32);
-    }
+    let feature_indexes = indexes(&id_map.features);
+    let lengths = id_map
+        .entities
+        .iter()
+        .map(|remap| TimelineLengths::of(&flight.entities[&remap.original]))
+        .collect::<Vec<_>>();
 
     let mut errors: Vec<anyhow::Error> = Vec::new();
     let mut append_error = |result: Option<anyhow::Error>| {
@@ -222,16 +536,21 @@ pub fn write(flight: &Flight, fh: std::fs::File) -> Result<u32> {
         });
 
         let entities = s.spawn(|_| {
-            let feature_position_offset =
-                write_entities(flight, &id_map.entities, &header, &mut entity_slice)
-                    .context("Entity write failed")?;
+            let feature_position_offset = write_entities(
+                flight,
+                &id_map.entities,
+                &lengths,
+                header,
+                &mut entity_slice,
+            )
+            .context("Entity write failed")?;
 
             write_features(
                 flight,
                 &id_map.features,
                 &feature_indexes,
                 feature_position_offset,
-                &header,
+                header,
                 &mut feature_slice,
             )
             .context("Feature write failed")
@@ -281,8 +600,6 @@ pub fn write(flight: &Flight, fh: std::fs::File) -> Result<u32> {
     })
     .unwrap();
 
-    mapped.flush()?;
-
     if !errors.is_empty() {
         for e in errors {
             error!("{:?}", e);
@@ -290,7 +607,339 @@ pub fn write(flight: &Flight, fh: std::fs::File) -> Result<u32> {
         bail!("VHS write failed");
     }
 
-    Ok(header.file_length)
+    Ok(())
+}
+
+/// How much of the FLT [`StreamingWrite`] parses at a time
+const STREAMING_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Converts a `.flt` to a `.vhs` in two passes, so that memory use doesn't grow
+/// with the length of the recording like it does when we parse a whole [`Flight`]
+/// before we [`write()`] it.
+///
+/// The first pass ([`scan()`](StreamingWrite::scan)) counts every entity's
+/// position updates and events, along with the other records that pile up
+/// as the recording goes on. That's everything we need to build the header
+/// and lay out the file.
+/// The second ([`write()`](StreamingWrite::write)) parses the FLT again,
+/// writing each of those records straight into its place in the VHS.
+pub struct StreamingWrite<'a> {
+    flt: &'a [u8],
+    chunk_size: usize,
+
+    /// Everything but the records we stream
+    flight: Flight,
+
+    timelines: FxHashMap<i32, TimelineLengths>,
+    general_event_count: u64,
+    feature_event_count: u64,
+}
+
+impl<'a> StreamingWrite<'a> {
+    /// Makes the first pass over the FLT.
+    pub fn scan(flt: &'a [u8]) -> Self {
+        Self::scan_in_chunks(flt, STREAMING_CHUNK_SIZE)
+    }
+
+    /// Like [`scan()`](StreamingWrite::scan), but parses (at least)
+    /// `chunk_size` bytes of the FLT at a time, in both passes.
+    pub fn scan_in_chunks(flt: &'a [u8], chunk_size: usize) -> Self {
+        let mut timelines: FxHashMap<i32, TimelineLengths> = FxHashMap::default();
+        let mut general_event_count = 0;
+        let mut feature_event_count = 0;
+
+        let flight = Flight::parse_streaming(flt, chunk_size, |chunk| {
+            for (uid, entity) in &chunk.entities {
+                let lengths = TimelineLengths::of(entity);
+                let total = timelines.entry(*uid).or_default();
+                total.positions += lengths.positions;
+                total.events += lengths.events;
+            }
+            general_event_count += chunk.general_events.len() as u64;
+            feature_event_count += chunk.feature_events.len() as u64;
+        });
+
+        Self {
+            flt,
+            chunk_size,
+            flight,
+            timelines,
+            general_event_count,
+            feature_event_count,
+        }
+    }
+
+    /// The flight from the first pass, without any position updates or events
+    /// (but with entities, features, callsigns, and any [`corruption`](Flight::corruption))
+    pub fn flight(&self) -> &Flight {
+        &self.flight
+    }
+
+    /// Makes the second pass over the FLT, writing the VHS as we go.
+    ///
+    /// Like [`write()`], sizes the file and memory maps it for writing;
+    /// pass "raw" file in.
+    /// Returns the number of bytes written on success.
+    pub fn write(&self, fh: std::fs::File) -> Result<u32> {
+        let id_map = IdMapping::new(&self.flight);
+        let lengths = id_map
+            .entities
+            .iter()
+            .map(|remap| self.timelines[&remap.original])
+            .collect::<Vec<_>>();
+
+        let header = Header::new(&SectionCounts {
+            entities: self.flight.entities.len() as u64,
+            features: self.flight.features.len() as u64,
+            entity_positions: lengths.iter().map(|l| l.positions as u64).sum(),
+            entity_events: lengths.iter().map(|l| l.events as u64).sum(),
+            general_events: self.general_event_count,
+            feature_events: self.feature_event_count,
+            callsigns: id_map.callsign_ids.len() as u64,
+        })?;
+
+        fh.set_len(header.file_length as u64)
+            .context("Couldn't grow output file")?;
+        let mut mapped =
+            unsafe { memmap::MmapMut::map_mut(&fh) }.context("Couldn't memory map output file")?;
+
+        self.write_mapped(&id_map, &lengths, &header, &mut mapped)?;
+        mapped.flush()?;
+
+        Ok(header.file_length)
+    }
+
+    fn write_mapped(
+        &self,
+        id_map: &IdMapping,
+        lengths: &[TimelineLengths],
+        header: &Header,
+        mapped: &mut [u8],
+    ) -> Result<()> {
+        assert_eq!(mapped.len(), header.file_length as usize);
+
+        let feature_indexes = indexes(&id_map.features);
+
+        let mut writer = ChunkWriter::new(id_map, lengths, header);
+        let mut result = Ok(());
+        Flight::parse_streaming(self.flt, self.chunk_size, |chunk| {
+            if result.is_ok() {
+                result = writer.write(&chunk, &feature_indexes, mapped);
+            }
+        });
+        result?;
+        ensure!(writer.finished(), CHANGED_BETWEEN_PASSES);
+
+        // Now that they're all in place, sort the trailers chronologically
+        // (see write_general_events()).
+        let trailers = &mut mapped
+            [header.general_event_trailer_offset as usize..header.feature_event_offset as usize];
+        let mut sorted = trailers
+            .chunks_exact(GENERAL_EVENT_TRAILER_SIZE as usize)
+            .map(|trailer| <[u8; GENERAL_EVENT_TRAILER_SIZE as usize]>::try_from(trailer).unwrap())
+            .collect::<Vec<_>>();
+        let time_end = |trailer: &[u8; GENERAL_EVENT_TRAILER_SIZE as usize]| {
+            f32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]])
+        };
+        sorted.par_sort_by(|a, b| time_end(a).total_cmp(&time_end(b)));
+        for (trailer, sorted) in trailers
+            .chunks_exact_mut(GENERAL_EVENT_TRAILER_SIZE as usize)
+            .zip(&sorted)
+        {
+            trailer.copy_from_slice(sorted);
+        }
+
+        // Everything else is small enough that we kept it from the first pass.
+        let flight = &self.flight;
+        header
+            .write(flight, &mut at(mapped, 0))
+            .context("Header write failed")?;
+        let feature_position_offset = write_entities(
+            flight,
+            &id_map.entities,
+            lengths,
+            header,
+            &mut at(mapped, ENTITY_OFFSET),
+        )
+        .context("Entity write failed")?;
+        write_features(
+            flight,
+            &id_map.features,
+            &feature_indexes,
+            feature_position_offset,
+            header,
+            &mut at(mapped, header.feature_offset),
+        )
+        .context("Feature write failed")?;
+        write_feature_positions(
+            flight,
+            &id_map.features,
+            &mut at(mapped, feature_position_offset),
+        )
+        .context("Feature position write failed")?;
+        write_callsigns(
+            flight,
+            &id_map.callsign_ids,
+            &mut at(mapped, header.text_event_offset),
+        )
+        .context("Callsigns write failed")?;
+
+        Ok(())
+    }
+}
+
+/// Writes each chunk's streamed records into their places in the VHS
+struct ChunkWriter<'a> {
+    header: &'a Header,
+
+    /// Radar targets need to be converted from UIDs to entity indexes.
+    entity_indexes: FxHashMap<i32, i32>,
+
+    /// Where the next position update and event for each entity go
+    timelines: FxHashMap<i32, (TimelineSlots, TimelineSlots)>,
+
+    general_events_written: u32,
+    feature_events_written: u32,
+}
+
+impl<'a> ChunkWriter<'a> {
+    fn new(id_map: &IdMapping, lengths: &[TimelineLengths], header: &'a Header) -> Self {
+        let timelines = id_map
+            .entities
+            .iter()
+            .zip(lengths)
+            .zip(first_timeline_offsets(lengths, header))
+            .map(|((remap, lengths), (first_position, first_event))| {
+                let positions = TimelineSlots::new(first_position, lengths.positions);
+                let events = TimelineSlots::new(first_event, lengths.events);
+                (remap.original, (positions, events))
+            })
+            .collect();
+
+        Self {
+            header,
+            entity_indexes: indexes(&id_map.entities),
+            timelines,
+            general_events_written: 0,
+            feature_events_written: 0,
+        }
+    }
+
+    fn write(
+        &mut self,
+        chunk: &Flight,
+        feature_indexes: &FxHashMap<i32, i32>,
+        mapped: &mut [u8],
+    ) -> Result<()> {
+        let header = self.header;
+
+        for (uid, entity) in &chunk.entities {
+            // The first pass chucked entities that were never defined.
+            let (positions, events) = match self.timelines.get_mut(uid) {
+                Some(slots) => slots,
+                None => continue,
+            };
+            let updates = entity
+                .position_data
+                .iter()
+                .flat_map(|data| &data.position_updates);
+            for update in updates {
+                let (offset, next, previous) = positions.take()?;
+                position_entry(update, &self.entity_indexes, next, previous)
+                    .write(&mut at(mapped, offset))?;
+            }
+            for event in &entity.events {
+                let (offset, next, previous) = events.take()?;
+                event_entry(event, next, previous).write(&mut at(mapped, offset))?;
+            }
+        }
+
+        for event in &chunk.general_events {
+            let i = self.general_events_written;
+            ensure!(i < header.general_event_count, CHANGED_BETWEEN_PASSES);
+            self.general_events_written += 1;
+
+            general_event_header(i as i32, event).write(&mut at(
+                mapped,
+                header.general_event_offset + GENERAL_EVENT_SIZE * i,
+            ))?;
+            // We'll sort these once they're all written.
+            GeneralEventTrailer {
+                time_end: event.stop,
+                index: i as i32,
+            }
+            .write(&mut at(
+                mapped,
+                header.general_event_trailer_offset + GENERAL_EVENT_TRAILER_SIZE * i,
+            ))?;
+        }
+
+        for event in &chunk.feature_events {
+            let i = self.feature_events_written;
+            ensure!(i < header.feature_event_count, CHANGED_BETWEEN_PASSES);
+            self.feature_events_written += 1;
+
+            feature_event(event, feature_indexes).write(&mut at(
+                mapped,
+                header.feature_event_offset + FEATURE_EVENT_SIZE * i,
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// True if we've written every record the first pass counted
+    fn finished(&self) -> bool {
+        self.timelines
+            .values()
+            .all(|(positions, events)| positions.left == 0 && events.left == 0)
+            && self.general_events_written == self.header.general_event_count
+            && self.feature_events_written == self.header.feature_event_count
+    }
+}
+
+const CHANGED_BETWEEN_PASSES: &str = "The FLT file changed between passes";
+
+/// The rest of the mapping, starting at `offset`, to write into
+fn at(mapped: &mut [u8], offset: u32) -> &mut [u8] {
+    &mut mapped[offset as usize..]
+}
+
+/// Hands out the places in the file for each entry of a timeline
+/// (an entity's position updates or events), in order.
+#[derive(Debug)]
+struct TimelineSlots {
+    next: u32,
+    previous: u32,
+    left: u32,
+}
+
+impl TimelineSlots {
+    fn new(first_offset: u32, length: u32) -> Self {
+        Self {
+            next: first_offset,
+            previous: 0,
+            left: length,
+        }
+    }
+
+    /// Returns the offset of the next entry, along with
+    /// the offsets of the entries after and before it (or 0 if there aren't any).
+    fn take(&mut self) -> Result<(u32, u32, u32)> {
+        ensure!(self.left > 0, CHANGED_BETWEEN_PASSES);
+        self.left -= 1;
+
+        let current = self.next;
+        let next = if self.left > 0 {
+            current + ENTITY_UPDATE_SIZE
+        } else {
+            0
+        };
+        let previous = self.previous;
+
+        self.previous = current;
+        self.next = current + ENTITY_UPDATE_SIZE;
+        Ok((current, next, previous))
+    }
 }
 
 /// Lots of sizes and offsets we need to write to the file header,
@@ -304,6 +953,8 @@ struct Header {
     feature_count: u32,
     position_count: u32,
     entity_event_count: u32,
+    general_event_count: u32,
+    feature_event_count: u32,
     feature_offset: u32,
     position_offset: u32,
     entity_event_offset: u32,
@@ -314,145 +965,187 @@ struct Header {
     file_length: u32,
 }
 
-impl Header {
+/// How many of each record go in the VHS
+#[derive(Debug)]
+struct SectionCounts {
+    entities: u64,
+    features: u64,
+    entity_positions: u64,
+    entity_events: u64,
+    general_events: u64,
+    feature_events: u64,
+    callsigns: u64,
+}
+
+impl SectionCounts {
     fn new(flight: &Flight, num_callsigns: usize) -> Self {
-        let entity_count = flight.entities.len() as u32;
-        let feature_count = flight.features.len() as u32;
-        let entity_position_count = flight
-            .entities
-            .values()
-            .map(|d| {
-                d.position_data
-                    .as_ref()
-                    .expect("No position data")
-                    .position_updates
-                    .len()
-            })
-            .sum::<usize>() as u32;
-        // Assuming ONE position per feature. Change me if we support multiple.
-        let position_count = entity_position_count + flight.features.len() as u32;
+        Self {
+            entities: flight.entities.len() as u64,
+            features: flight.features.len() as u64,
+            entity_positions: flight
+                .entities
+                .values()
+                .map(|d| {
+                    d.position_data
+                        .as_ref()
+                        .expect("No position data")
+                        .position_updates
+                        .len()
+                })
+                .sum::<usize>() as u64,
+            entity_events: flight
+                .entities
+                .values()
+                .map(|d| d.events.len())
+                .sum::<usize>() as u64,
+            general_events: flight.general_events.len() as u64,
+            feature_events: flight.feature_events.len() as u64,
+            callsigns: num_callsigns as u64,
+        }
+    }
 
-        let entity_event_count = flight
+    /// Counts what [`flight_part()`] would put in the part between the given times,
+    /// without building it.
+    fn of_part(flight: &Flight, times: &Range<f32>, num_callsigns: usize) -> Self {
+        let mut counts = Self {
+            entities: 0,
+            features: flight.features.len() as u64,
+            entity_positions: 0,
+            entity_events: 0,
+            general_events: time_range(&flight.general_events, times, |e| e.start).len() as u64,
+            feature_events: time_range(&flight.feature_events, times, |e| e.time).len() as u64,
+            callsigns: num_callsigns as u64,
+        };
+        for slice in flight
             .entities
             .values()
-            .map(|d| d.events.len())
-            .sum::<usize>() as u32;
+            .filter_map(|e| EntitySlice::new(e, times))
+        {
+            counts.entities += 1;
+            counts.entity_positions += slice.position_count();
+            counts.entity_events += slice.events.len() as u64;
+        }
+        counts
+    }
+}
+
+impl Header {
+    fn new(counts: &SectionCounts) -> Result<Self> {
+        // Do the math in 64 bits, then make sure it all fits in 32.
+        let entity_count = counts.entities;
+        let feature_count = counts.features;
+        // Assuming ONE position per feature. Change me if we support multiple.
+        let position_count = counts.entity_positions + feature_count;
+        let entity_event_count = counts.entity_events;
 
-        let feature_offset = ENTITY_OFFSET + ENTITY_SIZE * entity_count;
+        let feature_offset = ENTITY_OFFSET as u64 + ENTITY_SIZE as u64 * entity_count;
 
-        let position_offset = feature_offset + ENTITY_SIZE * feature_count;
+        let position_offset = feature_offset + ENTITY_SIZE as u64 * feature_count;
 
-        let entity_event_offset = position_offset + ENTITY_UPDATE_SIZE * position_count;
+        let entity_event_offset = position_offset + ENTITY_UPDATE_SIZE as u64 * position_count;
 
-        let general_event_count = flight.general_events.len() as u32;
+        let general_event_count = counts.general_events;
 
-        let general_event_offset = entity_event_offset + ENTITY_UPDATE_SIZE * entity_event_count;
+        let general_event_offset =
+            entity_event_offset + ENTITY_UPDATE_SIZE as u64 * entity_event_count;
 
         let general_event_trailer_offset =
-            general_event_offset + GENERAL_EVENT_SIZE * general_event_count;
+            general_event_offset + GENERAL_EVENT_SIZE as u64 * general_event_count;
 
         let feature_event_offset =
-            general_event_trailer_offset + GENERAL_EVENT_TRAILER_SIZE * general_event_count;
+            general_event_trailer_offset + GENERAL_EVENT_TRAILER_SIZE as u64 * general_event_count;
 
         let text_event_offset =
-            feature_event_offset + FEATURE_EVENT_SIZE * flight.feature_events.len() as u32;
+            feature_event_offset + FEATURE_EVENT_SIZE as u64 * counts.feature_events;
 
-        let callsign_array_len = num_callsigns as u32;
+        let callsign_array_len = counts.callsigns;
 
-        let file_length = text_event_offset + 4 + CALLSIGN_RECORD_SIZE * callsign_array_len;
+        let file_length = text_event_offset + 4 + CALLSIGN_RECORD_SIZE as u64 * callsign_array_len;
 
-        Self {
-            entity_count,
-            feature_count,
-            position_count,
-            entity_event_count,
-            feature_offset,
-            position_offset,
-            entity_event_offset,
-            general_event_offset,
-            general_event_trailer_offset,
-            feature_event_offset,
-            text_event_offset,
-            file_length,
-        }
+        // Everything else is smaller than the file length.
+        ensure!(
+            file_length <= u32::MAX as u64,
+            "Flight is too big for a VHS file ({} bytes, but offsets are 32 bits)",
+            file_length
+        );
+
+        Ok(Self {
+            entity_count: entity_count as u32,
+            feature_count: feature_count as u32,
+            position_count: position_count as u32,
+            entity_event_count: entity_event_count as u32,
+            general_event_count: general_event_count as u32,
+            feature_event_count: counts.feature_events as u32,
+            feature_offset: feature_offset as u32,
+            position_offset: position_offset as u32,
+            entity_event_offset: entity_event_offset as u32,
+            general_event_offset: general_event_offset as u32,
+            general_event_trailer_offset: general_event_trailer_offset as u32,
+            feature_event_offset: feature_event_offset as u32,
+            text_event_offset: text_event_offset as u32,
+            file_length: file_length as u32,
+        })
     }
 
     fn write<W: Write>(&self, flight: &Flight, w: &mut W) -> Result<()> {
-        // The magic bytes: "TAPE", but little-endian.
-        w.write_all(b"EPAT")?;
+        let total_time = flight.end_time - flight.start_time;
+
+        let tape = TapeHeader {
+            file_id: TapeHeader::MAGIC,
+            // Weird: acmi-compiler sets this to the text event offset,
+            // not the actual length, and it's just doing what FreeFalcon
+            // (so presumably F4 and BMS) do.
+            // TacView throws a fit if this isn't the case.
+            file_size: self.text_event_offset,
+            entity_count: self.entity_count as i32,
+            feature_count: self.feature_count as i32,
+            entity_offset: ENTITY_OFFSET,
+            feature_offset: self.feature_offset,
+            position_count: self.position_count as i32,
+            position_offset: self.position_offset,
+            entity_event_offset: self.entity_event_offset,
+            general_event_offset: self.general_event_offset,
+            general_event_trailer_offset: self.general_event_trailer_offset,
+            text_event_offset: self.text_event_offset,
+            feature_event_offset: self.feature_event_offset,
+            general_event_count: self.general_event_count as i32,
+            entity_event_count: self.entity_event_count as i32,
+            // Callsigns aren't text events - they're a separate thing that
+            // don't seem to get saved anymore. Looking at the FreeFalcon code,
+            // seems like they were pulled from the game state and not the FLT.
+            text_event_count: 0,
+            feature_event_count: self.feature_event_count as i32,
+            start_time: flight.start_time,
+            total_play_time: total_time,
+            tod_offset: flight.tod_offset,
+        };
 
         // Let's debug print the header. It's fairly short,
         // and the offsets and counts are a good sanity check.
-
         debug!("File size: {}", self.file_length);
-        // Weird: acmi-compiler sets this to the text event offset,
-        // not the actual length, and it's just doing what FreeFalcon
-        // (so presumably F4 and BMS) do.
-        // TacView throws a fit if this isn't the case.
-        write_u32(self.text_event_offset, w)?;
-
-        debug!("Entity count: {}", self.entity_count);
-        write_u32(self.entity_count, w)?;
-
-        debug!("Feature count: {}", self.feature_count);
-        write_u32(self.feature_count, w)?;
-
-        debug!("Entity offset: {}", ENTITY_OFFSET);
-        write_u32(ENTITY_OFFSET, w)?;
-
-        debug!("Feature offset: {}", self.feature_offset);
-        write_u32(self.feature_offset, w)?;
-
-        debug!("Position count: {}", self.position_count);
-        write_u32(self.position_count, w)?;
-
-        debug!("Position offset: {}", self.position_offset);
-        write_u32(self.position_offset, w)?;
-
-        debug!("Entity event offset: {}", self.entity_event_offset);
-        write_u32(self.entity_event_offset, w)?;
-
-        debug!("General event offset: {}", self.general_event_offset);
-        write_u32(self.general_event_offset, w)?;
-
+        debug!("Entity count: {}", tape.entity_count);
+        debug!("Feature count: {}", tape.feature_count);
+        debug!("Entity offset: {}", tape.entity_offset);
+        debug!("Feature offset: {}", tape.feature_offset);
+        debug!("Position count: {}", tape.position_count);
+        debug!("Position offset: {}", tape.position_offset);
+        debug!("Entity event offset: {}", tape.entity_event_offset);
+        debug!("General event offset: {}", tape.general_event_offset);
         debug!(
             "General event trailer offset: {}",
-            self.general_event_trailer_offset
+            tape.general_event_trailer_offset
         );
-        write_u32(self.general_event_trailer_offset, w)?;
-
-        debug!("Text event offset: {}", self.text_event_offset);
-        write_u32(self.text_event_offset, w)?;
-
-        debug!("Feature event offset: {}", self.feature_event_offset);
-        write_u32(self.feature_event_offset, w)?;
-
-        debug!("General event count: {}", flight.general_events.len());
-        write_u32(flight.general_events.len() as u32, w)?;
-
-        debug!("Entity event count: {}", self.entity_event_count);
-        write_u32(self.entity_event_count, w)?;
-
-        // Callsigns aren't text events - they're a separate thing that
-        // don't seem to get saved anymore. Looking at the FreeFalcon code,
-        // seems like they were pulled from the game state and not the FLT.
-        debug!("Text event count: 0");
-        write_u32(0, w)?;
-
-        debug!("Feature event count: {}", flight.feature_events.len());
-        write_u32(flight.feature_events.len() as u32, w)?;
-
-        debug!("Start time: {}", flight.start_time);
-        write_f32(flight.start_time, w)?;
-
-        let total_time = flight.end_time - flight.start_time;
-        debug!("Total play time: {}", total_time);
-        write_f32(total_time, w)?;
-
-        debug!("Time of day offset: {}", flight.tod_offset);
-        write_f32(flight.tod_offset, w)?;
-
-        Ok(())
+        debug!("Text event offset: {}", tape.text_event_offset);
+        debug!("Feature event offset: {}", tape.feature_event_offset);
+        debug!("General event count: {}", tape.general_event_count);
+        debug!("Entity event count: {}", tape.entity_event_count);
+        debug!("Text event count: {}", tape.text_event_count);
+        debug!("Feature event count: {}", tape.feature_event_count);
+        debug!("Start time: {}", tape.start_time);
+        debug!("Total play time: {}", tape.total_play_time);
+        debug!("Time of day offset: {}", tape.tod_offset);
+
+        tape.write(w)
     }
 }
 
@@ -479,55 +1172,102 @@ impl Header {
 fn write_entities<W: Write>(
     flight: &Flight,
     entity_mapping: &[IdRemap],
+    lengths: &[TimelineLengths],
     header: &Header,
     w: &mut W,
 ) -> Result<u32> {
     let mut kind_indexes = FxHashMap::default();
-    let mut position_index = 0;
-    let mut event_index = 0;
 
-    for (id, entity) in entity_mapping
+    for ((id, entity), (first_position_offset, first_event_offset)) in entity_mapping
         .iter()
         .map(|remap| (remap.new, &flight.entities[&remap.original]))
+        .zip(first_timeline_offsets(lengths, header))
     {
         let data = entity.position_data.as_ref().unwrap();
-        write_i32(id, w)?;
-        write_i32(data.kind, w)?;
 
         // For some reason (at least per the acmi-compiler code), for each entity
         // we store its index (starting at 1!?) out of all entities of the same kind.
         let kind_index = kind_indexes.entry(data.kind).or_insert(1);
-        write_i32(*kind_index, w)?;
+        let count = *kind_index;
         *kind_index += 1;
 
-        write_u32(data.flags, w)?;
-        // Lead index, slot, and special flags:
-        // all 0 for entities. Meaningful for features.
-        write_i32(0, w)?;
-        write_i32(0, w)?;
-        write_u32(0, w)?;
+        assert!(first_position_offset >= header.position_offset);
+        assert!(first_position_offset < header.entity_event_offset);
 
+        acmitape::Entity {
+            uid: id,
+            kind: data.kind,
+            count,
+            flags: data.flags,
+            // Lead index, slot, and special flags:
+            // all 0 for entities. Meaningful for features.
+            lead_index: 0,
+            slot: 0,
+            special_flags: 0,
+            first_position_offset,
+            first_event_offset,
+        }
+        .write(w)?;
+    }
+
+    let position_count = lengths.iter().map(|l| l.positions).sum::<u32>();
+    Ok(header.position_offset + ENTITY_UPDATE_SIZE * position_count)
+}
+
+/// How many position updates and events an entity has
+#[derive(Debug, Default, Copy, Clone)]
+struct TimelineLengths {
+    positions: u32,
+    events: u32,
+}
+
+impl TimelineLengths {
+    fn of(entity: &flt::EntityData) -> Self {
+        Self {
+            positions: entity
+                .position_data
+                .as_ref()
+                .map_or(0, |data| data.position_updates.len() as u32),
+            events: entity.events.len() as u32,
+        }
+    }
+}
+
+/// Where each entity's position updates and events start (or 0 if it has no events),
+/// given how many of each every entity has.
+/// They're written back to back, in the same order as the entities.
+fn first_timeline_offsets<'a>(
+    lengths: &'a [TimelineLengths],
+    header: &'a Header,
+) -> impl Iterator<Item = (u32, u32)> + 'a {
+    let mut position_index = 0;
+    let mut event_index = 0;
+    lengths.iter().map(move |lengths| {
         // Every entity should have at least one position,
         // and we've screwed something up if we get here without one.
-        assert!(!data.position_updates.is_empty());
+        assert!(lengths.positions > 0);
         let first_position_offset = header.position_offset + ENTITY_UPDATE_SIZE * position_index;
-        assert!(first_position_offset >= header.position_offset);
-        assert!(first_position_offset < header.entity_event_offset);
-
-        write_u32(first_position_offset, w)?;
 
-        let first_event_offset = if entity.events.is_empty() {
+        let first_event_offset = if lengths.events == 0 {
             0
         } else {
             header.entity_event_offset + ENTITY_UPDATE_SIZE * event_index
         };
-        write_u32(first_event_offset, w)?;
 
-        position_index += data.position_updates.len() as u32;
-        event_index += entity.events.len() as u32;
-    }
+        position_index += lengths.positions;
+        event_index += lengths.events;
+        (first_position_offset, first_event_offset)
+    })
+}
 
-    Ok(header.position_offset + ENTITY_UPDATE_SIZE * position_index)
+/// Maps each original ID to its index in the mapping,
+/// which is how the VHS refers to other entities and features.
+fn indexes(mapping: &[IdRemap]) -> FxHashMap<i32, i32> {
+    let mut indexes = FxHashMap::with_capacity_and_hasher(mapping.len(), Default::default());
+    for (i, id) in mapping.iter().map(|m| m.original).enumerate() {
+        indexes.insert(id, i as i32);
+    }
+    indexes
 }
 
 /// Write out the list of features - see [`write_entities()`](write_entities),
@@ -545,29 +1285,26 @@ fn write_features<W: Write>(
         .map(|remap| (remap.new, &flight.features[&remap.original]))
         .enumerate()
     {
-        write_i32(id, w)?;
-        write_i32(feature.kind, w)?;
-
-        // Features don't play the same "type index" game entities do.
-        write_i32(0, w)?;
-
-        write_u32(flt::ENTITY_FLAG_FEATURE, w)?;
-
-        // Lead index
-        write_i32(*feature_indexes.get(&feature.lead_uid).unwrap_or(&-1), w)?;
-
-        write_i32(feature.slot, w)?;
-        write_u32(feature.special_flags, w)?;
-
         let position_offset =
             feature_position_offset + ENTITY_UPDATE_SIZE * (position_index as u32);
         assert!(position_offset >= feature_position_offset);
         assert!(position_offset < header.entity_event_offset);
-        write_u32(position_offset, w)?;
 
-        // Since feature events are stored separately,
-        // first event offset is apparently always zero.
-        write_u32(0, w)?;
+        acmitape::Entity {
+            uid: id,
+            kind: feature.kind,
+            // Features don't play the same "type index" game entities do.
+            count: 0,
+            flags: flt::ENTITY_FLAG_FEATURE,
+            lead_index: *feature_indexes.get(&feature.lead_uid).unwrap_or(&-1),
+            slot: feature.slot,
+            special_flags: feature.special_flags,
+            first_position_offset: position_offset,
+            // Since feature events are stored separately,
+            // first event offset is apparently always zero.
+            first_event_offset: 0,
+        }
+        .write(w)?;
     }
 
     Ok(())
@@ -579,11 +1316,7 @@ fn write_entity_positions<W: Write>(
     w: &mut CountedWrite<W>,
 ) -> Result<()> {
     // Radar targets need to be converted from UIDs to entity indexes.
-    let mut entity_indexes: FxHashMap<i32, i32> =
-        FxHashMap::with_capacity_and_hasher(flight.entities.len(), Default::default());
-    for (i, id) in entity_mapping.iter().map(|m| m.original).enumerate() {
-        entity_indexes.insert(id, i as i32);
-    }
+    let entity_indexes = indexes(entity_mapping);
 
     for entity in entity_mapping
         .iter()
@@ -596,22 +1329,6 @@ fn write_entity_positions<W: Write>(
 
         while let Some(new_posit) = posits.next() {
             let current_offset = w.get_posit();
-            write_f32(new_posit.time, w)?;
-            // Updates are unions of position updates,
-            // switch updates, and DOF updates.
-            // The next byte is the union's tag/descriminant.
-            write_u8(0, w)?;
-            write_f32(new_posit.x, w)?;
-            write_f32(new_posit.y, w)?;
-            write_f32(new_posit.z, w)?;
-            write_f32(new_posit.pitch, w)?;
-            write_f32(new_posit.roll, w)?;
-            write_f32(new_posit.yaw, w)?;
-            // Radar target index
-            write_i32(
-                *entity_indexes.get(&new_posit.radar_target).unwrap_or(&-1),
-                w,
-            )?;
 
             // What's nice about having all an entity's position updates in
             // a contiguous lists is that we can write them out contiguously,
@@ -621,8 +1338,8 @@ fn write_entity_positions<W: Write>(
             } else {
                 0
             };
-            write_u32(next_offset, w)?;
-            write_u32(previous_offset, w)?;
+
+            position_entry(new_posit, &entity_indexes, next_offset, previous_offset).write(w)?;
             previous_offset = current_offset;
         }
     }
@@ -638,22 +1355,22 @@ fn write_feature_positions<W: Write>(
         .iter()
         .map(|remap| &flight.features[&remap.original])
     {
-        write_f32(feature.time, w)?;
-        // Updates are unions of position updates,
-        // switch updates, and DOF updates.
-        // The next byte is the union's tag/discriminant.
-        write_u8(0, w)?;
-        write_f32(feature.x, w)?;
-        write_f32(feature.y, w)?;
-        write_f32(feature.z, w)?;
-        write_f32(feature.pitch, w)?;
-        write_f32(feature.roll, w)?;
-        write_f32(feature.yaw, w)?;
-        // Radar target
-        write_i32(-1, w)?;
-        // No previous or next positions
-        write_u32(0, w)?;
-        write_u32(0, w)?;
+        TimelineEntry {
+            time: feature.time,
+            payload: TimelineEntryPayload::Pos(Position {
+                x: feature.x,
+                y: feature.y,
+                z: feature.z,
+                pitch: feature.pitch,
+                roll: feature.roll,
+                yaw: feature.yaw,
+                radar_target: -1,
+            }),
+            // No previous or next positions
+            next_update_offset: 0,
+            previous_update_offset: 0,
+        }
+        .write(w)?;
     }
     Ok(())
 }
@@ -673,29 +1390,6 @@ fn write_entity_events<W: Write>(
 
         while let Some(event) = events.next() {
             let current_offset = w.get_posit();
-            write_f32(event.time, w)?;
-            // Updates are unions of position updates,
-            // switch updates, and DOF updates.
-            // The next byte is the union's tag/discriminant.
-            match event.payload {
-                flt::EntityEventPayload::SwitchEvent(switch) => {
-                    write_u8(1, w)?;
-                    write_i32(switch.switch_number, w)?;
-                    write_i32(switch.new_switch_value, w)?;
-                    write_i32(switch.previous_switch_value, w)?;
-                }
-                flt::EntityEventPayload::DofEvent(dof) => {
-                    write_u8(2, w)?;
-                    write_i32(dof.dof_number, w)?;
-                    write_f32(dof.new_dof_value, w)?;
-                    write_f32(dof.previous_dof_value, w)?;
-                }
-            };
-            // Unused space, taken up by the position update in the union
-            write_u32(0, w)?;
-            write_u32(0, w)?;
-            write_u32(0, w)?;
-            write_u32(0, w)?;
 
             // What's nice about having all an entity's position updates in
             // a contiguous lists is that we can write them out contiguously,
@@ -705,76 +1399,137 @@ fn write_entity_events<W: Write>(
             } else {
                 0
             };
-            write_u32(next_offset, w)?;
-            write_u32(previous_offset, w)?;
+
+            event_entry(event, next_offset, previous_offset).write(w)?;
             previous_offset = current_offset;
         }
     }
     Ok(())
 }
 
-struct GeneralEventTrailer {
-    stop: f32,
-    index: u32,
+/// An entity's position update, as a timeline entry
+/// (with radar targets converted to entity indexes)
+fn position_entry(
+    update: &flt::EntityPositionUpdate,
+    entity_indexes: &FxHashMap<i32, i32>,
+    next_update_offset: u32,
+    previous_update_offset: u32,
+) -> TimelineEntry {
+    TimelineEntry {
+        time: update.time,
+        payload: TimelineEntryPayload::Pos(Position {
+            x: update.x,
+            y: update.y,
+            z: update.z,
+            pitch: update.pitch,
+            roll: update.roll,
+            yaw: update.yaw,
+            // Radar target index
+            radar_target: *entity_indexes.get(&update.radar_target).unwrap_or(&-1),
+        }),
+        next_update_offset,
+        previous_update_offset,
+    }
+}
+
+/// An entity's event, as a timeline entry
+fn event_entry(
+    event: &flt::EntityEvent,
+    next_update_offset: u32,
+    previous_update_offset: u32,
+) -> TimelineEntry {
+    let payload = match event.payload {
+        flt::EntityEventPayload::SwitchEvent(switch) => TimelineEntryPayload::Switch(Switch {
+            switch_index: switch.switch_number,
+            switch_value: switch.new_switch_value,
+            previous_switch_value: switch.previous_switch_value,
+        }),
+        flt::EntityEventPayload::DofEvent(dof) => TimelineEntryPayload::Dof(Dof {
+            dof_index: dof.dof_number,
+            dof_value: dof.new_dof_value,
+            previous_dof_value: dof.previous_dof_value,
+        }),
+    };
+    TimelineEntry {
+        time: event.time,
+        payload,
+        next_update_offset,
+        previous_update_offset,
+    }
 }
 
 fn write_general_events<W: Write>(flight: &Flight, w: &mut W) -> Result<()> {
     let mut trailers = Vec::with_capacity(flight.general_events.len());
 
     for (i, event) in flight.general_events.iter().enumerate() {
-        let i = i as u32;
+        let i = i as i32;
         trailers.push(GeneralEventTrailer {
-            stop: event.stop,
+            time_end: event.stop,
             index: i,
         });
 
-        write_u8(event.type_byte, w)?;
-        write_u32(i, w)?;
-        write_f32(event.start, w)?;
-        write_f32(event.stop, w)?;
-        write_i32(event.kind, w)?;
-        write_i32(event.user, w)?;
-        write_u32(event.flags, w)?;
-        write_f32(event.scale, w)?;
-        write_f32(event.x, w)?;
-        write_f32(event.y, w)?;
-        write_f32(event.z, w)?;
-        write_f32(event.dx, w)?;
-        write_f32(event.dy, w)?;
-        write_f32(event.dz, w)?;
-        write_f32(event.roll, w)?;
-        write_f32(event.pitch, w)?;
-        write_f32(event.yaw, w)?;
+        general_event_header(i, event).write(w)?;
     }
 
     // A list of "trailers" follows the event list, sorted chronologically.
-    trailers.par_sort_by(|a, b| a.stop.partial_cmp(&b.stop).expect("Nooo, not NaNs!"));
+    // (A garbage FLT can give us NaNs, so don't trust partial_cmp().)
+    trailers.par_sort_by(|a, b| a.time_end.total_cmp(&b.time_end));
     for trailer in trailers {
-        write_f32(trailer.stop, w)?;
-        write_u32(trailer.index, w)?;
+        trailer.write(w)?;
     }
 
     Ok(())
 }
 
+fn general_event_header(index: i32, event: &flt::GeneralEvent) -> GeneralEventHeader {
+    GeneralEventHeader {
+        event_type: event.type_byte,
+        index,
+        time: event.start,
+        time_end: event.stop,
+        kind: event.kind,
+        user: event.user,
+        flags: event.flags as i32,
+        scale: event.scale,
+        x: event.x,
+        y: event.y,
+        z: event.z,
+        dx: event.dx,
+        dy: event.dy,
+        dz: event.dz,
+        roll: event.roll,
+        pitch: event.pitch,
+        yaw: event.yaw,
+    }
+}
+
 fn write_feature_events<W: Write>(
     flight: &Flight,
     feature_indexes: &FxHashMap<i32, i32>,
     w: &mut W,
 ) -> Result<()> {
     for event in &flight.feature_events {
-        let index = *feature_indexes
-            .get(&event.feature_uid)
-            .expect("Feature event with no feature");
-        write_f32(event.time, w)?;
-        write_i32(index, w)?;
-        write_i32(event.new_status, w)?;
-        write_i32(event.previous_status, w)?;
+        feature_event(event, feature_indexes).write(w)?;
     }
 
     Ok(())
 }
 
+fn feature_event(
+    event: &flt::FeatureEvent,
+    feature_indexes: &FxHashMap<i32, i32>,
+) -> acmitape::FeatureEvent {
+    let index = *feature_indexes
+        .get(&event.feature_uid)
+        .expect("Feature event with no feature");
+    acmitape::FeatureEvent {
+        time: event.time,
+        index,
+        new_status: event.new_status,
+        previous_status: event.previous_status,
+    }
+}
+
 fn write_callsigns<W: Write>(flight: &Flight, callsign_ids: &[i32], w: &mut W) -> Result<()> {
     if callsign_ids.is_empty() {
         warn!("No callsigns to save!");
@@ -784,8 +1539,358 @@ fn write_callsigns<W: Write>(flight: &Flight, callsign_ids: &[i32], w: &mut W) -
 
     write_u32(callsign_ids.len() as u32, w)?;
     for callsign in callsign_ids.iter().map(|id| &flight.callsigns[id]) {
-        w.write_all(&callsign.label)?;
-        write_i32(callsign.team_color, w)?;
+        callsign.write(w)?;
     }
     Ok(())
 }
+
+/// Reads a VHS file back into a [`Flight`].
+///
+/// The flight's UIDs are the (compacted) ones in the VHS, not whatever
+/// BMS originally used, but they're consistent throughout
+/// (radar targets, feature leads, feature events, and callsigns
+/// all refer to the same UIDs).
+/// Since the VHS doesn't say which tracers and sounds came from which FLT record,
+/// general events come back in the same order they were written.
+pub fn read<R: Read>(r: R) -> Result<Flight> {
+    let mut counted = CountedRead::new(r);
+    let r = &mut counted;
+
+    let header = TapeHeader::read(r).context("Couldn't read VHS header")?;
+    ensure!(
+        header.file_id == TapeHeader::MAGIC,
+        "Expected magic bytes 'EPAT', got {:?} ({})",
+        &header.file_id,
+        String::from_utf8_lossy(&header.file_id)
+    );
+
+    let entities = read_section(
+        r,
+        "entities",
+        header.entity_offset,
+        header.entity_count,
+        acmitape::Entity::read,
+    )?;
+    let features = read_section(
+        r,
+        "features",
+        header.feature_offset,
+        header.feature_count,
+        acmitape::Entity::read,
+    )?;
+    let positions = read_section(
+        r,
+        "position updates",
+        header.position_offset,
+        header.position_count,
+        TimelineEntry::read,
+    )?;
+    let entity_events = read_section(
+        r,
+        "entity events",
+        header.entity_event_offset,
+        header.entity_event_count,
+        TimelineEntry::read,
+    )?;
+    let general_events = read_section(
+        r,
+        "general events",
+        header.general_event_offset,
+        header.general_event_count,
+        GeneralEventHeader::read,
+    )?;
+    // Trailers are just the general events sorted by end time.
+    // We'll rebuild them when we write.
+    read_section(
+        r,
+        "general event trailers",
+        header.general_event_trailer_offset,
+        header.general_event_count,
+        GeneralEventTrailer::read,
+    )?;
+    let feature_events = read_section(
+        r,
+        "feature events",
+        header.feature_event_offset,
+        header.feature_event_count,
+        acmitape::FeatureEvent::read,
+    )?;
+
+    // For reasons I don't understand, the callsign count is saved
+    // in four bytes preceding the block instead of as `text_event_count`
+    // in the file header.
+    ensure_posit(r, "callsigns", header.text_event_offset)?;
+    let callsign_count = read_i32(r).context("Couldn't read callsign count")?;
+    ensure!(
+        callsign_count >= 0,
+        "Negative ({}) callsign count",
+        callsign_count
+    );
+    let callsigns = (0..callsign_count)
+        .map(|_| acmitape::CallsignRecord::read(r))
+        .collect::<Result<Vec<_>>>()
+        .context("Couldn't read callsigns")?;
+
+    let mut flight = Flight {
+        tod_offset: header.tod_offset,
+        start_time: header.start_time,
+        end_time: header.start_time + header.total_play_time,
+        ..Default::default()
+    };
+
+    // Positions, events, and radar targets refer to entities by index,
+    // features refer to other features by index, and so on.
+    // Convert those back into UIDs.
+    let entity_uid = |index: i32| -> i32 {
+        usize::try_from(index)
+            .ok()
+            .and_then(|i| entities.get(i))
+            .map(|e| e.uid)
+            .unwrap_or(-1)
+    };
+    let feature_uid = |index: i32| -> Option<i32> {
+        usize::try_from(index)
+            .ok()
+            .and_then(|i| features.get(i))
+            .map(|f| f.uid)
+    };
+
+    flight.entities.reserve(entities.len());
+    for entity in &entities {
+        let position_updates = walk_timeline(
+            &positions,
+            header.position_offset,
+            entity.first_position_offset,
+        )
+        .with_context(|| format!("Bad position updates for entity {}", entity.uid))?
+        .map(|entry| match entry.payload {
+            TimelineEntryPayload::Pos(pos) => Ok(flt::EntityPositionUpdate {
+                time: entry.time,
+                x: pos.x,
+                y: pos.y,
+                z: pos.z,
+                pitch: pos.pitch,
+                roll: pos.roll,
+                yaw: pos.yaw,
+                radar_target: entity_uid(pos.radar_target),
+            }),
+            _ => Err(anyhow!(
+                "Entity {} has a non-position entry in its position updates",
+                entity.uid
+            )),
+        })
+        .collect::<Result<Vec<_>>>()?;
+        ensure!(
+            !position_updates.is_empty(),
+            "Entity {} has no position updates",
+            entity.uid
+        );
+
+        let events = if entity.first_event_offset == 0 {
+            Vec::new()
+        } else {
+            walk_timeline(
+                &entity_events,
+                header.entity_event_offset,
+                entity.first_event_offset,
+            )
+            .with_context(|| format!("Bad events for entity {}", entity.uid))?
+            .map(|entry| {
+                let payload = match entry.payload {
+                    TimelineEntryPayload::Switch(switch) => {
+                        flt::EntityEventPayload::SwitchEvent(flt::SwitchEvent {
+                            switch_number: switch.switch_index,
+                            new_switch_value: switch.switch_value,
+                            previous_switch_value: switch.previous_switch_value,
+                        })
+                    }
+                    TimelineEntryPayload::Dof(dof) => {
+                        flt::EntityEventPayload::DofEvent(flt::DofEvent {
+                            dof_number: dof.dof_index,
+                            new_dof_value: dof.dof_value,
+                            previous_dof_value: dof.previous_dof_value,
+                        })
+                    }
+                    TimelineEntryPayload::Pos(_) => {
+                        bail!("Entity {} has a position update in its events", entity.uid)
+                    }
+                };
+                Ok(flt::EntityEvent {
+                    time: entry.time,
+                    payload,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?
+        };
+
+        let previous = flight.entities.insert(
+            entity.uid,
+            flt::EntityData {
+                position_data: Some(flt::EntityPositionData {
+                    kind: entity.kind,
+                    flags: entity.flags,
+                    position_updates,
+                }),
+                events,
+            },
+        );
+        ensure!(previous.is_none(), "Duplicate entity UID {}", entity.uid);
+    }
+
+    flight.features.reserve(features.len());
+    for feature in &features {
+        // Features get exactly one position.
+        let position = walk_timeline(
+            &positions,
+            header.position_offset,
+            feature.first_position_offset,
+        )
+        .and_then(|mut entries| entries.next().ok_or_else(|| anyhow!("No entries")))
+        .with_context(|| format!("Bad position for feature {}", feature.uid))?;
+        let pos = match position.payload {
+            TimelineEntryPayload::Pos(pos) => pos,
+            _ => bail!("Feature {} has a non-position entry", feature.uid),
+        };
+
+        let previous = flight.features.insert(
+            feature.uid,
+            flt::FeatureData {
+                kind: feature.kind,
+                lead_uid: feature_uid(feature.lead_index).unwrap_or(-1),
+                slot: feature.slot,
+                special_flags: feature.special_flags,
+                time: position.time,
+                x: pos.x,
+                y: pos.y,
+                z: pos.z,
+                pitch: pos.pitch,
+                roll: pos.roll,
+                yaw: pos.yaw,
+            },
+        );
+        ensure!(previous.is_none(), "Duplicate feature UID {}", feature.uid);
+    }
+
+    flight.general_events = general_events
+        .iter()
+        .map(|event| flt::GeneralEvent {
+            type_byte: event.event_type,
+            start: event.time,
+            stop: event.time_end,
+            kind: event.kind,
+            user: event.user,
+            flags: event.flags as u32,
+            scale: event.scale,
+            x: event.x,
+            y: event.y,
+            z: event.z,
+            dx: event.dx,
+            dy: event.dy,
+            dz: event.dz,
+            roll: event.roll,
+            pitch: event.pitch,
+            yaw: event.yaw,
+        })
+        .collect();
+
+    flight.feature_events = feature_events
+        .iter()
+        .map(|event| {
+            let feature_uid = feature_uid(event.index)
+                .ok_or_else(|| anyhow!("Feature event for nonexistent feature {}", event.index))?;
+            Ok(flt::FeatureEvent {
+                time: event.time,
+                feature_uid,
+                new_status: event.new_status,
+                previous_status: event.previous_status,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // We (and BMS) index the callsign list by UID.
+    for (uid, callsign) in callsigns.into_iter().enumerate() {
+        let uid = uid as i32;
+        if flight.entities.contains_key(&uid) || flight.features.contains_key(&uid) {
+            flight.callsigns.insert(uid, callsign);
+        }
+    }
+
+    Ok(flight)
+}
+
+fn ensure_posit<R: Read>(r: &CountedRead<R>, section: &str, expected: u32) -> Result<()> {
+    let posit = r.get_posit();
+    ensure!(
+        expected == posit,
+        "Expected {} to start at {}, currently at {}",
+        section,
+        expected,
+        posit
+    );
+    Ok(())
+}
+
+/// Reads `count` records of a section starting at `offset`.
+fn read_section<R: Read, T, F>(
+    r: &mut CountedRead<R>,
+    section: &str,
+    offset: u32,
+    count: i32,
+    mut read_one: F,
+) -> Result<Vec<T>>
+where
+    F: FnMut(&mut CountedRead<R>) -> Result<T>,
+{
+    ensure_posit(r, section, offset)?;
+    ensure!(count >= 0, "Negative ({}) {} count", count, section);
+
+    // Don't trust the count for allocation size - a garbage file could
+    // have us reserving gigabytes.
+    let mut records = Vec::with_capacity(std::cmp::min(count as usize, 1024 * 1024));
+    for _ in 0..count {
+        records.push(read_one(r).with_context(|| format!("Couldn't read {}", section))?);
+    }
+    Ok(records)
+}
+
+/// Follows a doubly-linked list of timeline entries, starting at `head`.
+///
+/// `entries` is the whole section, which starts at `section_offset` in the file.
+fn walk_timeline(
+    entries: &[TimelineEntry],
+    section_offset: u32,
+    head: u32,
+) -> Result<impl Iterator<Item = &TimelineEntry>> {
+    // is_multiple_of() needs a newer Rust than we otherwise do.
+    #[allow(clippy::manual_is_multiple_of)]
+    let index_of = move |offset: u32| -> Result<usize> {
+        ensure!(
+            offset >= section_offset && (offset - section_offset) % ENTITY_UPDATE_SIZE == 0,
+            "Offset {} isn't a timeline entry",
+            offset
+        );
+        let index = ((offset - section_offset) / ENTITY_UPDATE_SIZE) as usize;
+        ensure!(
+            index < entries.len(),
+            "Offset {} is past the section",
+            offset
+        );
+        Ok(index)
+    };
+
+    // Walk the list up front to make sure it's sane: no bad offsets, and no cycles.
+    // (A list can't be longer than the whole section.)
+    let mut indexes = Vec::new();
+    let mut next = head;
+    while next != 0 {
+        ensure!(
+            indexes.len() < entries.len(),
+            "Timeline list starting at {} has a cycle",
+            head
+        );
+        let index = index_of(next)?;
+        indexes.push(index);
+        next = entries[index].next_update_offset;
+    }
+    Ok(indexes.into_iter().map(move |i| &entries[i]))
+}
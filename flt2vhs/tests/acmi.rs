@@ -0,0 +1,132 @@
+//! Make sure flights come out as text ACMI files Tacview can read.
+
+use flt2vhs::{acmi, flt, Flight};
+
+mod common;
+use common::straight_line;
+
+#[test]
+fn objects_come_and_go() {
+    let mut flight = Flight {
+        tod_offset: 3600.0,
+        start_time: 0.0,
+        end_time: 10.0,
+        ..Default::default()
+    };
+    let mut jet = straight_line(0.0, 1000.0, 0.0, 10.0, 1.0, flt::ENTITY_FLAG_AIRCRAFT);
+    for update in &mut jet.position_data.as_mut().unwrap().position_updates {
+        update.yaw = std::f32::consts::FRAC_PI_2;
+    }
+    flight.entities.insert(1, jet);
+    flight.entities.insert(
+        2,
+        straight_line(2000.0, 1000.0, 2.0, 5.0, 1.0, flt::ENTITY_FLAG_MISSILE),
+    );
+    flight
+        .callsigns
+        .insert(1, flt::CallsignRecord::new("Viper1,1", 2));
+
+    let mut acmi = Vec::new();
+    acmi::write_to(&flight, &mut acmi).unwrap();
+    let acmi = String::from_utf8(acmi).unwrap();
+    let lines = acmi.lines().collect::<Vec<_>>();
+
+    assert_eq!(lines[0], "FileType=text/acmi/tacview");
+    assert_eq!(lines[1], "FileVersion=2.2");
+
+    // Frames are times of day, in order.
+    let frames = lines
+        .iter()
+        .filter_map(|l| l.strip_prefix('#'))
+        .map(|t| t.parse::<f32>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(frames.first(), Some(&3600.0));
+    assert_eq!(frames.last(), Some(&3610.0));
+    assert!(frames.windows(2).all(|pair| pair[0] < pair[1]));
+
+    // Objects are described when they show up, then just move.
+    let jet = lines
+        .iter()
+        .filter(|l| l.starts_with("100000001,"))
+        .collect::<Vec<_>>();
+    assert_eq!(jet.len(), 11);
+    assert!(jet[0].ends_with(",Name=Viper1\\,1,Color=Blue,Type=Air+FixedWing"));
+    assert!(jet[1..].iter().all(|l| !l.contains("Name=")));
+    let transform = jet[0]
+        .strip_prefix("100000001,T=")
+        .unwrap()
+        .split(',')
+        .next()
+        .unwrap()
+        .split('|')
+        .map(|v| v.parse::<f64>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(transform, [0.0, 0.0, 6096.0, 0.0, 0.0, 90.0]);
+
+    let missile = lines
+        .iter()
+        .position(|l| l.starts_with("100000002,"))
+        .unwrap();
+    assert!(lines[missile].ends_with(",Type=Weapon+Missile"));
+
+    // The missile is removed when it stops updating,
+    // but the jet is still around when the recording ends.
+    let removed = lines
+        .iter()
+        .filter(|l| l.starts_with('-'))
+        .collect::<Vec<_>>();
+    assert_eq!(removed, [&"-100000002"]);
+    let removed_at = lines.iter().position(|l| l.starts_with('-')).unwrap();
+    let frame = lines[..removed_at]
+        .iter()
+        .rev()
+        .find(|l| l.starts_with('#'))
+        .unwrap();
+    assert_eq!(*frame, "#3605.00");
+}
+
+#[test]
+fn entities_and_features_stay_apart() {
+    // Entity and feature UIDs are separate, so they can collide.
+    let mut flight = Flight {
+        start_time: 0.0,
+        end_time: 10.0,
+        ..Default::default()
+    };
+    flight.entities.insert(
+        5,
+        straight_line(2000.0, 1000.0, 2.0, 5.0, 1.0, flt::ENTITY_FLAG_MISSILE),
+    );
+    flight.features.insert(
+        5,
+        flt::FeatureData {
+            kind: 1,
+            lead_uid: 5,
+            x: 500.0,
+            ..Default::default()
+        },
+    );
+
+    let mut acmi = Vec::new();
+    acmi::write_to(&flight, &mut acmi).unwrap();
+    let acmi = String::from_utf8(acmi).unwrap();
+
+    let objects = acmi
+        .lines()
+        .filter(|l| !l.starts_with('#') && !l.starts_with('-') && l.contains(",T="))
+        .map(|l| l.split(',').next().unwrap())
+        .collect::<std::collections::BTreeSet<_>>();
+    assert_eq!(objects.len(), 2);
+
+    // Removing the missile leaves the feature alone.
+    let removed = acmi
+        .lines()
+        .filter_map(|l| l.strip_prefix('-'))
+        .collect::<Vec<_>>();
+    assert_eq!(removed.len(), 1);
+    let feature = acmi
+        .lines()
+        .find(|l| l.ends_with("Type=Ground+Static"))
+        .unwrap();
+    assert_ne!(feature.split(',').next(), Some(removed[0]));
+}
@@ -0,0 +1,65 @@
+//! Helpers shared by the integration tests
+//! (each of which only uses some of them)
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use flt2vhs::flt;
+
+/// An F-16, as far as BMS is concerned
+const VIPER: i32 = 42;
+
+/// Where the golden files live (see `tests/golden/README.md`)
+pub fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+pub fn golden_path(name: &str) -> PathBuf {
+    golden_dir().join(name)
+}
+
+pub fn golden_bytes(name: &str) -> Vec<u8> {
+    fs::read(golden_path(name)).unwrap()
+}
+
+/// Something (an F-16, as far as its kind goes) moving in a straight line
+/// (along x) at `speed` feet per second, with an update every `step` seconds
+/// from `start` to `end`.
+pub fn straight_line(
+    x: f32,
+    speed: f32,
+    start: f32,
+    end: f32,
+    step: f32,
+    flags: u32,
+) -> flt::EntityData {
+    let yaw = if speed < 0.0 {
+        std::f32::consts::PI
+    } else {
+        0.0
+    };
+    let position_updates = (0..)
+        .map(|i| start + i as f32 * step)
+        .take_while(|t| *t <= end)
+        .map(|time| flt::EntityPositionUpdate {
+            time,
+            x: x + speed * (time - start),
+            y: 0.0,
+            z: -20_000.0,
+            pitch: 0.0,
+            roll: 0.0,
+            yaw,
+            radar_target: -1,
+        })
+        .collect();
+    flt::EntityData {
+        position_data: Some(flt::EntityPositionData {
+            kind: VIPER,
+            flags,
+            position_updates,
+        }),
+        events: Vec::new(),
+    }
+}
@@ -0,0 +1,259 @@
+//! Mangle the golden FLT files every way we can think of
+//! and make sure parsing, merging, and converting them never panics.
+
+use std::path::PathBuf;
+
+use flt2vhs::{flt, flt::Flight, merge_flights, vhs};
+
+mod common;
+use common::golden_bytes;
+
+/// A tiny xorshift so the mangling is the same every run
+/// without pulling in a whole RNG crate.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Parses the bytes from a reader, straight from the slice, and in chunks,
+/// and makes sure all the parsers saw the same thing.
+fn assert_parsers_agree(bytes: &[u8]) -> Flight {
+    let read = Flight::parse(bytes);
+    let decoded = Flight::parse_bytes(bytes);
+    for chunk_size in &[1, 1000] {
+        assert_same_flight(&decoded, &Flight::parse_in_chunks(bytes, *chunk_size));
+    }
+    assert_same_flight(&read, &decoded);
+    decoded
+}
+
+fn assert_same_flight(expected: &Flight, actual: &Flight) {
+    let flt = |flight: &Flight| {
+        let mut out = Vec::new();
+        flight.write(&mut out).ok().map(|()| out)
+    };
+    assert_eq!(flt(expected), flt(actual));
+    assert_eq!(expected.start_time.to_bits(), actual.start_time.to_bits());
+    assert_eq!(expected.end_time.to_bits(), actual.end_time.to_bits());
+    assert_eq!(expected.tod_offset.to_bits(), actual.tod_offset.to_bits());
+
+    let where_and_what = |flight: &Flight| {
+        flight
+            .corruption
+            .as_ref()
+            .map(|c| (c.offset(), c.type_byte(), c.bytes_left, c.records_read))
+    };
+    assert_eq!(where_and_what(expected), where_and_what(actual));
+}
+
+/// Parses the bytes, merges them into a clean flight and vice versa,
+/// and writes all of it out as VHS. Any of it can fail, but none of it can panic.
+fn convert_all_the_ways(bytes: &[u8], clean: &Flight) -> Flight {
+    let flight = assert_parsers_agree(bytes);
+    let _ = vhs::write_to(&flight, std::io::sink());
+
+    let paths = [PathBuf::from("a.flt"), PathBuf::from("b.flt")];
+    for merged in merge_flights(vec![clean.clone(), flight.clone()], &paths)
+        .into_iter()
+        .chain(merge_flights(vec![flight.clone(), clean.clone()], &paths))
+    {
+        let _ = vhs::write_to(&merged.flight, std::io::sink());
+    }
+    flight
+}
+
+#[test]
+fn parsers_agree_on_golden_files() {
+    for name in &[
+        "single.flt",
+        "merge-1.flt",
+        "merge-2.flt",
+        "truncated.flt",
+        "garbage.flt",
+    ] {
+        assert_parsers_agree(&golden_bytes(name));
+    }
+}
+
+#[test]
+fn truncated_anywhere() {
+    let bytes = golden_bytes("single.flt");
+    let clean = Flight::parse(&bytes[..]);
+    assert!(!clean.corrupted());
+
+    // Every record boundary (and then some) in the first few hundred bytes,
+    // then spaced out through the rest of the file.
+    let ends = (0..512).chain((512..bytes.len()).step_by(97));
+    for end in ends {
+        convert_all_the_ways(&bytes[..end], &clean);
+    }
+}
+
+#[test]
+fn garbage_bytes() {
+    let bytes = golden_bytes("single.flt");
+    let clean = Flight::parse(&bytes[..]);
+    let mut rng = XorShift(0x5eed);
+
+    for _ in 0..200 {
+        let mut mangled = bytes.clone();
+        for _ in 0..1 + rng.below(8) {
+            let at = rng.below(mangled.len());
+            mangled[at] = rng.next() as u8;
+        }
+        convert_all_the_ways(&mangled, &clean);
+    }
+}
+
+#[test]
+fn nonsense_values() {
+    let bytes = golden_bytes("single.flt");
+    let clean = Flight::parse(&bytes[..]);
+    let mut rng = XorShift(0xf00d);
+
+    // Plausible-looking records with the worst numbers we can find:
+    // huge callsign counts, NaN times, maxed-out IDs...
+    let nasty: [[u8; 4]; 6] = [
+        i32::MAX.to_le_bytes(),
+        i32::MIN.to_le_bytes(),
+        (-1i32).to_le_bytes(),
+        f32::NAN.to_le_bytes(),
+        f32::INFINITY.to_le_bytes(),
+        f32::NEG_INFINITY.to_le_bytes(),
+    ];
+    for _ in 0..200 {
+        let mut mangled = bytes.clone();
+        for _ in 0..1 + rng.below(4) {
+            let at = rng.below(mangled.len() - 4);
+            mangled[at..at + 4].copy_from_slice(&nasty[rng.below(nasty.len())]);
+        }
+        convert_all_the_ways(&mangled, &clean);
+    }
+}
+
+#[test]
+fn pure_noise() {
+    let clean = Flight::parse(&golden_bytes("single.flt")[..]);
+    let mut rng = XorShift(0xbad);
+
+    for _ in 0..100 {
+        let noise = (0..rng.below(4096))
+            .map(|_| rng.next() as u8)
+            .collect::<Vec<_>>();
+        convert_all_the_ways(&noise, &clean);
+    }
+}
+
+#[test]
+fn truncation_report() {
+    let bytes = golden_bytes("truncated.flt");
+    let flight = Flight::parse(&bytes[..]);
+    let corruption = flight
+        .corruption
+        .expect("truncated.flt should be corrupted");
+
+    assert!(corruption.truncated());
+    // The bad record runs right up to the end of the file.
+    assert_eq!(
+        corruption.offset() + corruption.bytes_left,
+        bytes.len() as u64
+    );
+    // BMS writes callsigns last, so that's what gets cut off.
+    assert_eq!(corruption.type_byte(), Some(flt::REC_TYPE_CALLSIGN_LIST));
+    assert!(corruption.total_records_read() > 0);
+}
+
+#[test]
+fn garbage_report() {
+    let bytes = golden_bytes("garbage.flt");
+    let flight = Flight::parse(&bytes[..]);
+    let corruption = flight.corruption.expect("garbage.flt should be corrupted");
+
+    assert!(!corruption.truncated());
+    let offset = corruption.offset() as usize;
+    assert_eq!(corruption.type_byte(), Some(bytes[offset]));
+    assert_eq!(corruption.bytes_left, (bytes.len() - offset) as u64);
+
+    // Everything before the garbage should have parsed just fine.
+    let before = Flight::parse(&bytes[..offset]);
+    assert!(!before.corrupted());
+    assert_eq!(
+        before.entities.len() + before.features.len(),
+        flight.entities.len() + flight.features.len()
+    );
+}
+
+/// Counts every position update and event in the flight
+fn record_count(flight: &Flight) -> usize {
+    flight
+        .entities
+        .values()
+        .map(|e| e.events.len() + e.position_data.as_ref().unwrap().position_updates.len())
+        .sum::<usize>()
+        + flight.features.len()
+        + flight.general_events.len()
+        + flight.feature_events.len()
+}
+
+#[test]
+fn recover_from_garbage() {
+    let bytes = golden_bytes("single.flt");
+    let clean = Flight::parse(&bytes[..]);
+    let mut rng = XorShift(0xdead);
+
+    for _ in 0..50 {
+        // Splat a few garbage bytes somewhere in the middle half of the file.
+        let mut mangled = bytes.clone();
+        let at = bytes.len() / 4 + rng.below(bytes.len() / 2);
+        let garbage = (0..1 + rng.below(16))
+            .map(|_| rng.next() as u8)
+            .collect::<Vec<_>>();
+        mangled.splice(at..at, garbage);
+
+        let stopped = Flight::parse(&mangled[..]);
+        let recovered = Flight::parse_recovering(&mangled);
+        if !stopped.corrupted() {
+            // The garbage happened to look like good records. Oh well.
+            continue;
+        }
+
+        let corruption = recovered.corruption.as_ref().unwrap();
+        assert!(!corruption.skipped.is_empty());
+        assert!(corruption.records_recovered > 0);
+        assert!(!corruption.truncated());
+
+        // We should lose no more than a few records around the garbage,
+        // and get the callsigns at the end back.
+        assert!(record_count(&recovered) > record_count(&stopped));
+        assert!(record_count(&recovered) + 8 >= record_count(&clean));
+        assert_eq!(recovered.callsigns.len(), clean.callsigns.len());
+    }
+}
+
+#[test]
+fn recover_from_truncation() {
+    let bytes = golden_bytes("truncated.flt");
+    let stopped = Flight::parse(&bytes[..]);
+    let recovered = Flight::parse_recovering(&bytes);
+
+    // There's nothing after the end of the file to recover.
+    let corruption = recovered.corruption.as_ref().unwrap();
+    assert!(corruption.truncated());
+    assert_eq!(corruption.records_recovered, 0);
+    assert_eq!(corruption.skipped.len(), 1);
+    assert_eq!(
+        corruption.skipped[0],
+        corruption.offset()..bytes.len() as u64
+    );
+    assert_eq!(record_count(&recovered), record_count(&stopped));
+}
@@ -0,0 +1,359 @@
+//! Golden file tests: run flt2vhs on the FLT files in `tests/golden`
+//! and make sure we get the same VHS files we got last time.
+//!
+//! Set `FLT2VHS_BLESS=1` to overwrite the expected files instead
+//! (see `tests/golden/README.md`).
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use acmitape::{primitives::read_i32, *};
+use flt2vhs::{flt, Flight};
+
+mod common;
+use common::{golden_path, straight_line};
+
+/// Copies the given inputs into `dir` and runs flt2vhs on them there,
+/// returning its exit code.
+fn convert(dir: &Path, inputs: &[&str], extra_args: &[&str]) -> i32 {
+    for input in inputs {
+        fs::copy(golden_path(input), dir.join(input)).unwrap();
+    }
+
+    let status = flt2vhs(dir)
+        .args(extra_args)
+        .args(inputs)
+        .status()
+        .expect("Couldn't run flt2vhs");
+    status.code().expect("flt2vhs was killed")
+}
+
+fn flt2vhs(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_flt2vhs"));
+    command.current_dir(dir);
+    command
+}
+
+/// Compares the VHS flt2vhs made to the golden one,
+/// byte for byte and then record by record to explain any differences.
+fn check_golden(dir: &Path, vhs_name: &str) {
+    let actual = fs::read(dir.join(vhs_name)).expect("flt2vhs didn't make a VHS");
+    let expected_path = golden_path(vhs_name);
+
+    if std::env::var_os("FLT2VHS_BLESS").is_some() {
+        fs::write(&expected_path, &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read(&expected_path).unwrap();
+    if actual == expected {
+        return;
+    }
+
+    let actual_records = dump(&actual);
+    let expected_records = dump(&expected);
+    let first_difference = actual_records
+        .iter()
+        .zip(&expected_records)
+        .position(|(a, e)| a != e)
+        .unwrap_or_else(|| std::cmp::min(actual_records.len(), expected_records.len()));
+    let differences = actual_records
+        .iter()
+        .zip(&expected_records)
+        .filter(|(a, e)| a != e)
+        .count();
+
+    panic!(
+        "{} ({} bytes) doesn't match the golden file ({} bytes)!\n\
+         {} records differ ({} vs. {} total); the first is:\n\
+         expected: {}\n  actual: {}",
+        vhs_name,
+        actual.len(),
+        expected.len(),
+        differences,
+        actual_records.len(),
+        expected_records.len(),
+        expected_records
+            .get(first_difference)
+            .map_or("<nothing>", |r| r.as_str()),
+        actual_records
+            .get(first_difference)
+            .map_or("<nothing>", |r| r.as_str()),
+    );
+}
+
+/// Describes each record in the VHS on its own line, vhscat-style,
+/// so we can point out what changed.
+fn dump(vhs: &[u8]) -> Vec<String> {
+    let mut records = Vec::new();
+    if let Err(e) = dump_records(vhs, &mut records) {
+        records.push(format!("error: {:#}", e));
+    }
+    records
+}
+
+fn dump_records(vhs: &[u8], records: &mut Vec<String>) -> anyhow::Result<()> {
+    let mut r = CountedRead::new(vhs);
+    let r = &mut r;
+
+    let header = TapeHeader::read(r)?;
+    records.push(format!("header: {:?}", header));
+
+    #[rustfmt::skip]
+    let sections = [
+        ("entity", header.entity_offset, header.entity_count, read_entity as Reader),
+        ("feature", header.feature_offset, header.feature_count, read_entity),
+        ("position", header.position_offset, header.position_count, read_timeline),
+        ("entity event", header.entity_event_offset, header.entity_event_count, read_timeline),
+        ("general event", header.general_event_offset, header.general_event_count, read_general),
+        ("general event trailer", header.general_event_trailer_offset, header.general_event_count, read_trailer),
+        ("feature event", header.feature_event_offset, header.feature_event_count, read_feature_event),
+    ];
+    for (name, offset, count, read_one) in &sections {
+        anyhow::ensure!(
+            r.get_posit() == *offset,
+            "{} section should start at {}, not {}",
+            name,
+            offset,
+            r.get_posit()
+        );
+        for i in 0..*count {
+            records.push(format!("{} {}: {}", name, i, read_one(r)?));
+        }
+    }
+
+    let callsign_count = read_i32(r)?;
+    for i in 0..callsign_count {
+        let callsign = CallsignRecord::read(r)?;
+        records.push(format!(
+            "callsign {}: {:?} (team {})",
+            i,
+            callsign.label_lossy(),
+            callsign.team_color
+        ));
+    }
+
+    anyhow::ensure!(
+        r.get_posit() as usize == vhs.len(),
+        "{} trailing bytes",
+        vhs.len() - r.get_posit() as usize
+    );
+    Ok(())
+}
+
+type Reader = fn(&mut CountedRead<&[u8]>) -> anyhow::Result<String>;
+
+fn read_entity(r: &mut CountedRead<&[u8]>) -> anyhow::Result<String> {
+    Ok(format!("{:?}", Entity::read(r)?))
+}
+
+fn read_timeline(r: &mut CountedRead<&[u8]>) -> anyhow::Result<String> {
+    Ok(format!("{:?}", TimelineEntry::read(r)?))
+}
+
+fn read_general(r: &mut CountedRead<&[u8]>) -> anyhow::Result<String> {
+    Ok(format!("{:?}", GeneralEventHeader::read(r)?))
+}
+
+fn read_trailer(r: &mut CountedRead<&[u8]>) -> anyhow::Result<String> {
+    Ok(format!("{:?}", GeneralEventTrailer::read(r)?))
+}
+
+fn read_feature_event(r: &mut CountedRead<&[u8]>) -> anyhow::Result<String> {
+    Ok(format!("{:?}", FeatureEvent::read(r)?))
+}
+
+#[test]
+fn single_file() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(convert(dir.path(), &["single.flt"], &[]), 0);
+    check_golden(dir.path(), "single.vhs");
+}
+
+#[test]
+fn merged_files() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(convert(dir.path(), &["merge-1.flt", "merge-2.flt"], &[]), 0);
+    check_golden(dir.path(), "merge-1.vhs");
+    assert!(
+        !dir.path().join("merge-2.vhs").exists(),
+        "merge-2.flt should have been merged into merge-1.vhs"
+    );
+}
+
+#[test]
+fn no_merge() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(
+        convert(dir.path(), &["merge-1.flt", "merge-2.flt"], &["--no-merge"]),
+        0
+    );
+    assert!(dir.path().join("merge-1.vhs").exists());
+    assert!(dir.path().join("merge-2.vhs").exists());
+}
+
+#[test]
+fn merge_report() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(
+        convert(
+            dir.path(),
+            &["merge-1.flt", "merge-2.flt"],
+            &["--merge-report", "report.json"]
+        ),
+        0
+    );
+    check_golden(dir.path(), "merge-1.vhs");
+
+    let report: serde_json::Value =
+        serde_json::from_slice(&fs::read(dir.path().join("report.json")).unwrap()).unwrap();
+    let outputs = report["outputs"].as_array().unwrap();
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(outputs[0]["output"], "merge-1.vhs");
+    assert_eq!(
+        outputs[0]["inputs"],
+        serde_json::json!(["merge-1.flt", "merge-2.flt"])
+    );
+
+    let seams = report["merges"].as_array().unwrap();
+    assert_eq!(seams.len(), 1);
+
+    let seam = &seams[0];
+    assert_eq!(seam["previous"], "merge-1.flt");
+    assert_eq!(seam["next"], "merge-2.flt");
+    assert_eq!(seam["decision"], "merged");
+    assert_eq!(seam["gap"], 0.5);
+    assert_eq!(seam["previous_tod_offset"], seam["next_tod_offset"]);
+    let entities = seam["entities"].as_array().unwrap();
+    assert!(entities.iter().any(|e| e["outcome"] == "matched"));
+    assert!(entities.iter().any(|e| e["outcome"] == "new"));
+    assert!(!seam["features"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn out_of_order() {
+    // Files are merged in the order they were recorded, not the order they were given.
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(convert(dir.path(), &["merge-2.flt", "merge-1.flt"], &[]), 0);
+    check_golden(dir.path(), "merge-1.vhs");
+    assert!(!dir.path().join("merge-2.vhs").exists());
+}
+
+/// Writes a FLT to `dir` with one aircraft flying from `start` to `end`
+fn write_flt(dir: &Path, name: &str, start: f32, end: f32) {
+    let entity = straight_line(
+        1000.0 * start,
+        1000.0,
+        start,
+        end,
+        0.5,
+        flt::ENTITY_FLAG_AIRCRAFT,
+    );
+    let flight = Flight {
+        start_time: start,
+        end_time: end,
+        entities: std::iter::once((1, entity)).collect(),
+        ..Default::default()
+    };
+    let mut bytes = Vec::new();
+    flight.write(&mut bytes).unwrap();
+    fs::write(dir.join(name), bytes).unwrap();
+}
+
+#[test]
+fn overlapping_files() {
+    let dir = tempfile::tempdir().unwrap();
+    // Overlapping by less than the default --max-gap still gets a warning.
+    write_flt(dir.path(), "first.flt", 0.0, 10.0);
+    write_flt(dir.path(), "second.flt", 9.5, 20.0);
+    // Too far after the others to merge
+    write_flt(dir.path(), "third.flt", 60.0, 70.0);
+
+    let output = flt2vhs(dir.path())
+        .args(["--merge-report", "report.json"])
+        .args(["third.flt", "second.flt", "first.flt"])
+        .output()
+        .expect("Couldn't run flt2vhs");
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let warnings = stderr
+        .lines()
+        .filter(|l| l.contains("overlap by"))
+        .collect::<Vec<_>>();
+    assert_eq!(warnings.len(), 1, "{}", stderr);
+    assert!(
+        warnings[0].contains("first.flt and second.flt overlap by 0.5s"),
+        "{}",
+        stderr
+    );
+
+    // Files are grouped in the order they were recorded.
+    let report: serde_json::Value =
+        serde_json::from_slice(&fs::read(dir.path().join("report.json")).unwrap()).unwrap();
+    assert_eq!(
+        report["outputs"],
+        serde_json::json!([
+            { "output": "first.vhs", "inputs": ["first.flt", "second.flt"] },
+            { "output": "third.vhs", "inputs": ["third.flt"] },
+        ])
+    );
+    assert!(dir.path().join("first.vhs").exists());
+    assert!(dir.path().join("third.vhs").exists());
+    assert!(!dir.path().join("second.vhs").exists());
+}
+
+#[test]
+fn acmi() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(convert(dir.path(), &["single.flt"], &["--acmi"]), 0);
+    let acmi = fs::read_to_string(dir.path().join("single.txt.acmi")).unwrap();
+    assert!(acmi.starts_with("FileType=text/acmi/tacview\n"));
+    assert!(acmi.contains("Type=Air+FixedWing"));
+    assert!(!dir.path().join("single.vhs").exists());
+}
+
+#[test]
+fn truncated_file() {
+    let dir = tempfile::tempdir().unwrap();
+    // Corrupted files get their own exit code.
+    assert_eq!(convert(dir.path(), &["truncated.flt"], &[]), 2);
+    check_golden(dir.path(), "truncated.vhs");
+}
+
+#[test]
+fn garbage_in_file() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(convert(dir.path(), &["garbage.flt"], &[]), 2);
+    check_golden(dir.path(), "garbage.vhs");
+}
+
+#[test]
+fn low_memory() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(convert(dir.path(), &["single.flt"], &["--low-memory"]), 0);
+    check_golden(dir.path(), "single.vhs");
+
+    assert_eq!(
+        convert(dir.path(), &["truncated.flt"], &["--low-memory"]),
+        2
+    );
+    check_golden(dir.path(), "truncated.vhs");
+}
+
+#[test]
+fn corrupted_file_does_not_overwrite() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(convert(dir.path(), &["truncated.flt"], &[]), 2);
+
+    // Without --force, we shouldn't stomp the VHS from last time...
+    let vhs = dir.path().join("truncated.vhs");
+    fs::write(&vhs, b"not a VHS").unwrap();
+    assert_eq!(convert(dir.path(), &["truncated.flt"], &[]), 1);
+    assert_eq!(fs::read(&vhs).unwrap(), b"not a VHS");
+
+    // ...but with it, we should.
+    assert_eq!(convert(dir.path(), &["truncated.flt"], &["--force"]), 2);
+    check_golden(dir.path(), "truncated.vhs");
+}
@@ -0,0 +1,676 @@
+//! Make sure merging FLT files BMS split up keeps each entity in one piece.
+
+use std::path::Path;
+
+use flt::{MergeDecision, MergeMode, MergeOutcome, MergePolicy, TodMismatch};
+use flt2vhs::{flt, Flight};
+
+mod common;
+
+/// An aircraft flying in a straight line (along x) at `speed` feet per second,
+/// with an update every half second from `start` to `end`.
+fn aircraft(x: f32, speed: f32, start: f32, end: f32) -> flt::EntityData {
+    common::straight_line(x, speed, start, end, 0.5, flt::ENTITY_FLAG_AIRCRAFT)
+}
+
+fn flight(entities: Vec<(i32, flt::EntityData)>, start_time: f32, end_time: f32) -> Flight {
+    Flight {
+        start_time,
+        end_time,
+        entities: entities.into_iter().collect(),
+        ..Default::default()
+    }
+}
+
+fn merge(mut previous: Flight, next: &Flight) -> Flight {
+    assert!(previous.merge(next, Path::new("previous.flt"), Path::new("next.flt")));
+    previous
+}
+
+fn merge_reporting(
+    mut previous: Flight,
+    next: &Flight,
+    policy: &MergePolicy,
+) -> (Flight, flt::MergeReport) {
+    let report = previous.merge_reporting(
+        next,
+        Path::new("previous.flt"),
+        Path::new("next.flt"),
+        policy,
+    );
+    (previous, report)
+}
+
+fn xs(entity: &flt::EntityData) -> Vec<f32> {
+    entity
+        .position_data
+        .as_ref()
+        .unwrap()
+        .position_updates
+        .iter()
+        .map(|u| u.x)
+        .collect()
+}
+
+#[test]
+fn fast_movers_stay_together() {
+    // Mach 2 covers more than a mile in the two and a half seconds
+    // between the last update in one file and the first in the next.
+    let speed = 2200.0;
+    let previous = flight(vec![(1, aircraft(0.0, speed, 0.0, 10.0))], 0.0, 12.0);
+    let next = flight(
+        vec![(7, aircraft(speed * 12.5, speed, 12.5, 20.0))],
+        12.5,
+        20.0,
+    );
+
+    let merged = merge(previous, &next);
+    assert_eq!(merged.entities.len(), 1);
+    let xs = xs(&merged.entities[&1]);
+    assert!(xs.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn crossing_aircraft_stay_themselves() {
+    // Two jets pass each other head-on right as one file ends and the next begins.
+    // Each one starts the next file right where the _other_ one left off.
+    let speed = 1000.0;
+    let previous = flight(
+        vec![
+            (1, aircraft(-10_000.0, speed, 0.0, 10.0)),
+            (2, aircraft(11_000.0, -speed, 0.0, 10.0)),
+        ],
+        0.0,
+        10.5,
+    );
+    let next = flight(
+        vec![
+            (3, aircraft(1000.0, speed, 11.0, 20.0)),
+            (4, aircraft(0.0, -speed, 11.0, 20.0)),
+        ],
+        11.0,
+        20.0,
+    );
+
+    let merged = merge(previous, &next);
+    assert_eq!(merged.entities.len(), 2);
+    let eastbound = xs(&merged.entities[&1]);
+    let westbound = xs(&merged.entities[&2]);
+    assert!(eastbound.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(westbound.windows(2).all(|pair| pair[0] > pair[1]));
+}
+
+#[test]
+fn strangers_stay_strangers() {
+    // Something that shows up a few miles away is someone else.
+    let previous = flight(vec![(1, aircraft(0.0, 500.0, 0.0, 10.0))], 0.0, 10.5);
+    let next = flight(vec![(1, aircraft(20_000.0, 500.0, 11.0, 20.0))], 11.0, 20.0);
+
+    let merged = merge(previous, &next);
+    assert_eq!(merged.entities.len(), 2);
+}
+
+#[test]
+fn no_stealing_matches() {
+    // Two parked trucks, a couple thousand feet apart...
+    let previous = flight(
+        vec![
+            (1, aircraft(0.0, 0.0, 0.0, 10.0)),
+            (2, aircraft(4000.0, 0.0, 0.0, 10.0)),
+        ],
+        0.0,
+        10.5,
+    );
+    // ...which are a bit off in the next file. (Maybe BMS bumped them.)
+    // The truck right by #1 could be either one, but the other could only be #1.
+    let next = flight(
+        vec![
+            (1, aircraft(1000.0, 0.0, 11.0, 20.0)),
+            (2, aircraft(-3000.0, 0.0, 11.0, 20.0)),
+        ],
+        11.0,
+        20.0,
+    );
+
+    let merged = merge(previous, &next);
+    assert_eq!(merged.entities.len(), 2);
+    assert_eq!(*xs(&merged.entities[&1]).last().unwrap(), -3000.0);
+    assert_eq!(*xs(&merged.entities[&2]).last().unwrap(), 1000.0);
+}
+
+#[test]
+fn formations_stay_in_formation() {
+    // A four-ship in fingertip, a few hundred feet apart...
+    let slots = [0.0, -300.0, 300.0, 600.0];
+    let in_slot = |slot: f32, start: f32, end: f32| {
+        let mut entity = aircraft(slot + 600.0 * start, 600.0, start, end);
+        for update in &mut entity.position_data.as_mut().unwrap().position_updates {
+            update.y = slot;
+        }
+        entity
+    };
+    let previous = flight(
+        slots
+            .iter()
+            .enumerate()
+            .map(|(i, slot)| (i as i32, in_slot(*slot, 0.0, 10.0)))
+            .collect(),
+        0.0,
+        10.5,
+    );
+    // ...shows up in the next file in some other order.
+    let next = flight(
+        slots
+            .iter()
+            .enumerate()
+            .map(|(i, slot)| (3 - i as i32, in_slot(*slot, 11.0, 20.0)))
+            .collect(),
+        11.0,
+        20.0,
+    );
+
+    let merged = merge(previous, &next);
+    assert_eq!(merged.entities.len(), slots.len());
+    for (id, slot) in slots.iter().enumerate() {
+        let updates = &merged.entities[&(id as i32)]
+            .position_data
+            .as_ref()
+            .unwrap()
+            .position_updates;
+        assert!(updates.iter().all(|u| u.y == *slot));
+    }
+}
+
+#[test]
+fn crowds_merge() {
+    // A big field of parked trucks, each within a mile of lots of others...
+    let side = 60;
+    let spot = |i: i32| (1500.0 * (i % side) as f32, 1500.0 * (i / side) as f32);
+    let parked = |i: i32, nudge: f32, start: f32, end: f32| {
+        let (x, y) = spot(i);
+        let mut entity = aircraft(x + nudge, 0.0, start, end);
+        for update in &mut entity.position_data.as_mut().unwrap().position_updates {
+            update.y = y;
+        }
+        entity
+    };
+    let count = side * side;
+
+    // ...and a whole lot of buildings.
+    let building = |i: i32, time: f32| flt::FeatureData {
+        kind: 7,
+        lead_uid: -1,
+        slot: 0,
+        special_flags: 0,
+        time,
+        x: (i % 200) as f32 * 100.0,
+        y: (i / 200) as f32 * 100.0,
+        z: 0.0,
+        pitch: 0.0,
+        roll: 0.0,
+        yaw: 0.0,
+    };
+    let buildings = 20_000;
+
+    let mut previous = flight(
+        (0..count).map(|i| (i, parked(i, 0.0, 0.0, 10.0))).collect(),
+        0.0,
+        10.5,
+    );
+    previous.features = (0..buildings)
+        .map(|i| (count + i, building(i, 0.0)))
+        .collect();
+
+    // BMS numbers everything differently in the next file,
+    // and the trucks got bumped a bit.
+    let mut next = flight(
+        (0..count)
+            .map(|i| (count - 1 - i, parked(i, 100.0, 11.0, 20.0)))
+            .collect(),
+        11.0,
+        20.0,
+    );
+    next.features = (0..buildings)
+        .map(|i| (count + buildings - 1 - i, building(i, 11.0)))
+        .collect();
+
+    let merged = merge(previous, &next);
+    assert_eq!(merged.entities.len(), count as usize);
+    assert_eq!(merged.features.len(), buildings as usize);
+    for (id, entity) in &merged.entities {
+        let (x, y) = spot(*id);
+        let updates = &entity.position_data.as_ref().unwrap().position_updates;
+        assert!(updates.iter().all(|u| u.y == y));
+        assert_eq!(updates.first().unwrap().x, x);
+        assert_eq!(updates.last().unwrap().x, x + 100.0);
+    }
+}
+
+#[test]
+fn reports_explain_merges() {
+    let previous = flight(
+        vec![
+            (1, aircraft(0.0, 500.0, 0.0, 10.0)),
+            (2, aircraft(50_000.0, 500.0, 0.0, 10.0)),
+        ],
+        0.0,
+        10.5,
+    );
+    let next = flight(
+        vec![
+            (5, aircraft(5500.0, 500.0, 11.0, 20.0)),
+            (6, aircraft(-50_000.0, 500.0, 11.0, 20.0)),
+        ],
+        11.0,
+        20.0,
+    );
+
+    let mut too_late = next.clone();
+    too_late.start_time = 30.0;
+    let report = merge_reporting(previous.clone(), &too_late, &MergePolicy::default()).1;
+    assert_eq!(report.decision, MergeDecision::TooFarApart);
+    assert_eq!(report.gap, 19.5);
+    assert!(report.entities.is_empty());
+
+    let report = merge_reporting(previous, &next, &MergePolicy::default()).1;
+    assert_eq!(report.decision, MergeDecision::Merged);
+    assert_eq!(report.gap, 0.5);
+    assert_eq!(report.entities.len(), 2);
+    assert_eq!(report.entities[0].next_id, 5);
+    match report.entities[0].outcome {
+        MergeOutcome::Matched {
+            id,
+            distance,
+            callsign_changed,
+        } => {
+            assert_eq!(id, 1);
+            assert!(!callsign_changed);
+            // We expected it at 5500 - 500 ft.
+            assert_eq!(distance, 0.0);
+        }
+        other => panic!("Expected a match, got {:?}", other),
+    }
+    assert_eq!(report.entities[1].next_id, 6);
+    assert_eq!(report.entities[1].outcome, MergeOutcome::New { id: 3 });
+}
+
+/// Two flights a few seconds apart, each with a jet flying along
+fn gapped() -> (Flight, Flight) {
+    let previous = flight(vec![(1, aircraft(0.0, 500.0, 0.0, 10.0))], 0.0, 10.0);
+    let next = flight(vec![(1, aircraft(10_000.0, 500.0, 15.0, 20.0))], 15.0, 20.0);
+    (previous, next)
+}
+
+#[test]
+fn policy_picks_what_to_merge() {
+    let (previous, next) = gapped();
+    let decision =
+        |policy: &MergePolicy| merge_reporting(previous.clone(), &next, policy).1.decision;
+
+    assert_eq!(
+        decision(&MergePolicy::default()),
+        MergeDecision::TooFarApart
+    );
+    let patient = MergePolicy {
+        max_gap: 5.0,
+        ..Default::default()
+    };
+    assert_eq!(decision(&patient), MergeDecision::Merged);
+    let forced = MergePolicy {
+        mode: MergeMode::Force,
+        ..Default::default()
+    };
+    assert_eq!(decision(&forced), MergeDecision::Merged);
+
+    // Even flights that line up perfectly don't merge if we say so.
+    let never = MergePolicy {
+        mode: MergeMode::Never,
+        ..Default::default()
+    };
+    let mut right_after = next.clone();
+    right_after.start_time = previous.end_time;
+    assert_eq!(
+        merge_reporting(previous.clone(), &right_after, &MergePolicy::default())
+            .1
+            .decision,
+        MergeDecision::Merged
+    );
+    assert_eq!(
+        merge_reporting(previous.clone(), &right_after, &never)
+            .1
+            .decision,
+        MergeDecision::Disabled
+    );
+}
+
+#[test]
+fn match_radius_by_kind() {
+    let (previous, next) = gapped();
+    let patient = MergePolicy {
+        max_gap: 5.0,
+        ..Default::default()
+    };
+    // The jet shows up 2500 ft past where we expected it.
+    let (merged, _) = merge_reporting(previous.clone(), &next, &patient);
+    assert_eq!(merged.entities.len(), 1);
+
+    let mut strict = patient.clone();
+    strict.match_radius.aircraft = 2000.0;
+    let (merged, _) = merge_reporting(previous.clone(), &next, &strict);
+    assert_eq!(merged.entities.len(), 2);
+
+    // Other kinds of things don't change how aircraft match.
+    let mut strict_elsewhere = patient;
+    strict_elsewhere.match_radius.missile = 2000.0;
+    strict_elsewhere.match_radius.countermeasure = 2000.0;
+    strict_elsewhere.match_radius.ground = 2000.0;
+    let (merged, _) = merge_reporting(previous, &next, &strict_elsewhere);
+    assert_eq!(merged.entities.len(), 1);
+}
+
+#[test]
+fn times_of_day() {
+    let previous = flight(vec![(1, aircraft(0.0, 500.0, 0.0, 10.0))], 0.0, 10.5);
+    // The same jet, recorded with a time of day offset an hour earlier.
+    let mut next = flight(
+        vec![(1, aircraft(5500.0, 500.0, 3611.0, 3620.0))],
+        3611.0,
+        3620.0,
+    );
+    next.tod_offset = -3600.0;
+
+    let policy = |tod_mismatch| MergePolicy {
+        tod_mismatch,
+        ..Default::default()
+    };
+
+    let (_, report) = merge_reporting(previous.clone(), &next, &policy(TodMismatch::Refuse));
+    assert_eq!(report.decision, MergeDecision::DifferentTimeOfDay);
+
+    // Warning about it doesn't fix the times.
+    let (_, report) = merge_reporting(previous.clone(), &next, &policy(TodMismatch::Warn));
+    assert_eq!(report.decision, MergeDecision::TooFarApart);
+
+    let (merged, report) = merge_reporting(previous, &next, &policy(TodMismatch::Rebase));
+    assert_eq!(report.decision, MergeDecision::Merged);
+    assert_eq!(report.gap, 0.5);
+    assert_eq!(merged.end_time, 20.0);
+    assert_eq!(merged.entities.len(), 1);
+    let updates = &merged.entities[&1]
+        .position_data
+        .as_ref()
+        .unwrap()
+        .position_updates;
+    assert_eq!(updates.last().unwrap().time, 20.0);
+    assert!(updates.windows(2).all(|pair| pair[0].time < pair[1].time));
+}
+
+#[test]
+fn callsigns_follow_players() {
+    let mut previous = flight(vec![(1, aircraft(0.0, 500.0, 0.0, 10.0))], 0.0, 10.5);
+    previous
+        .callsigns
+        .insert(1, flt::CallsignRecord::new("Viper11", 1));
+
+    // Viper11 respawned way off somewhere else,
+    // and someone else showed up right where we expected Viper11 to be.
+    let mut next = flight(
+        vec![
+            (1, aircraft(5500.0, 500.0, 11.0, 20.0)),
+            (2, aircraft(100_000.0, 500.0, 11.0, 20.0)),
+        ],
+        11.0,
+        20.0,
+    );
+    next.callsigns
+        .insert(1, flt::CallsignRecord::new("Viper12", 1));
+    next.callsigns
+        .insert(2, flt::CallsignRecord::new("Viper11", 1));
+
+    let (merged, report) = merge_reporting(previous.clone(), &next, &MergePolicy::default());
+    assert_eq!(merged.entities.len(), 2);
+    assert_eq!(*xs(&merged.entities[&1]).last().unwrap(), 104_500.0);
+    assert_eq!(merged.callsigns[&1], flt::CallsignRecord::new("Viper11", 1));
+    assert_eq!(merged.callsigns[&2], flt::CallsignRecord::new("Viper12", 1));
+    assert_eq!(report.entities[0].outcome, MergeOutcome::New { id: 2 });
+
+    // Team matters too. Without a callsign to go by,
+    // the one where we expected Viper11 to be is our best guess.
+    next.callsigns
+        .insert(2, flt::CallsignRecord::new("Viper11", 2));
+    let (merged, _) = merge_reporting(previous, &next, &MergePolicy::default());
+    assert_eq!(merged.entities.len(), 2);
+    assert_eq!(*xs(&merged.entities[&1]).last().unwrap(), 10_000.0);
+}
+
+#[test]
+fn callsigns_can_change() {
+    let previous = flight(vec![(1, aircraft(0.0, 500.0, 0.0, 10.0))], 0.0, 10.5);
+    let mut next = flight(vec![(7, aircraft(5500.0, 500.0, 11.0, 20.0))], 11.0, 20.0);
+    next.callsigns
+        .insert(7, flt::CallsignRecord::new("Viper11", 1));
+
+    // Something that didn't have a callsign picks one up...
+    let (merged, report) = merge_reporting(previous.clone(), &next, &MergePolicy::default());
+    assert_eq!(merged.entities.len(), 1);
+    assert_eq!(merged.callsigns[&1], flt::CallsignRecord::new("Viper11", 1));
+    assert!(matches!(
+        report.entities[0].outcome,
+        MergeOutcome::Matched {
+            id: 1,
+            callsign_changed: false,
+            ..
+        }
+    ));
+
+    // ...and something that did keeps the one it had.
+    let mut previous = previous;
+    previous
+        .callsigns
+        .insert(1, flt::CallsignRecord::new("Uzi21", 1));
+    let (merged, report) = merge_reporting(previous, &next, &MergePolicy::default());
+    assert_eq!(merged.entities.len(), 1);
+    assert_eq!(merged.callsigns[&1], flt::CallsignRecord::new("Uzi21", 1));
+    assert!(matches!(
+        report.entities[0].outcome,
+        MergeOutcome::Matched {
+            id: 1,
+            callsign_changed: true,
+            ..
+        }
+    ));
+}
+
+/// `entity` as seen from another player's jet, with an update every two seconds
+fn remote(mut entity: flt::EntityData) -> flt::EntityData {
+    let updates = &mut entity.position_data.as_mut().unwrap().position_updates;
+    let mut i = 0;
+    updates.retain(|_| {
+        i += 1;
+        (i - 1) % 4 == 0
+    });
+    entity
+}
+
+fn gear_down(time: f32) -> flt::EntityEvent {
+    flt::EntityEvent {
+        time,
+        payload: flt::EntityEventPayload::DofEvent(flt::DofEvent {
+            dof_number: 0,
+            new_dof_value: 1.0,
+            previous_dof_value: 0.0,
+        }),
+    }
+}
+
+fn tracer(start: f32) -> flt::GeneralEvent {
+    flt::GeneralEvent {
+        type_byte: flt::REC_TYPE_TRACER_START,
+        start,
+        stop: start + 1.0,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn overlapping_recordings_merge() {
+    let policy = MergePolicy {
+        mode: MergeMode::Overlapping,
+        ..Default::default()
+    };
+
+    // Viper11 and their wingman, Viper12, each recorded their flight.
+    // Viper12 started late and kept recording after Viper11 stopped.
+    let lead = |start: f32, end| aircraft(500.0 * start, 500.0, start, end);
+    let wing = |start: f32, end| aircraft(500.0 * start - 1000.0, 500.0, start, end);
+
+    let mut mine = lead(0.0, 60.0);
+    mine.events = vec![gear_down(10.0), gear_down(50.0)];
+    let mut previous = flight(vec![(1, mine), (2, remote(wing(0.0, 60.0)))], 0.0, 60.0);
+    previous
+        .callsigns
+        .insert(1, flt::CallsignRecord::new("Viper11", 1));
+    previous
+        .callsigns
+        .insert(2, flt::CallsignRecord::new("Viper12", 1));
+    previous.general_events = vec![tracer(10.0), tracer(40.0)];
+
+    let mut theirs = wing(30.0, 90.0);
+    theirs.events = vec![gear_down(40.0), gear_down(80.0)];
+    let mut next = flight(
+        vec![
+            (5, theirs),
+            (9, remote(lead(30.0, 90.0))),
+            (3, aircraft(50_000.0, 20.0, 30.0, 90.0)),
+        ],
+        30.0,
+        90.0,
+    );
+    next.callsigns
+        .insert(5, flt::CallsignRecord::new("Viper12", 1));
+    next.callsigns
+        .insert(9, flt::CallsignRecord::new("Viper11", 1));
+    next.general_events = vec![tracer(40.0), tracer(80.0)];
+
+    let (merged, report) = merge_reporting(previous.clone(), &next, &policy);
+    assert_eq!(report.decision, MergeDecision::Merged);
+    assert_eq!(report.gap, -30.0);
+    assert_eq!(merged.entities.len(), 3);
+    assert_eq!((merged.start_time, merged.end_time), (0.0, 90.0));
+    assert_eq!(
+        report.entities,
+        vec![
+            flt::MergedId {
+                next_id: 3,
+                outcome: MergeOutcome::New { id: 3 },
+            },
+            flt::MergedId {
+                next_id: 5,
+                outcome: MergeOutcome::Matched {
+                    id: 2,
+                    distance: 0.0,
+                    callsign_changed: false,
+                },
+            },
+            flt::MergedId {
+                next_id: 9,
+                outcome: MergeOutcome::Matched {
+                    id: 1,
+                    distance: 0.0,
+                    callsign_changed: false,
+                },
+            },
+        ]
+    );
+
+    // Each jet's own recording has the final say about it while it was recording,
+    // and the other fills in the rest.
+    let times = |id| -> (Vec<f32>, Vec<f32>) {
+        let entity: &flt::EntityData = &merged.entities[&id];
+        (
+            entity
+                .position_data
+                .as_ref()
+                .unwrap()
+                .position_updates
+                .iter()
+                .map(|u| u.time)
+                .collect(),
+            entity.events.iter().map(|e| e.time).collect(),
+        )
+    };
+    let (lead_updates, lead_events) = times(1);
+    assert_eq!(lead_updates.len(), 121 + 15);
+    assert_eq!(lead_events, [10.0, 50.0]);
+    let (wing_updates, wing_events) = times(2);
+    assert_eq!(wing_updates.len(), 15 + 121);
+    assert_eq!(wing_events, [40.0, 80.0]);
+    for updates in [lead_updates, wing_updates] {
+        assert!(updates.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    // Everyone saw the same tracers.
+    let tracers = merged
+        .general_events
+        .iter()
+        .map(|e| e.start)
+        .collect::<Vec<_>>();
+    assert_eq!(tracers, [10.0, 40.0, 80.0]);
+
+    // Recordings of different missions stay apart.
+    let later = flight(vec![(1, lead(200.0, 260.0))], 200.0, 260.0);
+    let (merged, report) = merge_reporting(previous, &later, &policy);
+    assert_eq!(report.decision, MergeDecision::TooFarApart);
+    assert_eq!(report.gap, 140.0);
+    assert_eq!(merged.end_time, 60.0);
+}
+
+#[test]
+fn seams_can_be_smoothed() {
+    let speed = 1000.0;
+    let mut previous = flight(vec![(1, aircraft(0.0, speed, 0.0, 10.0))], 0.0, 12.0);
+    let mut next = flight(
+        vec![(7, aircraft(speed * 12.5, speed, 12.5, 20.0))],
+        12.5,
+        20.0,
+    );
+    // Turning through due south, where yaw wraps around from π to -π.
+    let yaws = |flight: &mut Flight, id, yaw| {
+        let data = flight.entities.get_mut(&id).unwrap();
+        for update in &mut data.position_data.as_mut().unwrap().position_updates {
+            update.yaw = yaw;
+        }
+    };
+    yaws(&mut previous, 1, 3.0);
+    yaws(&mut next, 7, -3.0);
+
+    // By default, entities jump from where they left off to where they pick up...
+    let (merged, _) = merge_reporting(previous.clone(), &next, &MergePolicy::default());
+    let updates = &merged.entities[&1]
+        .position_data
+        .as_ref()
+        .unwrap()
+        .position_updates;
+    assert_eq!(updates.len(), 21 + 16);
+    assert_eq!((updates[20].time, updates[21].time), (10.0, 12.5));
+
+    // ...unless we ask to fill in the seam.
+    let policy = MergePolicy {
+        seam_step: Some(0.5),
+        ..Default::default()
+    };
+    let (merged, _) = merge_reporting(previous, &next, &policy);
+    let updates = &merged.entities[&1]
+        .position_data
+        .as_ref()
+        .unwrap()
+        .position_updates;
+    assert_eq!(updates.len(), 21 + 4 + 16);
+    for (i, update) in updates.iter().enumerate() {
+        assert_eq!(update.time, i as f32 * 0.5);
+        assert_eq!(update.x, update.time * speed);
+    }
+    for update in &updates[21..25] {
+        assert!(update.yaw.abs() > 3.0, "{} turned the long way", update.yaw);
+    }
+}
@@ -0,0 +1,555 @@
+//! Property tests: generate arbitrary flights, write them out as FLT and VHS files,
+//! read them back in, and make sure nothing that matters got lost along the way.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+
+use proptest::prelude::*;
+use proptest::sample::select;
+
+use acmitape::{primitives::*, CountedRead, GeneralEventTrailer, TapeHeader};
+use flt2vhs::{flt, vhs, Flight};
+
+/// Times are sixteenths of a second so that the math we do on them
+/// (stop = start + TTL, end = start + length) is exact.
+fn time() -> impl Strategy<Value = f32> {
+    (0..1_000_000u32).prop_map(|ticks| ticks as f32 / 16.0)
+}
+
+/// A sorted list of times, since everything in a flight should be chronological.
+fn times(max: usize) -> impl Strategy<Value = Vec<f32>> {
+    prop::collection::vec(time(), 0..max).prop_map(|mut times| {
+        times.sort_by(f32::total_cmp);
+        times
+    })
+}
+
+/// Finite, if not always sensible, values.
+fn value() -> impl Strategy<Value = f32> {
+    -1e6f32..1e6
+}
+
+/// One of the entity types a FLT can store
+fn entity_flags() -> impl Strategy<Value = u32> {
+    select(vec![
+        0,
+        flt::ENTITY_FLAG_MISSILE,
+        flt::ENTITY_FLAG_AIRCRAFT,
+        flt::ENTITY_FLAG_CHAFF,
+        flt::ENTITY_FLAG_FLARE,
+    ])
+}
+
+/// An ID picked from `ids`, -1, or one that isn't in the flight at all.
+fn reference(ids: &[i32]) -> BoxedStrategy<i32> {
+    if ids.is_empty() {
+        prop_oneof![Just(-1), Just(10_000)].boxed()
+    } else {
+        prop_oneof![Just(-1), Just(10_000), select(ids.to_vec())].boxed()
+    }
+}
+
+fn entity(entity_ids: &[i32]) -> impl Strategy<Value = flt::EntityData> {
+    let update = (value(), value(), value(), value(), value(), value());
+    let event = (any::<bool>(), any::<i32>(), any::<i32>(), value(), value());
+    (
+        any::<i32>(),
+        entity_flags(),
+        times(20).prop_filter("Entities need a position", |t| !t.is_empty()),
+        prop::collection::vec((update, reference(entity_ids)), 20),
+        times(8),
+        prop::collection::vec(event, 8),
+    )
+        .prop_map(
+            |(kind, flags, update_times, updates, event_times, events)| {
+                let position_updates = update_times
+                    .into_iter()
+                    .zip(updates)
+                    .map(|(time, ((x, y, z, pitch, roll, yaw), radar_target))| {
+                        flt::EntityPositionUpdate {
+                            time,
+                            x,
+                            y,
+                            z,
+                            pitch,
+                            roll,
+                            yaw,
+                            // Only aircraft have radars.
+                            radar_target: if flags == flt::ENTITY_FLAG_AIRCRAFT {
+                                radar_target
+                            } else {
+                                -1
+                            },
+                        }
+                    })
+                    .collect();
+
+                let events = event_times
+                    .into_iter()
+                    .zip(events)
+                    .map(|(time, (switch, number, new, old_dof, new_dof))| {
+                        let payload = if switch {
+                            flt::EntityEventPayload::SwitchEvent(flt::SwitchEvent {
+                                switch_number: number,
+                                new_switch_value: new,
+                                previous_switch_value: new.wrapping_sub(1),
+                            })
+                        } else {
+                            flt::EntityEventPayload::DofEvent(flt::DofEvent {
+                                dof_number: number,
+                                new_dof_value: new_dof,
+                                previous_dof_value: old_dof,
+                            })
+                        };
+                        flt::EntityEvent { time, payload }
+                    })
+                    .collect();
+
+                flt::EntityData {
+                    position_data: Some(flt::EntityPositionData {
+                        kind,
+                        flags,
+                        position_updates,
+                    }),
+                    events,
+                }
+            },
+        )
+}
+
+fn feature(feature_ids: &[i32]) -> impl Strategy<Value = flt::FeatureData> {
+    (
+        (
+            any::<i32>(),
+            reference(feature_ids),
+            any::<i32>(),
+            any::<u32>(),
+        ),
+        time(),
+        (value(), value(), value(), value(), value(), value()),
+    )
+        .prop_map(
+            |((kind, lead_uid, slot, special_flags), time, (x, y, z, pitch, roll, yaw))| {
+                flt::FeatureData {
+                    kind,
+                    lead_uid,
+                    slot,
+                    special_flags,
+                    time,
+                    x,
+                    y,
+                    z,
+                    pitch,
+                    roll,
+                    yaw,
+                }
+            },
+        )
+}
+
+/// A tracer or sound effect, with only the fields a FLT stores for its type
+fn general_event() -> impl Strategy<Value = flt::GeneralEvent> {
+    (
+        select(vec![
+            flt::REC_TYPE_TRACER_START,
+            flt::REC_TYPE_STATIONARY_SFX,
+            flt::REC_TYPE_MOVING_SFX,
+        ]),
+        time(),
+        // TTL
+        (0..1000u32).prop_map(|ticks| ticks as f32 / 16.0),
+        (any::<i32>(), any::<i32>(), any::<u32>(), value()),
+        (value(), value(), value(), value(), value(), value()),
+    )
+        .prop_map(
+            |(type_byte, start, ttl, (kind, user, flags, scale), (x, y, z, dx, dy, dz))| {
+                let event = flt::GeneralEvent {
+                    type_byte,
+                    start,
+                    x,
+                    y,
+                    z,
+                    ..Default::default()
+                };
+                match type_byte {
+                    flt::REC_TYPE_TRACER_START => flt::GeneralEvent {
+                        stop: start + 5.0,
+                        dx,
+                        dy,
+                        dz,
+                        ..event
+                    },
+                    flt::REC_TYPE_STATIONARY_SFX => flt::GeneralEvent {
+                        stop: start + ttl,
+                        kind,
+                        scale,
+                        ..event
+                    },
+                    _ => flt::GeneralEvent {
+                        stop: start + ttl,
+                        kind,
+                        user,
+                        flags,
+                        scale,
+                        dx,
+                        dy,
+                        dz,
+                        ..event
+                    },
+                }
+            },
+        )
+}
+
+fn callsign() -> impl Strategy<Value = flt::CallsignRecord> {
+    (any::<[u8; 16]>(), any::<i32>())
+        .prop_map(|(label, team_color)| flt::CallsignRecord { label, team_color })
+        // The parser treats blank callsigns as missing.
+        .prop_filter("Blank callsign", |c| *c != flt::CallsignRecord::default())
+}
+
+/// A flight that a FLT file can hold, with its start and end times
+/// where the parser would find them.
+fn flight() -> impl Strategy<Value = Flight> {
+    prop::collection::btree_map(0..5000i32, any::<bool>(), 1..24)
+        .prop_flat_map(|ids| {
+            // The first ID is always an entity, so we have at least one.
+            let mut entities = Vec::new();
+            let mut features = Vec::new();
+            for (i, (id, is_feature)) in ids.iter().enumerate() {
+                if *is_feature && i > 0 {
+                    features.push(*id);
+                } else {
+                    entities.push(*id);
+                }
+            }
+
+            let all_ids = ids.keys().copied().collect::<Vec<_>>();
+            (
+                entities
+                    .iter()
+                    .map(|id| (Just(*id), entity(&entities)))
+                    .collect::<Vec<_>>(),
+                features
+                    .iter()
+                    .map(|id| (Just(*id), feature(&features)))
+                    .collect::<Vec<_>>(),
+                prop::collection::vec(general_event(), 0..16),
+                feature_events(&features),
+                prop::collection::vec((select(all_ids), callsign()), 0..24),
+                (value(), 0..100u32),
+            )
+        })
+        .prop_map(
+            |(entities, features, mut general_events, feature_events, callsigns, (tod, slack))| {
+                general_events.sort_by(|a, b| a.start.total_cmp(&b.start));
+                let features = features.into_iter().collect::<BTreeMap<_, _>>();
+
+                // Feature events come some time after their feature is defined.
+                let mut feature_events = feature_events
+                    .into_iter()
+                    .map(
+                        |(feature_uid, delay, new_status, previous_status)| flt::FeatureEvent {
+                            time: features[&feature_uid].time + delay,
+                            feature_uid,
+                            new_status,
+                            previous_status,
+                        },
+                    )
+                    .collect::<Vec<_>>();
+                feature_events.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+                let mut flight = Flight {
+                    tod_offset: tod,
+                    entities: entities.into_iter().collect(),
+                    features: features.into_iter().collect(),
+                    general_events,
+                    feature_events,
+                    callsigns: callsigns.into_iter().collect(),
+                    ..Default::default()
+                };
+
+                let record_times = record_times(&flight);
+                flight.start_time = record_times.iter().copied().fold(f32::INFINITY, f32::min);
+                flight.end_time = record_times.iter().copied().fold(0.0, f32::max);
+                // The callsign list comes last, and can push the end time out a bit.
+                if !flight.callsigns.is_empty() {
+                    flight.end_time += slack as f32 / 16.0;
+                }
+                flight
+            },
+        )
+}
+
+fn feature_events(feature_ids: &[i32]) -> BoxedStrategy<Vec<(i32, f32, i32, i32)>> {
+    if feature_ids.is_empty() {
+        return Just(Vec::new()).boxed();
+    }
+    prop::collection::vec(
+        (
+            select(feature_ids.to_vec()),
+            (0..1000u32).prop_map(|ticks| ticks as f32 / 16.0),
+            any::<i32>(),
+            any::<i32>(),
+        ),
+        0..8,
+    )
+    .boxed()
+}
+
+fn record_times(flight: &Flight) -> Vec<f32> {
+    let mut times = Vec::new();
+    for entity in flight.entities.values() {
+        let data = entity.position_data.as_ref().unwrap();
+        times.extend(data.position_updates.iter().map(|u| u.time));
+        times.extend(entity.events.iter().map(|e| e.time));
+    }
+    times.extend(flight.features.values().map(|f| f.time));
+    times.extend(flight.general_events.iter().map(|e| e.start));
+    times.extend(flight.feature_events.iter().map(|e| e.time));
+    times
+}
+
+/// Everything in a flight, in an order that doesn't depend on hash maps.
+/// (Comparing the `Debug` output also compares floats bit-for-bit, more or less.)
+fn canonical(flight: &Flight) -> String {
+    fn sorted<T>(map: &HashMap<i32, T, impl BuildHasher>) -> BTreeMap<&i32, &T> {
+        map.iter().collect()
+    }
+    format!(
+        "tod: {:?}\nstart: {:?}\nend: {:?}\nentities: {:#?}\nfeatures: {:#?}\n\
+         general: {:#?}\nfeature events: {:#?}\ncallsigns: {:#?}",
+        flight.tod_offset,
+        flight.start_time,
+        flight.end_time,
+        sorted(&flight.entities),
+        sorted(&flight.features),
+        flight.general_events,
+        flight.feature_events,
+        sorted(&flight.callsigns),
+    )
+}
+
+fn to_vhs(flight: &Flight) -> Vec<u8> {
+    let mut vhs = Vec::new();
+    vhs::write_to(flight, &mut vhs).unwrap();
+    vhs
+}
+
+/// The UID each entity and feature should get in a VHS:
+/// ones with callsigns first (so the callsign table is compact),
+/// then entities before features, each by their original UID.
+fn vhs_uids(flight: &Flight) -> (BTreeMap<i32, i32>, BTreeMap<i32, i32>) {
+    let mut ids = flight
+        .entities
+        .keys()
+        .map(|id| (!flight.callsigns.contains_key(id), false, *id))
+        .chain(
+            flight
+                .features
+                .keys()
+                .map(|id| (!flight.callsigns.contains_key(id), true, *id)),
+        )
+        .collect::<Vec<_>>();
+    ids.sort();
+
+    let mut entities = BTreeMap::new();
+    let mut features = BTreeMap::new();
+    for (new, (_, is_feature, original)) in ids.into_iter().enumerate() {
+        let map = if is_feature {
+            &mut features
+        } else {
+            &mut entities
+        };
+        map.insert(original, new as i32);
+    }
+    (entities, features)
+}
+
+/// The general event trailers in a VHS, which [`vhs::read()`] skips over
+fn trailers(vhs: &[u8]) -> Vec<GeneralEventTrailer> {
+    let mut r = CountedRead::new(vhs);
+    let header = TapeHeader::read(&mut r).unwrap();
+    let mut r = &vhs[header.general_event_trailer_offset as usize..];
+    (0..header.general_event_count)
+        .map(|_| GeneralEventTrailer::read(&mut r).unwrap())
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn flt_round_trip(flight in flight()) {
+        let mut flt = Vec::new();
+        flight.write(&mut flt).unwrap();
+        let parsed = Flight::parse(&flt[..]);
+
+        prop_assert!(parsed.corruption.is_none(), "{}", parsed.corruption.unwrap());
+        prop_assert_eq!(canonical(&parsed), canonical(&flight));
+        prop_assert_eq!(to_vhs(&parsed), to_vhs(&flight));
+    }
+
+    #[test]
+    fn vhs_round_trip(flight in flight()) {
+        let vhs = to_vhs(&flight);
+        let read = vhs::read(&vhs[..]).unwrap();
+        let (entity_uids, feature_uids) = vhs_uids(&flight);
+
+        prop_assert_eq!(read.tod_offset, flight.tod_offset);
+        prop_assert_eq!(read.start_time, flight.start_time);
+        prop_assert_eq!(read.end_time, flight.end_time);
+
+        // Entities keep their kinds, flags, and timelines (in order),
+        // and radar targets follow their entities to their new UIDs.
+        prop_assert_eq!(read.entities.len(), flight.entities.len());
+        for (id, entity) in &flight.entities {
+            let data = entity.position_data.as_ref().unwrap();
+            let read_entity = &read.entities[&entity_uids[id]];
+            let read_data = read_entity.position_data.as_ref().unwrap();
+            prop_assert_eq!(read_data.kind, data.kind);
+            prop_assert_eq!(read_data.flags, data.flags);
+
+            let expected_updates = data
+                .position_updates
+                .iter()
+                .map(|u| flt::EntityPositionUpdate {
+                    radar_target: *entity_uids.get(&u.radar_target).unwrap_or(&-1),
+                    ..*u
+                })
+                .collect::<Vec<_>>();
+            prop_assert_eq!(
+                format!("{:?}", read_data.position_updates),
+                format!("{:?}", expected_updates)
+            );
+            prop_assert_eq!(
+                format!("{:?}", read_entity.events),
+                format!("{:?}", entity.events)
+            );
+        }
+
+        // Features keep their leads.
+        prop_assert_eq!(read.features.len(), flight.features.len());
+        for (id, feature) in &flight.features {
+            let expected = flt::FeatureData {
+                lead_uid: *feature_uids.get(&feature.lead_uid).unwrap_or(&-1),
+                ..feature.clone()
+            };
+            prop_assert_eq!(&read.features[&feature_uids[id]], &expected);
+        }
+        let expected_feature_events = flight
+            .feature_events
+            .iter()
+            .map(|e| flt::FeatureEvent {
+                feature_uid: feature_uids[&e.feature_uid],
+                ..*e
+            })
+            .collect::<Vec<_>>();
+        prop_assert_eq!(
+            format!("{:?}", read.feature_events),
+            format!("{:?}", expected_feature_events)
+        );
+
+        // General events come back in the same order,
+        // and the trailers list them by when they stop.
+        prop_assert_eq!(
+            format!("{:?}", read.general_events),
+            format!("{:?}", flight.general_events)
+        );
+        let trailers = trailers(&vhs);
+        prop_assert!(trailers
+            .windows(2)
+            .all(|pair| pair[0].time_end <= pair[1].time_end));
+        let mut indexes = trailers
+            .iter()
+            .map(|t| {
+                prop_assert_eq!(t.time_end, flight.general_events[t.index as usize].stop);
+                Ok(t.index)
+            })
+            .collect::<Result<Vec<_>, TestCaseError>>()?;
+        indexes.sort_unstable();
+        prop_assert!(indexes.into_iter().eq(0..flight.general_events.len() as i32));
+
+        // Callsigns follow their entities and features,
+        // and take up the first UIDs so the table has no gaps.
+        let expected_callsigns = flight
+            .callsigns
+            .iter()
+            .filter_map(|(id, callsign)| {
+                let uid = entity_uids.get(id).into_iter().chain(feature_uids.get(id)).next()?;
+                Some((*uid, *callsign))
+            })
+            .collect::<BTreeMap<_, _>>();
+        let read_callsigns = read.callsigns.into_iter().collect::<BTreeMap<_, _>>();
+        prop_assert_eq!(&read_callsigns, &expected_callsigns);
+        prop_assert!(read_callsigns.keys().copied().eq(0..read_callsigns.len() as i32));
+        let mut r = &vhs[vhs.len() - 4 - read_callsigns.len() * 20..];
+        prop_assert_eq!(read_i32(&mut r).unwrap(), read_callsigns.len() as i32);
+    }
+}
+
+#[test]
+fn sparse_callsign_ids() {
+    let mut flight = Flight::default();
+    let callsign = flt::CallsignRecord::new("Viper11", 1);
+
+    // FLT callsign lists have an entry for every ID up to the highest,
+    // so a few far apart ones are fine...
+    for id in [3, 100_000] {
+        // The parser only keeps callsigns for things it saw.
+        flight.features.insert(
+            id,
+            flt::FeatureData {
+                lead_uid: id,
+                ..Default::default()
+            },
+        );
+        flight.callsigns.insert(id, callsign);
+    }
+    let mut flt = Vec::new();
+    flight.write(&mut flt).unwrap();
+    let parsed = Flight::parse(&flt[..]);
+    assert!(parsed.corruption.is_none());
+    assert_eq!(parsed.callsigns.len(), 2);
+    assert_eq!(parsed.callsigns[&100_000], callsign);
+
+    // ...but one huge one would take gigabytes, so we refuse
+    // before writing anything.
+    flight.callsigns.insert(2_000_000_000, callsign);
+    let mut flt = Vec::new();
+    assert!(flight.write(&mut flt).is_err());
+    assert!(flt.is_empty());
+}
+
+#[test]
+fn unwritable_flights() {
+    let mut vhs = Vec::new();
+
+    // An entity needs somewhere to start...
+    let mut flight = Flight::default();
+    flight.entities.insert(1, flt::EntityData::default());
+    assert!(vhs::write_to(&flight, &mut vhs).is_err());
+
+    flight.entities.insert(
+        1,
+        flt::EntityData {
+            position_data: Some(flt::EntityPositionData {
+                kind: 42,
+                flags: flt::ENTITY_FLAG_AIRCRAFT,
+                position_updates: Vec::new(),
+            }),
+            events: Vec::new(),
+        },
+    );
+    assert!(vhs::write_to(&flight, &mut vhs).is_err());
+
+    // ...and a feature event needs a feature.
+    let mut flight = Flight::default();
+    flight.feature_events.push(flt::FeatureEvent {
+        time: 1.0,
+        feature_uid: 2,
+        new_status: 1,
+        previous_status: 0,
+    });
+    assert!(vhs::write_to(&flight, &mut vhs).is_err());
+    flight.features.insert(2, flt::FeatureData::default());
+    vhs::write_to(&flight, &mut vhs).unwrap();
+}
@@ -0,0 +1,158 @@
+//! Make sure flights too big for one VHS (see `vhs::write_split`)
+//! get split into valid files that pick up where each other left off.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use flt2vhs::{flt, vhs, Flight};
+
+mod common;
+use common::golden_bytes;
+
+fn golden(name: &str) -> Flight {
+    Flight::parse_bytes(&golden_bytes(name))
+}
+
+fn merged() -> Flight {
+    let flights = vec![golden("merge-1.flt"), golden("merge-2.flt")];
+    let paths = [PathBuf::from("merge-1.flt"), PathBuf::from("merge-2.flt")];
+    let mut merged = flt2vhs::merge_flights(flights, &paths);
+    assert_eq!(merged.len(), 1);
+    merged.pop().unwrap().flight
+}
+
+/// Writes the flight with `vhs::write_split`, returning each file's contents.
+fn split(flight: &Flight, max_file_length: u64) -> Vec<Vec<u8>> {
+    let mut files = Vec::new();
+    let sizes = vhs::write_split(flight, max_file_length, |i| {
+        assert_eq!(i, files.len());
+        let fh = tempfile::tempfile()?;
+        files.push(fh.try_clone()?);
+        Ok(fh)
+    })
+    .unwrap();
+    assert_eq!(sizes.len(), files.len());
+
+    files
+        .into_iter()
+        .zip(sizes)
+        .map(|(mut fh, size)| {
+            let mut vhs = Vec::new();
+            fh.seek(SeekFrom::Start(0)).unwrap();
+            fh.read_to_end(&mut vhs).unwrap();
+            assert_eq!(vhs.len(), size as usize);
+            vhs
+        })
+        .collect()
+}
+
+fn in_memory(flight: &Flight) -> Vec<u8> {
+    let mut vhs = Vec::new();
+    vhs::write_to(flight, &mut vhs).unwrap();
+    vhs
+}
+
+fn updates(entity: &flt::EntityData) -> &[flt::EntityPositionUpdate] {
+    &entity.position_data.as_ref().unwrap().position_updates
+}
+
+fn assert_splits_cleanly(flight: &Flight, max_file_length: u64) {
+    let whole = vhs::read(&in_memory(flight)[..]).unwrap();
+    let files = split(flight, max_file_length);
+    assert!(
+        files.len() > 1,
+        "{} bytes didn't need splitting",
+        max_file_length
+    );
+
+    let parts = files
+        .iter()
+        .map(|vhs| {
+            assert!(vhs.len() as u64 <= max_file_length);
+            vhs::read(&vhs[..]).unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(parts[0].start_time, whole.start_time);
+    assert_eq!(parts.last().unwrap().end_time, whole.end_time);
+
+    // Nothing that happens at a single moment should be lost or doubled up.
+    let total = |count: fn(&Flight) -> usize| parts.iter().map(count).sum::<usize>();
+    assert_eq!(
+        total(|f| f.general_events.len()),
+        whole.general_events.len()
+    );
+    assert_eq!(
+        total(|f| f.feature_events.len()),
+        whole.feature_events.len()
+    );
+    assert_eq!(
+        total(|f| f.entities.values().map(|e| e.events.len()).sum()),
+        whole.entities.values().map(|e| e.events.len()).sum()
+    );
+
+    // UIDs are the same in every part, so every entity should turn up somewhere
+    // with the same callsign.
+    for (uid, entity) in &whole.entities {
+        let kind = entity.position_data.as_ref().unwrap().kind;
+        let found = parts
+            .iter()
+            .filter_map(|p| p.entities.get(uid))
+            .collect::<Vec<_>>();
+        assert!(!found.is_empty(), "Entity {} got lost", uid);
+        for part in found {
+            assert_eq!(part.position_data.as_ref().unwrap().kind, kind);
+        }
+        for part in &parts {
+            assert_eq!(part.callsigns.get(uid), whole.callsigns.get(uid));
+        }
+    }
+
+    let mut crossings = 0;
+    for (before, after) in parts.iter().zip(&parts[1..]) {
+        let seam = before.end_time;
+        assert_eq!(after.start_time, seam);
+
+        for (uid, entity) in &before.entities {
+            let last = *updates(entity).last().unwrap();
+            let first_after = after.entities.get(uid).map(|e| updates(e)[0]);
+
+            // An entity still around at the seam should pick up in the next part
+            // right where it left off...
+            if last.time == seam {
+                let first = first_after.expect("Entity vanished at the seam");
+                assert_eq!(first.time, seam);
+                assert_eq!((first.x, first.y, first.z), (last.x, last.y, last.z));
+                crossings += 1;
+            }
+            // ...and one that isn't shouldn't linger in it.
+            else if let Some(first) = first_after {
+                assert!(first.time >= seam);
+            }
+        }
+    }
+    assert!(crossings > 0, "Nothing crossed a seam");
+}
+
+#[test]
+fn small_flights_stay_whole() {
+    for flight in &[golden("single.flt"), merged()] {
+        let files = split(flight, vhs::MAX_FILE_LENGTH);
+        assert_eq!(files, [in_memory(flight)]);
+    }
+}
+
+#[test]
+fn big_flights_split() {
+    assert_splits_cleanly(&golden("single.flt"), 12_000);
+    assert_splits_cleanly(&golden("single.flt"), 8_000);
+    assert_splits_cleanly(&merged(), 15_000);
+    assert_splits_cleanly(&golden("truncated.flt"), 10_000);
+}
+
+#[test]
+fn too_small_to_split() {
+    // Every part needs all the features and callsigns, which won't fit.
+    let flight = golden("single.flt");
+    assert!(vhs::write_split(&flight, 1000, |_| Ok(tempfile::tempfile()?)).is_err());
+}
@@ -0,0 +1,82 @@
+//! Make sure converting FLT files in two passes (see `vhs::StreamingWrite`)
+//! gets us the same VHS as parsing the whole flight first.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use flt2vhs::{vhs, Flight};
+
+mod common;
+use common::golden_bytes;
+
+fn in_memory(flt: &[u8]) -> Option<Vec<u8>> {
+    let mut vhs = Vec::new();
+    vhs::write_to(&Flight::parse_bytes(flt), &mut vhs).ok()?;
+    Some(vhs)
+}
+
+fn streamed(flt: &[u8], chunk_size: usize) -> Option<Vec<u8>> {
+    let streaming = vhs::StreamingWrite::scan_in_chunks(flt, chunk_size);
+    let mut fh = tempfile::tempfile().unwrap();
+    streaming.write(fh.try_clone().unwrap()).ok()?;
+
+    let mut vhs = Vec::new();
+    fh.seek(SeekFrom::Start(0)).unwrap();
+    fh.read_to_end(&mut vhs).unwrap();
+    Some(vhs)
+}
+
+fn assert_streams_the_same(flt: &[u8]) {
+    let expected = in_memory(flt);
+    for chunk_size in &[1, 1000, usize::MAX] {
+        assert!(
+            streamed(flt, *chunk_size) == expected,
+            "Streaming in {}-byte chunks made a different VHS",
+            chunk_size
+        );
+    }
+}
+
+#[test]
+fn golden_files() {
+    for name in &[
+        "single.flt",
+        "merge-1.flt",
+        "merge-2.flt",
+        "truncated.flt",
+        "garbage.flt",
+    ] {
+        assert_streams_the_same(&golden_bytes(name));
+    }
+}
+
+#[test]
+fn truncated_anywhere() {
+    let bytes = golden_bytes("single.flt");
+    for end in (0..bytes.len()).step_by(211) {
+        assert_streams_the_same(&bytes[..end]);
+    }
+}
+
+#[test]
+fn scan_keeps_no_records() {
+    let bytes = golden_bytes("single.flt");
+    let streaming = vhs::StreamingWrite::scan_in_chunks(&bytes, 1000);
+    let flight = streaming.flight();
+    let clean = Flight::parse_bytes(&bytes);
+
+    assert!(flight.general_events.is_empty());
+    assert!(flight.feature_events.is_empty());
+    assert!(flight.entities.values().all(|e| e.events.is_empty()
+        && e.position_data
+            .as_ref()
+            .unwrap()
+            .position_updates
+            .is_empty()));
+
+    // But everything else should be there.
+    assert_eq!(flight.entities.len(), clean.entities.len());
+    assert_eq!(flight.features.len(), clean.features.len());
+    assert_eq!(flight.callsigns.len(), clean.callsigns.len());
+    assert_eq!(flight.start_time, clean.start_time);
+    assert_eq!(flight.end_time, clean.end_time);
+}
@@ -0,0 +1,69 @@
+//! Parse arbitrary bytes as two FLT files, merge them,
+//! and write whatever we got as a VHS.
+//!
+//! The first byte picks the merge policy:
+//! - bits 0-1: the mode (auto, never, force, or overlapping)
+//! - bits 2-3: what to do about mismatched times of day
+//!   (refuse, warn, or rebase; 3 also rebases)
+//! - bits 4-7: if nonzero, smooth seams with updates every 1/4 that many seconds
+//!
+//! The next four bytes (little-endian) say how long the first FLT is;
+//! the second is everything after it.
+
+#![no_main]
+
+use std::io;
+use std::path::Path;
+
+use libfuzzer_sys::fuzz_target;
+
+use flt2vhs::{
+    flt::{MergeDecision, MergeMode, MergePolicy, TodMismatch},
+    vhs, Flight,
+};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 5 {
+        return;
+    }
+    let (header, rest) = data.split_at(5);
+    let policy = policy(header[0]);
+    let length = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    let (first, second) = rest.split_at(length.min(rest.len()));
+
+    let mut flight = Flight::parse(first);
+    let next = Flight::parse(second);
+    let report = flight.merge_reporting(
+        &next,
+        Path::new("first.flt"),
+        Path::new("second.flt"),
+        &policy,
+    );
+    if report.decision == MergeDecision::Merged {
+        let _ = vhs::write_to(&flight, io::sink());
+    }
+});
+
+fn policy(byte: u8) -> MergePolicy {
+    let mode = match byte & 0b11 {
+        0 => MergeMode::Auto,
+        1 => MergeMode::Never,
+        2 => MergeMode::Force,
+        _ => MergeMode::Overlapping,
+    };
+    let tod_mismatch = match (byte >> 2) & 0b11 {
+        0 => TodMismatch::Refuse,
+        1 => TodMismatch::Warn,
+        _ => TodMismatch::Rebase,
+    };
+    let seam_step = match byte >> 4 {
+        0 => None,
+        quarters => Some(f32::from(quarters) / 4.0),
+    };
+    MergePolicy {
+        mode,
+        tod_mismatch,
+        seam_step,
+        ..MergePolicy::default()
+    }
+}
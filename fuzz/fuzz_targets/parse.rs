@@ -0,0 +1,50 @@
+//! Parse arbitrary bytes as a FLT every way we know how,
+//! make sure the strict parsers agree, then write whatever we got as a VHS.
+//!
+//! The first byte also picks the chunk size for `parse_in_chunks()`,
+//! so chunk boundaries land all over the place.
+
+#![no_main]
+
+use std::io;
+
+use libfuzzer_sys::fuzz_target;
+
+use flt2vhs::{vhs, Flight};
+
+fuzz_target!(|data: &[u8]| {
+    let chunk_size = data.first().map_or(1, |b| *b as usize + 1);
+
+    let read = Flight::parse(data);
+    let decoded = Flight::parse_bytes(data);
+    let chunked = Flight::parse_in_chunks(data, chunk_size);
+    assert_same_flight(&read, &decoded);
+    assert_same_flight(&read, &chunked);
+
+    let recovered = Flight::parse_recovering(data);
+
+    for flight in &[read, decoded, chunked, recovered] {
+        let _ = vhs::write_to(flight, io::sink());
+    }
+});
+
+/// Flights don't implement `PartialEq`, so compare them as FLT files
+/// (plus what doesn't make it into one).
+fn assert_same_flight(expected: &Flight, actual: &Flight) {
+    let flt = |flight: &Flight| {
+        let mut out = Vec::new();
+        flight.write(&mut out).ok().map(|()| out)
+    };
+    assert_eq!(flt(expected), flt(actual));
+    assert_eq!(expected.start_time.to_bits(), actual.start_time.to_bits());
+    assert_eq!(expected.end_time.to_bits(), actual.end_time.to_bits());
+    assert_eq!(expected.tod_offset.to_bits(), actual.tod_offset.to_bits());
+
+    let where_and_what = |flight: &Flight| {
+        flight
+            .corruption
+            .as_ref()
+            .map(|c| (c.offset(), c.type_byte(), c.bytes_left, c.records_read))
+    };
+    assert_eq!(where_and_what(expected), where_and_what(actual));
+}
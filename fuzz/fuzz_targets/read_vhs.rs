@@ -0,0 +1,16 @@
+//! Read arbitrary bytes as a VHS, with both `vhscat` and `flt2vhs`.
+
+#![no_main]
+
+use std::io;
+
+use libfuzzer_sys::fuzz_target;
+
+use flt2vhs::vhs;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = vhscat::read_vhs(data, io::sink());
+    if let Ok(flight) = vhs::read(data) {
+        let _ = vhs::write_to(&flight, io::sink());
+    }
+});
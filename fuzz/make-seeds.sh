@@ -0,0 +1,78 @@
+#!/bin/sh
+# Regenerates the seed corpus in seeds/ with flt-gen (see README.md).
+set -eu
+
+cd "$(dirname "$0")"
+rm -rf seeds
+mkdir -p seeds/parse seeds/merge seeds/read_vhs
+
+cargo build --quiet --manifest-path ../Cargo.toml -p flt-gen -p flt2vhs
+bin=../target/debug
+scratch=$(mktemp -d)
+trap 'rm -rf "$scratch"' EXIT
+
+# Small recordings with a bit of everything, so the fuzzer starts with
+# every record type in hand but doesn't waste time on huge inputs.
+SMALL="--duration 4 --aircraft 2 --ground 1 --missiles 1 --chaff 1 --flares 1
+       --features 3 --feature-events 2 --tracers 2 --sounds 2 --switches 2 --dofs 2"
+NOTHING="--aircraft 0 --ground 0 --missiles 0 --chaff 0 --flares 0
+         --tracers 0 --sounds 0 --switches 0 --dofs 0"
+
+gen() {
+    output=$1
+    shift
+    "$bin/flt-gen" "$@" "$output"
+}
+
+# shellcheck disable=SC2086
+{
+    gen seeds/parse/small.flt $SMALL --seed 1
+    gen seeds/parse/empty.flt $NOTHING --seed 2 --duration 1 --features 0 --feature-events 0
+    gen seeds/parse/features.flt $NOTHING --seed 3 --duration 1 --features 8 --feature-events 8
+    gen seeds/parse/truncated.flt $SMALL --seed 4 --truncate 13
+    gen seeds/parse/garbage.flt $SMALL --seed 5 --garbage 7
+}
+
+# Merge inputs are a byte picking the merge policy (see fuzz_targets/merge.rs),
+# the first FLT's length (as four little-endian bytes), and both FLTs.
+# BMS chunks recordings into files that pick up right where the last one left off;
+# make one pair that does and one that doesn't, plus ones for the other policies:
+# two players' overlapping recordings, and a chunk whose time of day got
+# rebased, with smoothed seams.
+pair() {
+    name=$1
+    policy=$2
+    first=$3
+    second=$4
+    python3 - "$policy" "$first" "$second" "seeds/merge/$name" <<'PY'
+import struct, sys
+policy = int(sys.argv[1], 0)
+first = open(sys.argv[2], "rb").read()
+second = open(sys.argv[3], "rb").read()
+open(sys.argv[4], "wb").write(struct.pack("<BI", policy, len(first)) + first + second)
+PY
+}
+
+# shellcheck disable=SC2086
+{
+    gen "$scratch/chunk-1.flt" $SMALL --seed 6
+    gen "$scratch/chunk-2.flt" $SMALL --seed 6 --start-time 4.5
+    gen "$scratch/other.flt" $SMALL --seed 7 --start-time 100
+    gen "$scratch/wingman.flt" $SMALL --seed 8 --start-time 2
+    gen "$scratch/later-day.flt" $SMALL --seed 6 --start-time 4.5 --tod-offset 3600
+}
+# Auto, warning about times of day
+pair consecutive 0x04 "$scratch/chunk-1.flt" "$scratch/chunk-2.flt"
+pair unrelated 0x04 "$scratch/chunk-1.flt" "$scratch/other.flt"
+# Overlapping
+pair overlapping 0x07 "$scratch/chunk-1.flt" "$scratch/wingman.flt"
+# Auto, rebasing times of day, with a seam update every half second
+pair rebased 0x28 "$scratch/chunk-1.flt" "$scratch/later-day.flt"
+
+# VHS files are just the FLT seeds, converted.
+for flt in seeds/parse/*.flt; do
+    name=$(basename "$flt" .flt)
+    cp "$flt" "$scratch/$name.flt"
+    (cd "$scratch" && "$OLDPWD/$bin/flt2vhs" --force "$name.flt" >/dev/null 2>&1) || true
+    cp "$scratch/$name.vhs" "seeds/read_vhs/$name.vhs"
+done
@@ -268,7 +268,7 @@ fn find_bms_version(map: &[u8]) -> Result<BmsExeVersion> {
         "4.35.1" => BmsExeVersion::Ver4_35_1,
         "4.35.2" => BmsExeVersion::Ver4_35_2,
         "4.35.3" => BmsExeVersion::Ver4_35_3,
-        _ => unreachable!(format!("version that was detected {} is invalid", vs)),
+        _ => unreachable!("version that was detected {} is invalid", vs),
     };
 
     let expected_exe_size = match version {
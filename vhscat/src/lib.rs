@@ -0,0 +1,335 @@
+//! Reads VHS files, printing each data structure in them as JSON
+
+use std::io::prelude::*;
+
+use anyhow::*;
+use log::*;
+
+use acmitape::{primitives::read_i32, *};
+
+/// Read the VHS file, printing it as JSON to `w`
+///
+/// VHS files have a few sections:
+///
+/// 1. A header with some magic bytes, offsets into other sections of the file,
+///    flight time of day, etc.
+///
+/// 2. A list of entities - planes, etc. which move around the world
+///
+/// 3. A list of "features" which get an initial position and then stay there.
+///
+/// 4. A lits of position updates for entities and features (each feature has one).
+///    Updates don't contain the UID of the entity or feature they apply to.
+///    Instead, each entity & feature has a "head" offset that points to their
+///    first update, and each update had a "previous" and "next" offset, forming
+///    a doubly-linked list of updates for each entity & feature.
+///
+/// 5. A lists of non-position "events" for entities (switch & DOF changes),
+///    similarly chained in doubly-linked lists
+///
+/// 6. A list of "general" events, split into two parts:
+///    - Event "Headers" with most of the data (position, orientation, velocity,
+///      scale, flags...)
+///    - Event "trailers" sorted chronologically by timestamp with the index
+///      of their corresponding header
+///
+/// 7. Feature events containing a feature index, a timestamp, and a state change
+///
+/// 8. A set of calligns and team colors.
+pub fn read_vhs<R: Read, W: Write>(r: R, mut w: W) -> Result<()> {
+    let mut counted = CountedRead::new(r);
+    let counted = &mut counted;
+
+    writeln!(w, "{{")?;
+
+    let header = read_header(counted, &mut w)?;
+    read_entities(&header, counted, &mut w)?;
+    read_features(&header, counted, &mut w)?;
+    read_position_updates(&header, counted, &mut w)?;
+    read_entity_events(&header, counted, &mut w)?;
+    read_general_events(&header, counted, &mut w)?;
+    read_feature_events(&header, counted, &mut w)?;
+    read_callsigns(&header, counted, &mut w)?;
+
+    writeln!(w, "\n}}")?;
+    w.flush()?;
+    Ok(())
+}
+
+fn read_header<R: Read, W: Write>(r: &mut CountedRead<R>, w: &mut W) -> Result<TapeHeader> {
+    let header = TapeHeader::read(r)?;
+    if &header.file_id != b"EPAT" {
+        warn!(
+            "Expected magic bytes 'EPAT', got {:?} ({})",
+            &header.file_id,
+            String::from_utf8_lossy(&header.file_id)
+        );
+    }
+
+    write!(w, "\"header\": ")?;
+    serde_json::to_writer(&mut *w, &header)?;
+    writeln!(w, ",")?;
+    Ok(header)
+}
+
+fn read_entities<R: Read, W: Write>(
+    header: &TapeHeader,
+    r: &mut CountedRead<R>,
+    w: &mut W,
+) -> Result<()> {
+    let posit = r.get_posit();
+    ensure!(
+        header.entity_offset == posit,
+        "Expected entities to start at {}, currently at {}",
+        header.entity_offset,
+        posit
+    );
+    ensure!(
+        header.entity_count >= 0,
+        "Negative ({}) entity count",
+        header.entity_count
+    );
+    writeln!(w, "\"entities\": [")?;
+    for i in 0..header.entity_count {
+        let entity = Entity::read(r)?;
+        serde_json::to_writer(&mut *w, &entity)?;
+        writeln!(w, "{}", if i < header.entity_count - 1 { "," } else { "" })?;
+    }
+    writeln!(w, "],")?;
+    Ok(())
+}
+
+fn read_features<R: Read, W: Write>(
+    header: &TapeHeader,
+    r: &mut CountedRead<R>,
+    w: &mut W,
+) -> Result<()> {
+    let posit = r.get_posit();
+    ensure!(
+        header.feature_offset == posit,
+        "Expected features to start at {}, currently at {}",
+        header.feature_offset,
+        posit
+    );
+    ensure!(
+        header.feature_count >= 0,
+        "Negative ({}) feature count",
+        header.entity_count
+    );
+    writeln!(w, "\"features\": [")?;
+    for i in 0..header.feature_count {
+        let feature = Entity::read(r)?;
+        serde_json::to_writer(&mut *w, &feature)?;
+        writeln!(
+            w,
+            "{}",
+            if i < header.feature_count - 1 {
+                ","
+            } else {
+                ""
+            }
+        )?;
+    }
+    writeln!(w, "],")?;
+    Ok(())
+}
+
+fn read_position_updates<R: Read, W: Write>(
+    header: &TapeHeader,
+    r: &mut CountedRead<R>,
+    w: &mut W,
+) -> Result<()> {
+    let posit = r.get_posit();
+    ensure!(
+        header.position_offset == posit,
+        "Expected position updates to start at {}, currently at {}",
+        header.position_offset,
+        posit
+    );
+    ensure!(
+        header.position_count >= 0,
+        "Negative ({}) position update entry count",
+        header.entity_count
+    );
+    writeln!(w, "\"position updates\": [")?;
+    for i in 0..header.position_count {
+        let entry = TimelineEntry::read(r)?;
+        serde_json::to_writer(&mut *w, &entry)?;
+        writeln!(
+            w,
+            "{}",
+            if i < header.position_count - 1 {
+                ","
+            } else {
+                ""
+            }
+        )?;
+    }
+    writeln!(w, "],")?;
+    Ok(())
+}
+
+fn read_entity_events<R: Read, W: Write>(
+    header: &TapeHeader,
+    r: &mut CountedRead<R>,
+    w: &mut W,
+) -> Result<()> {
+    let posit = r.get_posit();
+    ensure!(
+        header.entity_event_offset == posit,
+        "Expected entity events to start at {}, currently at {}",
+        header.entity_event_offset,
+        posit
+    );
+    ensure!(
+        header.entity_event_count >= 0,
+        "Negative ({}) timeline entry count",
+        header.entity_event_count
+    );
+    writeln!(w, "\"entity events\": [")?;
+    for i in 0..header.entity_event_count {
+        let entry = TimelineEntry::read(r)?;
+
+        serde_json::to_writer(&mut *w, &entry)?;
+        writeln!(
+            w,
+            "{}",
+            if i < header.entity_event_count - 1 {
+                ","
+            } else {
+                ""
+            }
+        )?;
+    }
+    writeln!(w, "],")?;
+    Ok(())
+}
+
+fn read_general_events<R: Read, W: Write>(
+    header: &TapeHeader,
+    r: &mut CountedRead<R>,
+    w: &mut W,
+) -> Result<()> {
+    let mut posit = r.get_posit();
+    ensure!(
+        header.general_event_offset == posit,
+        "Expected general event headers to start at {}, currently at {}",
+        header.general_event_offset,
+        posit
+    );
+    ensure!(
+        header.general_event_count >= 0,
+        "Negative ({}) timeline entry count",
+        header.general_event_count
+    );
+    writeln!(w, "\"general event headers\": [")?;
+    for i in 0..header.general_event_count {
+        let entry = GeneralEventHeader::read(r)?;
+
+        serde_json::to_writer(&mut *w, &entry)?;
+        writeln!(
+            w,
+            "{}",
+            if i < header.general_event_count - 1 {
+                ","
+            } else {
+                ""
+            }
+        )?;
+    }
+    writeln!(w, "],")?;
+
+    posit = r.get_posit();
+    ensure!(
+        header.general_event_trailer_offset == posit,
+        "Expected general event trailers to start at {}, currently at {}",
+        header.general_event_trailer_offset,
+        posit
+    );
+    writeln!(w, "\"general event trailers\": [")?;
+    for i in 0..header.general_event_count {
+        let entry = GeneralEventTrailer::read(r)?;
+
+        serde_json::to_writer(&mut *w, &entry)?;
+        writeln!(
+            w,
+            "{}",
+            if i < header.general_event_count - 1 {
+                ","
+            } else {
+                ""
+            }
+        )?;
+    }
+    writeln!(w, "],")?;
+    Ok(())
+}
+
+fn read_feature_events<R: Read, W: Write>(
+    header: &TapeHeader,
+    r: &mut CountedRead<R>,
+    w: &mut W,
+) -> Result<()> {
+    let posit = r.get_posit();
+    ensure!(
+        header.feature_event_offset == posit,
+        "Expected feature events to start at {}, currently at {}",
+        header.feature_event_offset,
+        posit
+    );
+    ensure!(
+        header.feature_event_count >= 0,
+        "Negative ({}) timeline entry count",
+        header.feature_event_count
+    );
+    writeln!(w, "\"feature events\": [")?;
+    for i in 0..header.feature_event_count {
+        let entry = FeatureEvent::read(r)?;
+
+        serde_json::to_writer(&mut *w, &entry)?;
+        writeln!(
+            w,
+            "{}",
+            if i < header.feature_event_count - 1 {
+                ","
+            } else {
+                ""
+            }
+        )?;
+    }
+    writeln!(w, "],")?;
+    Ok(())
+}
+
+fn read_callsigns<R: Read, W: Write>(
+    header: &TapeHeader,
+    r: &mut CountedRead<R>,
+    w: &mut W,
+) -> Result<()> {
+    let posit = r.get_posit();
+    ensure!(
+        header.text_event_offset == posit,
+        "Expected text events to start at {}, currently at {}",
+        header.text_event_offset,
+        posit
+    );
+
+    // For reasons I don't understand, the callsign count is saved
+    // in four bytes preceding the block instead of as `text_event_count`
+    // in the file header.
+    let callsign_count = read_i32(r)?;
+    ensure!(
+        callsign_count >= 0,
+        "Negative ({}) timeline entry count",
+        callsign_count
+    );
+    writeln!(w, "\"callsigns\": [")?;
+    for i in 0..callsign_count {
+        let callsign = CallsignRecord::read(r)?;
+
+        serde_json::to_writer(&mut *w, &callsign)?;
+        writeln!(w, "{}", if i < callsign_count - 1 { "," } else { "" })?;
+    }
+    writeln!(w, "]")?;
+    Ok(())
+}